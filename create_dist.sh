@@ -9,7 +9,7 @@ OS="linux"
 MACHINE=`uname -m`
 
 mkdir $DIR 
-cp rsmodules setup_rsmodules.sh setup_rsmodules.csh rsmodules.py rsmodules.pm $DIR
+cp rsmodules setup_rsmodules.sh setup_rsmodules.csh setup_rsmodules.fish rsmodules.py rsmodules.pm $DIR
 cp -R examples/ $DIR
 cp -R tools/ $DIR
 cp README.md $DIR
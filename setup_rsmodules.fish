@@ -0,0 +1,48 @@
+#!/usr/bin/env fish
+
+function module
+	set -gx TERMWIDTH (/bin/stty size 2>&1 | cut -d" " -f2)
+	$RSMODULES_INSTALL_DIR/rsmodules fish,$TERMWIDTH $argv | source
+end
+
+function update_modules_cache
+	set -gx TERMWIDTH (/bin/stty size 2>&1 | cut -d" " -f2)
+	$RSMODULES_INSTALL_DIR/rsmodules progressbar,$TERMWIDTH cache make
+end
+
+set -gx MODULEPATH ""
+set -gx RSMODULES_INSTALL_DIR ""
+# loading a second module of a family (compiler, mpi, ...) replaces the loaded one, set it to no to refuse it
+set -gx RSMODULES_SWAP_FAMILY "yes"
+#set -gx LOADEDMODULES ""
+if not set -q PYTHONPATH
+	set -gx PYTHONPATH "$RSMODULES_INSTALL_DIR"
+else
+	set -gx PYTHONPATH "$RSMODULES_INSTALL_DIR:$PYTHONPATH"
+end
+if not set -q PERL5LIB
+	set -gx PERL5LIB "$RSMODULES_INSTALL_DIR"
+else
+	set -gx PERL5LIB "$RSMODULES_INSTALL_DIR:$PERL5LIB"
+end
+
+if test -f ~/.rsmodules_autoload
+	source ~/.rsmodules_autoload
+end
+
+# cleanup old tmp files from crashed rsmodules sessions, when $RSMODULES_TMPFILE is set
+find ~/.rsmodulestmp* -mtime +1 -delete > /dev/null 2>&1
+
+function __module_avail
+	$RSMODULES_INSTALL_DIR/rsmodules noshell avail
+end
+
+complete -c module -f
+complete -c module -n __fish_use_subcommand -a "available list readme load purge info unload cache add rm cd edit autoload refurbish undo redo history refresh delete create switch save restore savelist disable use unuse spider keyword which lint test preview"
+complete -c module -l dry-run
+complete -c module -n "__fish_seen_subcommand_from load add info readme delete cd edit switch lint test preview" -a "(__module_avail)"
+complete -c module -n "__fish_seen_subcommand_from unload rm" -a "(string split : -- \$LOADEDMODULES)"
+complete -c module -n "__fish_seen_subcommand_from cache" -a "make add edit delete verify"
+complete -c module -n "__fish_seen_subcommand_from autoload" -a "append prepend list purge remove"
+complete -c module -n "__fish_seen_subcommand_from use unuse" -a "(__fish_complete_directories)"
+complete -c module -n "__fish_seen_subcommand_from restore disable" -a "(\$RSMODULES_INSTALL_DIR/rsmodules noshell savelist)"
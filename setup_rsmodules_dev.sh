@@ -2,7 +2,7 @@
 
 module() {
 	export TERMWIDTH=`/bin/stty size 2>&1 | cut -d" " -f2`;
-	eval `$RSMODULES_INSTALL_DIR/rsmodules bash,$TERMWIDTH $*`;
+	eval "$($RSMODULES_INSTALL_DIR/rsmodules bash,$TERMWIDTH $*)";
 }
 
 export MODULEPATH=""
@@ -15,7 +15,7 @@ if [ "`hostname`" == "pad39a.psb.ugent.be" ]; then
 
 	module() {
 		export TERMWIDTH=`/bin/stty size | cut -d" " -f2`;
-		eval `$RSMODULES_INSTALL_DIR/target/debug/rsmodules bash,$TERMWIDTH $*`;
+		eval "$($RSMODULES_INSTALL_DIR/target/debug/rsmodules bash,$TERMWIDTH $*)";
 	}
 
 	export -f module
@@ -46,7 +46,7 @@ if [ -f ~/.rsmodules_autoload ]; then
 	source ~/.rsmodules_autoload
 fi
 
-# cleanup old tmp files from crashed rsmodules sessions
+# cleanup old tmp files from crashed rsmodules sessions, when $RSMODULES_TMPFILE is set
 find ~/.rsmodulestmp* -mtime +1 -delete > /dev/null 2>&1
 # also cleanup empty tmp files
 find ~/.rsmodulestmp* -empty -delete > /dev/null 2>&1
@@ -94,21 +94,26 @@ if [ ${BASH_VERSINFO:-0} -ge 3 ]; then
 		COMPREPLY=()
 
 		cmds="available \
-			list load purge info cd edit \
-			unload readme cache add rm \
-			autoload refurbish undo refresh delete create"
+			list readme load purge info \
+			unload cache add rm cd edit \
+			autoload refurbish undo redo history refresh delete create \
+			save restore savelist disable use unuse spider keyword which lint test preview"
 
-		opts="-h --help"
+		opts="-h --help --dry-run"
 
 		case "$prev" in
-		load)    COMPREPLY=( $(compgen -W "$(_module_not_yet_loaded)" -- "$cur") );;
+		load|preview)    COMPREPLY=( $(compgen -W "$(_module_not_yet_loaded)" -- "$cur") );;
 		unload)
 				COMPREPLY=( $(IFS=: compgen -W "${LOADEDMODULES}" -- "$cur") );;
-		delete|readme|info|cd|edit)
+		info|readme|delete|cd|edit|lint|test)
 				      COMPREPLY=( $(compgen -W "$(_module_avail)" -- "$cur") );;
-		cache)	COMPREPLY=( $(IFS=: compgen -W "make:add:edit:delete" -- "$cur") );;
+		cache)	COMPREPLY=( $(IFS=: compgen -W "make:add:edit:delete:verify" -- "$cur") );;
 		autoload)
 				COMPREPLY=( $(IFS=: compgen -W "append:prepend:list:purge:remove" -- "$cur") );;
+		use|unuse)
+				COMPREPLY=( $(compgen -d -- "$cur") );;
+		restore|disable)
+				COMPREPLY=( $(compgen -W "$($RSMODULES_INSTALL_DIR/rsmodules noshell savelist)" -- "$cur") );;
 		*)  if test $COMP_CWORD -gt 2
 			then
 				_module_long_arg_list "$cur"
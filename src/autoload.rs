@@ -33,6 +33,7 @@ extern crate regex;
 extern crate shellexpand;
 
 use regex::Regex;
+use rustc_serialize::json;
 
 #[derive(Clone, Eq, Debug)]
 struct Module {
@@ -67,6 +68,14 @@ impl PartialEq for Module {
     }
 }
 
+// what module autoload list prints when the json shell is used
+#[derive(RustcEncodable)]
+struct JsonModule {
+    name: String,
+    path: String,
+    managed: bool,
+}
+
 static AUTOLOAD_FILE: &str = "~/.rsmodules_autoload";
 lazy_static! {
     // Avoid compiling the same regex in a loop
@@ -159,6 +168,7 @@ pub fn run(subcommand: &str, args: &mut Vec<&str>, shell: &str) {
         "bash" => "~/.bashrc",
         "csh" => "~/.cshrc",
         "zsh" => "~/.zshrc",
+        "fish" => "~/.config/fish/config.fish",
         "tcsh" => "~/.tcshrc",
         _ => "~/.login", // meh ?
     };
@@ -190,6 +200,17 @@ pub fn run(subcommand: &str, args: &mut Vec<&str>, shell: &str) {
         for al_module in &al_modules {
             output(format!("module load {}\n", al_module.name));
         }
+    } else if subcommand == "list" && shell == "json" {
+        al_modules.sort();
+        let result: Vec<JsonModule> = al_modules
+            .iter()
+            .map(|al_module| JsonModule {
+                name: al_module.name.clone(),
+                path: al_module.path.clone(),
+                managed: al_module.path == shellexpand::tilde(AUTOLOAD_FILE),
+            })
+            .collect();
+        println!("{}", json::encode(&result).unwrap());
     } else if subcommand == "list" {
         al_modules.sort();
         let mut old_path: String = String::new();
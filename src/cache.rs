@@ -23,21 +23,39 @@ SOFTWARE.
 */
 
 use super::super::bold;
+use super::super::wizard::{is_yes, read_input_shell};
 use chrono::{DateTime, NaiveDate, Utc};
 use if_let_return::if_let_some;
 use regex::Regex;
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::env::args;
+use std::ffi::CString;
+use std::fs;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Stdout};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Stdout, Write};
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
-
+use std::process;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+use glob::Pattern;
 use walkdir::WalkDir;
 extern crate bincode;
-use super::{crash, echo, get_module_description, get_module_paths, is_module_loaded, AvailableOptions, Rsmodule};
+use super::{
+    crash, echo, get_module_description, get_module_paths, get_modulefile, is_module_loaded, AvailableOptions, Rsmodule,
+    SpiderOptions,
+};
 use bincode::rustc_serialize::{decode_from, encode_into};
+use rustc_serialize::json;
 
 use super::script;
+use super::tcl;
 
 use pbr::ProgressBar;
 
@@ -45,6 +63,25 @@ use gumdrop::Options;
 
 pub static MODULECACHE: &str = ".modulecache";
 
+// every cache file starts with these bytes, followed by the version of the
+// format, increase CACHE_VERSION when Module or ModuleMetadata changes
+static CACHE_MAGIC: &[u8] = b"RSMODCACHE";
+static CACHE_VERSION: u32 = 1;
+
+lazy_static! {
+    // the cache file of every modulepath we used, it only has to be looked up once
+    static ref CACHE_FILES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+// why a cache file cannot be used
+#[derive(Debug, PartialEq)]
+enum CacheError {
+    Missing,
+    // made by another version of rsmodules, contains the version of its format
+    Stale(u32),
+    Corrupt,
+}
+
 #[derive(RustcEncodable, RustcDecodable, Clone, Eq, Debug)]
 struct Module {
     name: String,
@@ -73,6 +110,65 @@ impl Module {
     }
 }
 
+// everything module spider and module which search in, this is
+// stored after the list of modules in the cache file
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+struct ModuleMetadata {
+    name: String,
+    description: Vec<String>,
+    variables: Vec<String>,
+    binaries: Vec<String>,
+    executables: Vec<String>,
+    // to find out if the modulefile or the .version file changed since the last cache make
+    mtime: i64,
+    size: u64,
+    version_mtime: i64,
+}
+
+// what module spider prints when the json shell is used
+#[derive(RustcEncodable)]
+struct JsonSpiderModule {
+    name: String,
+    description: Vec<String>,
+    default: bool,
+    loaded: bool,
+    variables: Vec<String>,
+    binaries: Vec<String>,
+}
+
+// what module which prints when the json shell is used
+#[derive(RustcEncodable)]
+struct JsonWhichModule {
+    name: String,
+    executables: Vec<String>,
+    default: bool,
+    deprecated: String,
+    deprecated_state: String,
+    loaded: bool,
+}
+
+// what module av prints when the json shell is used
+#[derive(RustcEncodable)]
+struct JsonModule {
+    name: String,
+    description: String,
+    default: bool,
+    deprecated: String,
+    deprecated_state: String,
+    loaded: bool,
+}
+
+// what module list prints when the json shell is used
+#[derive(RustcEncodable)]
+struct JsonLoadedModule {
+    name: String,
+    description: String,
+    default: bool,
+    deprecated: String,
+    deprecated_state: String,
+    dependency: bool,
+}
+
 impl Ord for Module {
     fn cmp(&self, other: &Module) -> Ordering {
         self.name.to_lowercase().cmp(&other.name.to_lowercase())
@@ -100,18 +196,17 @@ pub fn release_debug() -> String {
     String::from("")
 }
 
-fn add_module(name: String, description: String, default: bool, deprecated: String, modules: &mut Vec<Module>) {
-    let module: Module = Module::from(name, description, default, deprecated);
-
-    modules.push(module);
-}
-
 fn get_default_version(modulepath: &str, modulename: &str) -> String {
     let parts: Vec<&str> = modulename.split('/').collect();
     let groupname = if !parts.is_empty() { parts[0] } else { "" };
 
-    let tmp = format!("{}/{}/.version", modulepath, groupname);
-    let module_path = Path::new(&tmp);
+    let version_file = get_version_file(modulepath, modulename);
+    let module_path = Path::new(&version_file);
+
+    // .modulerc files and .version files of Environment Modules are tcl
+    if tcl::is_modulefile(module_path) {
+        return tcl::get_default_version(module_path, groupname).unwrap_or_default();
+    }
 
     // read filename line by line, and push it to modules
     let mut buffer = String::new();
@@ -135,35 +230,7 @@ fn get_default_version(modulepath: &str, modulename: &str) -> String {
 }
 
 fn is_default_version(modulepath: &str, modulename: &str) -> bool {
-    let parts: Vec<&str> = modulename.split('/').collect();
-    let groupname = if !parts.is_empty() { parts[0] } else { "" };
-
-    let tmp = format!("{}/{}/.version", modulepath, groupname);
-    let module_path = Path::new(&tmp);
-
-    // read filename line by line, and push it to modules
-    let mut buffer = String::new();
-
-    if Path::new(&module_path).is_file() {
-        let file: File = match File::open(module_path) {
-            Ok(file) => file,
-            Err(_) => {
-                return false;
-            }
-        };
-
-        let file = BufReader::new(file);
-        // if there are multiple entries in .version, the last one counts
-        for (_, line) in file.lines().enumerate() {
-            buffer = line.unwrap();
-        }
-    }
-
-    if buffer == modulename {
-        return true;
-    }
-
-    false
+    get_default_version(modulepath, modulename) == modulename
 }
 
 fn progressbar(num: u64, msg: &str) -> ProgressBar<Stdout> {
@@ -181,12 +248,25 @@ pub fn manipulate_cache(action: &str, modopts: &ModifyOpts, shell: &str) -> bool
     if modopts.modulepath.is_empty() {
         return false;
     }
-    // TODO: check if the modulefile exists, if not don't add it
 
-    let file_str = format!("{}/{}{}", modopts.modulepath, MODULECACHE, release_debug());
-    let file: File = match File::open(&file_str) {
-        Ok(file) => file,
-        Err(_) => {
+    // only modules that have a modulefile can be added
+    if action == "add" && !get_modulefile(&modopts.modulepath, &modopts.name).is_file() {
+        eprintln!(
+            "{}/{} doesn't exist, the module was not added to the cache.",
+            modopts.modulepath, modopts.name
+        );
+        return false;
+    }
+
+    // makes an outdated personal cache again, this takes the lock itself
+    if read_cache(&modopts.modulepath, shell, false).is_none() {
+        return false;
+    }
+
+    // nobody else can change the cache between reading and writing it
+    let _lock = match lock_cache(&modopts.modulepath, shell) {
+        Some(lock) => lock,
+        None => {
             if shell != "noshell" {
                 echo("", shell);
                 let msg: String = format!(
@@ -203,6 +283,12 @@ pub fn manipulate_cache(action: &str, modopts: &ModifyOpts, shell: &str) -> bool
         }
     };
 
+    // read it again, we hold the lock now
+    let (mut modules, mut metadata) = match read_cache_file(&PathBuf::from(cache_file(&modopts.modulepath))) {
+        Ok(cache) => cache,
+        Err(_) => return false,
+    };
+
     let default = if modopts.default > 0 { true } else { false };
 
     let deprecated = if modopts.deprecated.is_empty() {
@@ -218,29 +304,6 @@ pub fn manipulate_cache(action: &str, modopts: &ModifyOpts, shell: &str) -> bool
         }
     };
 
-    let mut reader = BufReader::new(&file);
-    let mut modules: Vec<Module> = decode_from(&mut reader, bincode::SizeLimit::Infinite).unwrap();
-
-    // open the file again
-    let file: File = match File::options().write(true).open(&file_str) {
-        Ok(file) => file,
-        Err(_) => {
-            if shell != "noshell" {
-                echo("", shell);
-                let msg: String = format!(
-                    "  {}: {} could NOT be opened.",
-                    bold(shell, "WARNING"),
-                    bold(shell, &modopts.modulepath)
-                );
-                echo(&msg, shell);
-            } else {
-                let msg: String = format!("{} failed", &modopts.modulepath);
-                echo(&msg, shell);
-            }
-            return false;
-        }
-    };
-
     let module: Module = Module::from(
         modopts.name.to_string(),
         modopts.description.to_string(),
@@ -260,9 +323,21 @@ pub fn manipulate_cache(action: &str, modopts: &ModifyOpts, shell: &str) -> bool
     if action == "add" {
         // this only checks the modulename, so you cannot overwrite
         if !modules.contains(&module) {
-            let mut writer = BufWriter::new(&file);
+            metadata.push(ModuleMetadata {
+                name: module.name.clone(),
+                description: vec![module.description.clone()],
+                variables: Vec::new(),
+                binaries: Vec::new(),
+                executables: Vec::new(),
+                mtime: 0,
+                size: 0,
+                version_mtime: 0,
+            });
             modules.push(module);
-            encode_into(&modules, &mut writer, bincode::SizeLimit::Infinite).unwrap();
+            if let Err(e) = write_cache(&modopts.modulepath, &modules, &metadata) {
+                eprintln!("Failed to write the cache: {}", e);
+                return false;
+            }
             eprintln!("Successfully modified the cache.");
         }
     } else if action == "edit" {
@@ -272,6 +347,11 @@ pub fn manipulate_cache(action: &str, modopts: &ModifyOpts, shell: &str) -> bool
             for tmp_module in modules.iter_mut() {
                 if tmp_module.name == module.name {
                     modified = true;
+                    for tmp_metadata in metadata.iter_mut() {
+                        if tmp_metadata.name == tmp_module.name {
+                            tmp_metadata.name = modopts.new_name.to_string();
+                        }
+                    }
                     tmp_module.name = modopts.new_name.to_string();
                     if tmp_module.deprecated != deprecated {
                         tmp_module.deprecated = deprecated.to_string();
@@ -285,8 +365,10 @@ pub fn manipulate_cache(action: &str, modopts: &ModifyOpts, shell: &str) -> bool
                 }
             }
 
-            let mut writer = BufWriter::new(&file);
-            encode_into(&modules, &mut writer, bincode::SizeLimit::Infinite).unwrap();
+            if let Err(e) = write_cache(&modopts.modulepath, &modules, &metadata) {
+                eprintln!("Failed to write the cache: {}", e);
+                return false;
+            }
         }
 
         if modified {
@@ -294,36 +376,174 @@ pub fn manipulate_cache(action: &str, modopts: &ModifyOpts, shell: &str) -> bool
         } else {
             eprintln!("Cache was not modified.");
         }
+    } else if action == "delete" {
+        let count = modules.len();
+        modules.retain(|tmp_module| tmp_module.name != modopts.name);
+        metadata.retain(|tmp_metadata| tmp_metadata.name != modopts.name);
+
+        if modules.len() == count {
+            eprintln!("Cache was not modified.");
+        } else {
+            if let Err(e) = write_cache(&modopts.modulepath, &modules, &metadata) {
+                eprintln!("Failed to write the cache: {}", e);
+                return false;
+            }
+            eprintln!("Successfully modified the cache.");
+        }
     }
 
     return true;
 }
 
-pub fn update(modulepath: &str, shell: &str) -> bool {
-    // list is: path to file, module name, default
-    let mut list: Vec<(String, String, bool, script::Deprecated)> = Vec::new();
-    let module_path = Path::new(&modulepath);
-    let mut index_succes: i32 = 0;
-    let mut index_default: i32 = 0;
+// returns the modification time in nanoseconds and the size of a file
+fn get_file_stamp(path: &str) -> (i64, u64) {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let mtime = match metadata.modified() {
+                Ok(time) => match time.duration_since(UNIX_EPOCH) {
+                    Ok(duration) => duration.as_nanos() as i64,
+                    Err(_) => 0,
+                },
+                Err(_) => 0,
+            };
+            (mtime, metadata.len())
+        }
+        Err(_) => (0, 0),
+    }
+}
 
-    let file_str = format!("{}/{}{}", modulepath, MODULECACHE, release_debug());
-    let num_modules = if Path::new(&file_str).exists() {
-        count_modules_in_cache(&PathBuf::from(&file_str))
-    } else {
-        0
+fn get_version_file(modulepath: &str, modulename: &str) -> String {
+    let parts: Vec<&str> = modulename.split('/').collect();
+    let groupname = if !parts.is_empty() { parts[0] } else { "" };
+
+    // a .version file wins from a .modulerc file
+    let version_file = format!("{}/{}/.version", modulepath, groupname);
+    let modulerc = format!("{}/{}/.modulerc", modulepath, groupname);
+    if !Path::new(&version_file).exists() && Path::new(&modulerc).exists() {
+        return modulerc;
+    }
+
+    version_file
+}
+
+// a module and what we know about it for the cache file
+type ParsedModule = (Module, ModuleMetadata);
+
+// a modulefile that has to be parsed again: index in the cache file,
+// path to the file, module name and mtime, size and .version mtime
+type ChangedModule = (usize, String, String, (i64, u64, i64));
+
+// evaluates the modulefile in deprecated, description and info mode
+fn parse_module(modulepath: &str, filename: &str, modulename: &str, stamp: (i64, u64, i64)) -> ParsedModule {
+    let path: PathBuf = PathBuf::from(filename);
+    let (mtime, size, version_mtime) = stamp;
+
+    // check if this module is deprecated or not
+    let context = script::run(&path, "deprecated");
+    let deprecated = script::get_deprecated(&context);
+
+    let default = is_default_version(modulepath, modulename);
+
+    let description: Vec<String> = get_module_description(&path, "description");
+    let description = description.join(" ");
+
+    // the full description, variables and binaries for module spider
+    // and the executables for module which
+    let context = script::run(&path, "info");
+    let (full_description, variables, binaries, executables) = script::get_metadata(&context);
+    let metadata = ModuleMetadata {
+        name: modulename.to_string(),
+        description: full_description,
+        variables,
+        binaries,
+        executables,
+        mtime,
+        size,
+        version_mtime,
     };
 
-    if shell == "progressbar" {
-        echo("", shell);
-        echo(&format!("  Indexing {}", modulepath), shell);
+    let deprecated = match deprecated.state {
+        script::DeprecatedState::Not => "0".to_string(),
+        script::DeprecatedState::Before => deprecated.time,
+        script::DeprecatedState::After => deprecated.time,
+    };
+
+    let module = Module::from(modulename.to_string(), description, default, deprecated);
+
+    (module, metadata)
+}
+
+// parses the modulefiles on a pool of worker threads, every evaluation
+// has its own script::Context so they don't share any state
+// returns the parsed modules together with their index
+fn parse_modules(
+    modulepath: &str,
+    changed: &[ChangedModule],
+    shell: &str,
+    pb: &mut ProgressBar<Stdout>,
+) -> Vec<(usize, ParsedModule)> {
+    let workers = match thread::available_parallelism() {
+        Ok(workers) => workers.get().min(changed.len()),
+        Err(_) => 1,
+    };
+    let next = AtomicUsize::new(0);
+    let mut parsed: Vec<(usize, ParsedModule)> = Vec::new();
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let next = &next;
+            scope.spawn(move || {
+                while let Some(job) = changed.get(next.fetch_add(1, atomic::Ordering::Relaxed)) {
+                    let (index, filename, modulename, stamp) = job;
+                    let module = parse_module(modulepath, filename, modulename, *stamp);
+                    if sender.send((*index, module)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // the progressbar is only updated from this thread
+        for module in receiver {
+            if shell == "progressbar" {
+                pb.inc();
+                pb.message("  Parsing files    ");
+            }
+            parsed.push(module);
+        }
+    });
+
+    parsed
+}
+
+// only the modulefiles that changed since the last time are parsed again
+pub fn update(modulepath: &str, shell: &str) -> bool {
+    make(modulepath, shell, false)
+}
+
+fn print_index_failed(modulepath: &str, shell: &str) {
+    if shell != "noshell" {
         echo("", shell);
+        let msg: String = format!(
+            "  {}: {} could NOT be indexed.",
+            bold(shell, "WARNING"),
+            bold(shell, modulepath)
+        );
+        echo(&msg, shell);
+    } else {
+        let msg: String = format!("{} failed", modulepath);
+        echo(&msg, shell);
     }
+}
 
-    let mut pb = if num_modules != 0 && shell == "progressbar" {
-        progressbar(num_modules, "  Scanning folders ")
-    } else {
-        ProgressBar::new(0)
-    };
+// returns the path to the file and the name of all the modulefiles in modulepath
+fn find_modulefiles(modulepath: &str, shell: &str, pb: &mut ProgressBar<Stdout>) -> Vec<(String, String)> {
+    let mut list: Vec<(String, String)> = Vec::new();
+    let module_path = Path::new(&modulepath);
 
     #[allow(clippy::redundant_closure)]
     for entry in WalkDir::new(module_path).into_iter().filter_map(|e| e.ok()) {
@@ -356,42 +576,272 @@ pub fn update(modulepath: &str, shell: &str) -> bool {
                     let modulename_part: Vec<&str> = modulename.split('/').collect();
                     let mut is_version_file = false;
 
-                    // skip the .version files, these are not modulefiles
+                    // skip the .version and .modulerc files, these are not modulefiles
                     for mp in modulename_part {
-                        if mp == ".version" {
+                        if mp == ".version" || mp == ".modulerc" {
                             is_version_file = true;
                         }
                     }
 
+                    // Lmod modulefiles: the name of blast/2.5.0.lua is blast/2.5.0
+                    let modulename = modulename.strip_suffix(".lua").unwrap_or(modulename);
+
                     if second != "." && !is_version_file {
-                        // check if this module is deprecated or not
-                        let path = format!("{}/{}", modulepath, modulename);
-                        let path = PathBuf::from(&path);
+                        list.push((str_path.to_string(), modulename.to_string()));
+                    }
+                }
+            }
+        }
+    }
 
-                        script::run(&path, "deprecated");
-                        let deprecated;
+    list
+}
 
-                        {
-                            // we ne need a different scope, or DEPRECATED is locked
-                            let tmp_deprecated = lu!(script::DEPRECATED);
-                            deprecated = tmp_deprecated.clone();
-                        }
+// the modules of modulepath like they would be in a cache file that is made now
+fn parse_all_modules(modulepath: &str, shell: &str) -> Vec<ParsedModule> {
+    let mut pb = ProgressBar::new(0);
+    let changed: Vec<ChangedModule> = find_modulefiles(modulepath, shell, &mut pb)
+        .into_iter()
+        .enumerate()
+        .map(|(index, (filename, modulename))| {
+            let (mtime, size) = get_file_stamp(&filename);
+            let (version_mtime, _) = get_file_stamp(&get_version_file(modulepath, &modulename));
+            (index, filename, modulename, (mtime, size, version_mtime))
+        })
+        .collect();
+
+    let mut parsed = parse_modules(modulepath, &changed, shell, &mut pb);
+    parsed.sort_by_key(|(index, _)| *index);
+
+    parsed.into_iter().map(|(_, module)| module).collect()
+}
 
-                        //
-                        let default = is_default_version(modulepath, modulename);
-                        list.push((str_path.to_string(), modulename.to_string(), default, deprecated.clone()));
-                        index_succes += 1;
-                    }
+// the differences between the cache and the modulefiles
+fn find_problems(cached: &[Module], current: &[ParsedModule]) -> Vec<String> {
+    let mut problems: Vec<String> = Vec::new();
+
+    for module in cached {
+        if !current.iter().any(|(current_module, _)| current_module.name == module.name) {
+            problems.push(format!("{}: there is no modulefile for this module", module.name));
+        }
+    }
+
+    for (module, _) in current {
+        let cached_module = match cached.iter().find(|cached_module| cached_module.name == module.name) {
+            Some(cached_module) => cached_module,
+            None => {
+                problems.push(format!("{}: is missing from the cache", module.name));
+                continue;
+            }
+        };
+
+        if cached_module.default && !module.default {
+            problems.push(format!("{}: is the default in the cache, but not in .version", module.name));
+        } else if !cached_module.default && module.default {
+            problems.push(format!("{}: is the default in .version, but not in the cache", module.name));
+        }
+
+        if cached_module.description != module.description {
+            problems.push(format!(
+                "{}: the description in the cache differs from the modulefile",
+                module.name
+            ));
+        }
+    }
+
+    problems
+}
+
+// makes the cache agree with the modulefiles again, the entries
+// without problems are left alone
+fn fix_cache(modulepath: &str, shell: &str, current: Vec<ParsedModule>) -> bool {
+    let _lock = match lock_cache(modulepath, shell) {
+        Some(lock) => lock,
+        None => {
+            print_index_failed(modulepath, shell);
+            return false;
+        }
+    };
+
+    // read it again, it can have changed while the user was thinking
+    // read_cache would take the lock we hold to make a personal cache again
+    let (mut modules, mut metadata) = match read_cache_file(&PathBuf::from(cache_file(modulepath))) {
+        Ok(cache) => cache,
+        Err(_) => {
+            print_index_failed(modulepath, shell);
+            return false;
+        }
+    };
+
+    modules.retain(|module| current.iter().any(|(current_module, _)| current_module.name == module.name));
+    metadata.retain(|data| current.iter().any(|(current_module, _)| current_module.name == data.name));
+
+    for (module, data) in current {
+        match modules.iter_mut().find(|cached_module| cached_module.name == module.name) {
+            Some(cached_module) => {
+                if cached_module.default == module.default && cached_module.description == module.description {
+                    continue;
                 }
+                *cached_module = module;
             }
+            None => modules.push(module),
+        }
+        metadata.retain(|cached_data| cached_data.name != data.name);
+        metadata.push(data);
+    }
+
+    if write_cache(modulepath, &modules, &metadata).is_err() {
+        print_index_failed(modulepath, shell);
+        return false;
+    }
+
+    true
+}
+
+// reports the problems in the cache of modulepath and offers to fix them
+// this is printed to stderr, so it is shown before we ask something
+fn verify(modulepath: &str, shell: &str, fix: bool) {
+    let (cached, _) = match read_cache(modulepath, shell, false) {
+        Some(cache) => cache,
+        None => return,
+    };
+
+    let current = parse_all_modules(modulepath, shell);
+    let problems = find_problems(&cached, &current);
+
+    if problems.is_empty() {
+        eprintln!("  The index of {} is up to date.", bold(shell, modulepath));
+        return;
+    }
+
+    eprintln!("  The index of {} has the following problems:", bold(shell, modulepath));
+    eprintln!();
+    for problem in &problems {
+        eprintln!("    * {}", problem);
+    }
+    eprintln!();
+
+    let interactive = shell != "noshell" && shell != "json" && shell != "python" && shell != "perl" && shell != "r";
+    if !fix && !interactive {
+        eprintln!("  Run {} to fix them.", bold(shell, "module cache verify --fix"));
+        return;
+    }
+
+    let fix = fix || is_yes(&read_input_shell("  Do you want to fix these problems ? [Y/n]: ", shell));
+    if fix && fix_cache(modulepath, shell, current) {
+        eprintln!("  The index of {} was fixed.", bold(shell, modulepath));
+    }
+}
+
+fn make(modulepath: &str, shell: &str, full: bool) -> bool {
+    let (index_succes, index_default, index_parsed) = match build(modulepath, shell, full, true) {
+        Some(counts) => counts,
+        None => {
+            print_index_failed(modulepath, shell);
+            return false;
         }
+    };
+
+    if shell != "noshell" {
+        echo("", shell);
+        let msg: String = format!("  {} was succesfully indexed.", bold(shell, modulepath));
+        echo(&msg, shell);
+        echo("", shell);
+        let tmp = format!("{}", index_succes);
+        let msg: String = format!("  * Total number of modules: {}", bold(shell, &tmp));
+        echo(&msg, shell);
+        let tmp = format!("{}", index_default);
+        let msg: String = format!("  * Number of default (D) modules: {}", bold(shell, &tmp));
+        echo(&msg, shell);
+        let tmp = format!("{}", index_parsed);
+        let msg: String = format!("  * Number of new or changed modules: {}", bold(shell, &tmp));
+        echo(&msg, shell);
+        echo("", shell);
+    } else {
+        let msg: String = format!("{} success", modulepath);
+        echo(&msg, shell);
+        let tmp = format!("{}", index_succes);
+        let msg: String = format!("Total number of modules: {}", &tmp);
+        echo(&msg, shell);
+        let tmp = format!("{}", index_default);
+        let msg: String = format!("Number of default (D) modules: {}", &tmp);
+        echo(&msg, shell);
+        let tmp = format!("{}", index_parsed);
+        let msg: String = format!("Number of new or changed modules: {}", &tmp);
+        echo(&msg, shell);
+    }
+
+    true
+}
+
+// the modules of a cache file together with their metadata,
+// modules without metadata have to be parsed again
+fn with_metadata(modules: Vec<Module>, metadata: &[ModuleMetadata]) -> Vec<ParsedModule> {
+    modules
+        .into_iter()
+        .filter_map(|module| {
+            metadata
+                .iter()
+                .find(|data| data.name == module.name)
+                .map(|data| (module, data.clone()))
+        })
+        .collect()
+}
+
+// the module from the previous cache file when its modulefile and .version file didn't change
+fn find_unchanged<'a>(previous: &'a [ParsedModule], modulename: &str, stamp: (i64, u64, i64)) -> Option<&'a ParsedModule> {
+    let (mtime, size, version_mtime) = stamp;
+    previous.iter().find(|(module, data)| {
+        module.name == modulename && data.mtime == mtime && data.size == size && data.version_mtime == version_mtime
+    })
+}
+
+// writes the cache file of modulepath and returns the total number of modules,
+// the number of default modules and the number of modules that were parsed
+fn build(modulepath: &str, shell: &str, full: bool, verbose: bool) -> Option<(i32, i32, i32)> {
+    // only one cache make or cache add/edit at a time
+    let _lock = lock_cache(modulepath, shell)?;
+
+    let mut index_default: i32 = 0;
+
+    let file_str = cache_file(modulepath);
+    // taken before we look at the modulefiles, a change while we are
+    // busy makes a personal cache outdated
+    let stamp = folders_stamp(modulepath);
+    let num_modules = if Path::new(&file_str).exists() {
+        count_modules_in_cache(&PathBuf::from(&file_str))
+    } else {
+        0
+    };
+
+    if shell == "progressbar" {
+        echo("", shell);
+        echo(&format!("  Indexing {}", modulepath), shell);
+        echo("", shell);
     }
 
+    let mut pb = if num_modules != 0 && shell == "progressbar" {
+        progressbar(num_modules, "  Scanning folders ")
+    } else {
+        ProgressBar::new(0)
+    };
+
+    let list = find_modulefiles(modulepath, shell, &mut pb);
+    let index_succes = list.len() as i32;
+
     // now we have all the module files in the current folder
     // we need to parse them to get their description
     // our list of modules that we will save into the .modulecache
 
     let mut modules: Vec<Module> = vec![];
+    let mut metadata: Vec<ModuleMetadata> = vec![];
+    let mut index_parsed: i32 = 0;
+
+    // the modules from the previous cache file, stale or corrupt caches are rebuilt
+    let previous: Vec<ParsedModule> = match read_cache_file(&PathBuf::from(&file_str)) {
+        Ok((previous_modules, previous_metadata)) if !full => with_metadata(previous_modules, &previous_metadata),
+        _ => Vec::new(),
+    };
 
     let num_modules = list.len() as u64;
     let mut pb = if num_modules != 0 && shell == "progressbar" {
@@ -400,126 +850,455 @@ pub fn update(modulepath: &str, shell: &str) -> bool {
         ProgressBar::new(0)
     };
 
-    for (modulepath, modulename, default, deprecated) in list {
-        let path: PathBuf = PathBuf::from(&modulepath);
-        if shell == "progressbar" {
-            pb.inc();
-            pb.message("  Parsing files    ");
+    // the unchanged modules are taken from the previous cache file
+    // the others are parsed again
+    let mut parsed: Vec<Option<ParsedModule>> = Vec::new();
+    let mut changed: Vec<ChangedModule> = Vec::new();
+
+    for (filename, modulename) in list {
+        let (mtime, size) = get_file_stamp(&filename);
+        let (version_mtime, _) = get_file_stamp(&get_version_file(modulepath, &modulename));
+
+        if let Some(unchanged) = find_unchanged(&previous, &modulename, (mtime, size, version_mtime)) {
+            if shell == "progressbar" {
+                pb.inc();
+                pb.message("  Parsing files    ");
+            }
+            parsed.push(Some(unchanged.clone()));
+        } else {
+            changed.push((parsed.len(), filename, modulename, (mtime, size, version_mtime)));
+            parsed.push(None);
         }
+    }
 
-        let description: Vec<String> = get_module_description(&path, "description");
-        let description = description.join(" ");
+    index_parsed += changed.len() as i32;
 
-        // flags is supposed to be a bitfield
-        // currently it is only used for flagging a module as default
-        //        let mut _default: bool = false;
-        if default {
-            //           flags = flags | ModuleFlags::DEFAULT;
+    for (index, module) in parse_modules(modulepath, &changed, shell, &mut pb) {
+        parsed[index] = Some(module);
+    }
+
+    // the order of the modules is the order in which we found them
+    for (module, data) in parsed.into_iter().flatten() {
+        if module.default {
             index_default += 1;
         }
+        modules.push(module);
+        metadata.push(data);
+    }
+
+    if shell == "progressbar" {
+        echo("", shell);
+    }
+    if verbose {
+        echo("", shell);
+        echo(&"  Writing cache file.", shell);
+    }
+
+    write_cache(modulepath, &modules, &metadata).ok()?;
+
+    if is_personal_cache(modulepath, &file_str) {
+        fs::write(format!("{}.stamp", file_str), format!("{}", stamp)).ok()?;
+    }
+
+    Some((index_succes, index_default, index_parsed))
+}
+
+// FNV-1a, unlike the hasher of std this gives the same hash in every
+// version of rust, so the personal caches keep their names
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash
+}
+
+static FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+// adding, removing or renaming a modulefile changes the mtime of the
+// folder it is in, so this changes when the list of modulefiles does
+// only modulepath and the group folders in it are checked, walking the
+// whole tree on every module command is too slow on network shares
+fn folders_stamp(modulepath: &str) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for entry in WalkDir::new(modulepath)
+        .max_depth(1)
+        .sort_by(|a, b| a.cmp(b))
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if entry.file_type().is_dir() {
+            let path = entry.path().to_string_lossy();
+            let (mtime, _) = get_file_stamp(&path);
+            hash = fnv1a(hash, path.as_bytes());
+            hash = fnv1a(hash, &mtime.to_le_bytes());
+        }
+    }
+
+    hash
+}
+
+// access also knows about acls and read-only mounts, the permission bits don't
+fn is_writable(path: &str) -> bool {
+    let path = match CString::new(path) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+
+    // SAFETY: path is a nul terminated string that lives until the end of this function
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+// $XDG_CACHE_HOME/rsmodules/<hash of modulepath>, the folder is made when
+// the cache is written
+fn personal_cache_dir(modulepath: &str) -> Option<PathBuf> {
+    let path = fs::canonicalize(modulepath).unwrap_or_else(|_| PathBuf::from(modulepath));
+    let hash = fnv1a(FNV_OFFSET, path.to_string_lossy().as_bytes());
+
+    Some(dirs::cache_dir()?.join("rsmodules").join(format!("{:016x}", hash)))
+}
+
+// the cache file of modulepath, this is the .modulecache in modulepath itself,
+// unless there is none and we cannot make it: shared folders that are
+// maintained by somebody else or a read-only filesystem, then the user gets
+// an index of their own that is made when it is needed
+pub fn cache_file(modulepath: &str) -> String {
+    let mut cache_files = lu!(CACHE_FILES);
+    if let Some(filename) = cache_files.get(modulepath) {
+        return filename.to_string();
+    }
+
+    let filename = select_cache_file(modulepath, is_writable);
+
+    cache_files.insert(modulepath.to_string(), filename.to_string());
+    filename
+}
+
+fn select_cache_file(modulepath: &str, is_writable: impl Fn(&str) -> bool) -> String {
+    let filename = format!("{}/{}{}", modulepath, MODULECACHE, release_debug());
+    if Path::new(&filename).exists() || !Path::new(modulepath).is_dir() || is_writable(modulepath) {
+        return filename;
+    }
+
+    match personal_cache_dir(modulepath) {
+        Some(dir) => format!("{}/{}{}", dir.display(), MODULECACHE, release_debug()),
+        None => filename,
+    }
+}
+
+pub fn is_personal_cache(modulepath: &str, filename: &str) -> bool {
+    filename != format!("{}/{}{}", modulepath, MODULECACHE, release_debug())
+}
+
+// a personal cache is made again when one of the folders in modulepath changed
+fn is_personal_cache_outdated(modulepath: &str, filename: &str) -> bool {
+    match fs::read_to_string(format!("{}.stamp", filename)) {
+        Ok(stamp) => stamp.trim() != format!("{}", folders_stamp(modulepath)),
+        Err(_) => true,
+    }
+}
+
+// the header, the modules and their metadata
+fn encode_cache(modules: &[Module], metadata: &[ModuleMetadata]) -> Vec<u8> {
+    let mut buffer: Vec<u8> = CACHE_MAGIC.to_vec();
+    encode_into(&CACHE_VERSION, &mut buffer, bincode::SizeLimit::Infinite).unwrap();
+    encode_into(&modules, &mut buffer, bincode::SizeLimit::Infinite).unwrap();
+    encode_into(&metadata, &mut buffer, bincode::SizeLimit::Infinite).unwrap();
+
+    buffer
+}
+
+// the cache is written to a temporary file in the same folder that is renamed
+// afterwards, this way nobody can read a half written cache file
+fn write_cache(modulepath: &str, modules: &[Module], metadata: &[ModuleMetadata]) -> io::Result<()> {
+    let filename = cache_file(modulepath);
+    let tmp_filename = format!("{}.{}.tmp", filename, process::id());
+
+    let result = write_and_rename(&tmp_filename, &filename, &encode_cache(modules, metadata));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_filename);
+    }
+
+    result
+}
+
+fn write_and_rename(tmp_filename: &str, filename: &str, buffer: &[u8]) -> io::Result<()> {
+    let mut file = File::create(tmp_filename)?;
+    file.write_all(buffer)?;
+    file.sync_all()?;
+
+    // a new file gets the permissions of the umask of the user that runs
+    // cache make, keep the ones of the cache we replace
+    if let Ok(metadata) = fs::metadata(filename) {
+        fs::set_permissions(tmp_filename, metadata.permissions())?;
+    }
+
+    fs::rename(tmp_filename, filename)
+}
+
+// takes an advisory lock on the folder of the cache of a modulepath, the
+// lock is released when the returned file is dropped, None when we cannot
+// write in that folder. The cache file itself is replaced by a rename, a
+// lock on it would be gone with the old file
+fn lock_cache(modulepath: &str, shell: &str) -> Option<File> {
+    let cache = cache_file(modulepath);
+    let dir = Path::new(&cache).parent()?;
+    if is_personal_cache(modulepath, &cache) {
+        fs::create_dir_all(dir).ok()?;
+    }
+    if !is_writable(&dir.to_string_lossy()) {
+        return None;
+    }
+
+    let file = File::open(dir).ok()?;
+    let fd = file.as_raw_fd();
+
+    // SAFETY: fd belongs to file, which is open for the whole call
+    if unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } != 0
+        && io::Error::last_os_error().raw_os_error() == Some(libc::EWOULDBLOCK)
+    {
+        echo(
+            &format!("  Waiting until somebody else is done updating the index of {}.", modulepath),
+            shell,
+        );
+        // filesystems that don't support locking are used without a lock
+        // SAFETY: fd belongs to file, which is open for the whole call
+        unsafe { libc::flock(fd, libc::LOCK_EX) };
+    }
+
+    Some(file)
+}
+
+fn read_cache_file(filename: &Path) -> Result<(Vec<Module>, Vec<ModuleMetadata>), CacheError> {
+    let mut buffer: Vec<u8> = Vec::new();
+    match File::open(filename) {
+        Ok(mut file) => {
+            if file.read_to_end(&mut buffer).is_err() {
+                return Err(CacheError::Corrupt);
+            }
+        }
+        Err(_) => return Err(CacheError::Missing),
+    };
+
+    let limit = bincode::SizeLimit::Bounded(buffer.len() as u64);
+
+    if !buffer.starts_with(CACHE_MAGIC) {
+        // caches without a header were made before the format had a version,
+        // they only contain the modules, module cache make adds the metadata
+        // that spider and which need
+        let mut reader: &[u8] = &buffer;
+        let modules: Vec<Module> = decode_from(&mut reader, limit).map_err(|_| CacheError::Corrupt)?;
+        if !reader.is_empty() {
+            return Err(CacheError::Corrupt);
+        }
+        return Ok((modules, Vec::new()));
+    }
+
+    let mut reader: &[u8] = &buffer[CACHE_MAGIC.len()..];
+    let version: u32 = decode_from(&mut reader, limit).map_err(|_| CacheError::Corrupt)?;
+    if version != CACHE_VERSION {
+        return Err(CacheError::Stale(version));
+    }
+
+    let modules: Vec<Module> = decode_from(&mut reader, limit).map_err(|_| CacheError::Corrupt)?;
+    let metadata: Vec<ModuleMetadata> = decode_from(&mut reader, limit).map_err(|_| CacheError::Corrupt)?;
+
+    // a file that was only partly overwritten
+    if !reader.is_empty() {
+        return Err(CacheError::Corrupt);
+    }
+
+    Ok((modules, metadata))
+}
+
+// reads the cache file of a modulepath, when it is missing, stale or corrupt we
+// tell the user and when rebuild is true we try to make a new one
+fn read_cache(modulepath: &str, shell: &str, rebuild: bool) -> Option<(Vec<Module>, Vec<ModuleMetadata>)> {
+    let filename = cache_file(modulepath);
+
+    // nobody else makes a personal cache, so we always do it ourselves
+    let personal = is_personal_cache(modulepath, &filename);
+    if personal && is_personal_cache_outdated(modulepath, &filename) {
+        build(modulepath, shell, false, false);
+    }
+
+    let filename = PathBuf::from(filename);
+    let error = match read_cache_file(&filename) {
+        Ok(cache) => return Some(cache),
+        Err(error) => error,
+    };
+
+    let warning = bold(shell, "WARNING");
+    let msg = match error {
+        CacheError::Missing => format!("  {}: {} doesn't contain an index.", warning, modulepath),
+        CacheError::Stale(version) if version > CACHE_VERSION => format!(
+            "  {}: the index of {} was made by a newer version of rsmodules.",
+            warning, modulepath
+        ),
+        CacheError::Stale(_) => format!(
+            "  {}: the index of {} was made by an older version of rsmodules.",
+            warning, modulepath
+        ),
+        CacheError::Corrupt => format!("  {}: the index of {} is corrupt.", warning, modulepath),
+    };
+    echo(&msg, shell);
+
+    // don't replace the index of a newer version with an older one
+    let newer = match error {
+        CacheError::Stale(version) => version > CACHE_VERSION && !personal,
+        _ => false,
+    };
+    let rebuild = rebuild || personal;
+
+    if !rebuild || newer {
+        if error != CacheError::Missing && !newer {
+            echo(&format!("  Run {} to update it.", bold(shell, "module cache make")), shell);
+        }
+        return None;
+    }
+
+    if update(modulepath, shell) {
+        return read_cache_file(&filename).ok();
+    }
+
+    None
+}
+
+fn count_modules_in_cache(filename: &Path) -> u64 {
+    match read_cache_file(filename) {
+        Ok((modules, _)) => modules.len() as u64,
+        Err(_) => 0,
+    }
+}
+
+// adds the modules in the cache of modulepath, returns false when there is no usable cache
+pub fn parse_modules_cache_file(modulepath: &str, shell: &str, modules: &mut Vec<(String, bool, String)>) -> bool {
+    let (decoded, _) = match read_cache(modulepath, shell, true) {
+        Some(cache) => cache,
+        None => return false,
+    };
+
+    for module in decoded {
+        modules.push((module.name, module.default, module.deprecated));
+    }
+
+    true
+}
+
+// returns not, before or after, depending on the deprecation date
+// that is stored in the cache
+pub fn get_deprecated_state(deprecated: &str) -> &'static str {
+    if deprecated == "0" || deprecated.is_empty() {
+        return "not";
+    }
+
+    let mstime = format!("{} 00:00:00 +0000", deprecated);
+    let mstime = match DateTime::parse_from_str(&mstime, "%Y-%m-%d %T %z") {
+        Ok(mstime) => mstime.timestamp_millis(),
+        Err(_) => return "not",
+    };
+
+    if Utc::now().timestamp_millis() > mstime {
+        "after"
+    } else {
+        "before"
+    }
+}
+
+// prints the module av output as a json array, the search strings
+// are or'ed together, just like module av blast java would do
+pub fn get_module_list_json(search: &[String], opts: &AvailableOptions) {
+    let mut decoded: Vec<Module> = Vec::new();
+    for modulepath in get_module_paths(false) {
+        if let Some((decoded_file, _)) = read_cache(&modulepath, "json", false) {
+            decoded.extend(decoded_file);
+        }
+    }
+
+    decoded.sort_by(|a, b| natord::compare(&a.name, &b.name));
+
+    let mut regexes: Vec<Regex> = Vec::new();
+    if opts.regex {
+        for arg in search {
+            match Regex::new(arg) {
+                Ok(re) => regexes.push(re),
+                Err(_) => {
+                    crash(super::super::CRASH_INVALID_REGEX, "Invalid regular expression");
+                    return;
+                }
+            }
+        }
+    }
+
+    let mut result: Vec<JsonModule> = Vec::new();
+    for module in decoded {
+        if opts.default && !module.default {
+            continue;
+        }
+
+        if opts.deprecated && module.deprecated == "0" {
+            continue;
+        }
 
-        match deprecated.state {
-            script::DeprecatedState::Not => add_module(modulename, description, default, "0".to_string(), &mut modules),
-            script::DeprecatedState::Before => add_module(modulename, description, default, deprecated.time, &mut modules),
-            script::DeprecatedState::After => add_module(modulename, description, default, deprecated.time, &mut modules),
-            //script::DeprecatedState::After => {}
+        let matches = if search.is_empty() {
+            true
+        } else if opts.regex {
+            regexes.iter().any(|re| re.is_match(&module.name))
+        } else {
+            let name_lc = module.name.to_lowercase();
+            search.iter().any(|arg| name_lc.contains(&arg.to_lowercase()))
         };
-    }
-
-    if shell == "progressbar" {
-        echo("", shell);
-    }
-    echo("", shell);
-    echo(&"  Writing cache file.", shell);
 
-    let file: File = match File::create(&file_str) {
-        Ok(file) => file,
-        Err(_) => {
-            if shell != "noshell" {
-                echo("", shell);
-                let msg: String = format!(
-                    "  {}: {} could NOT be indexed.",
-                    bold(shell, "WARNING"),
-                    bold(shell, modulepath)
-                );
-                echo(&msg, shell);
-            } else {
-                let msg: String = format!("{} failed", modulepath);
-                echo(&msg, shell);
-            }
-            return false;
+        if !matches {
+            continue;
         }
-    };
 
-    let mut writer = BufWriter::new(file);
-    encode_into(&modules, &mut writer, bincode::SizeLimit::Infinite).unwrap();
-
-    if shell != "noshell" {
-        echo("", shell);
-        let msg: String = format!("  {} was succesfully indexed.", bold(shell, modulepath));
-        echo(&msg, shell);
-        echo("", shell);
-        let tmp = format!("{}", index_succes);
-        let msg: String = format!("  * Total number of modules: {}", bold(shell, &tmp));
-        echo(&msg, shell);
-        let tmp = format!("{}", index_default);
-        let msg: String = format!("  * Number of default (D) modules: {}", bold(shell, &tmp));
-        echo(&msg, shell);
-        echo("", shell);
-    } else {
-        let msg: String = format!("{} success", modulepath);
-        echo(&msg, shell);
-        let tmp = format!("{}", index_succes);
-        let msg: String = format!("Total number of modules: {}", &tmp);
-        echo(&msg, shell);
-        let tmp = format!("{}", index_default);
-        let msg: String = format!("Number of default (D) modules: {}", &tmp);
-        echo(&msg, shell);
+        result.push(JsonModule {
+            deprecated_state: get_deprecated_state(&module.deprecated).to_string(),
+            deprecated: if module.deprecated == "0" {
+                String::new()
+            } else {
+                module.deprecated.clone()
+            },
+            loaded: is_module_loaded(&module.name, true),
+            name: module.name,
+            description: module.description,
+            default: module.default,
+        });
     }
 
-    true
+    println!("{}", json::encode(&result).unwrap());
 }
 
-fn count_modules_in_cache(filename: &PathBuf) -> u64 {
-    let file: File = match File::open(filename) {
-        Ok(file) => file,
-        Err(_) => {
-            crash(
-                super::super::CRASH_COULDNT_OPEN_CACHE_FILE,
-                &format!(
-                    "count_modules_in_cache: couldn't open the required index file: {:?}",
-                    filename
-                ),
-            );
-            return 0;
+// prints the loaded modules as a json array, in the order they are given
+// the details come from the cache, a module that isn't in it still gets a name
+pub fn get_loaded_module_list_json(loadedmodules: &[&str]) {
+    let mut decoded: Vec<Module> = Vec::new();
+    for modulepath in get_module_paths(false) {
+        if let Some((decoded_file, _)) = read_cache(&modulepath, "json", false) {
+            decoded.extend(decoded_file);
         }
-    };
-    let mut reader = BufReader::new(file);
-    let decoded: Vec<Module> = decode_from(&mut reader, bincode::SizeLimit::Infinite).unwrap();
-
-    decoded.len() as u64
-}
+    }
 
-pub fn parse_modules_cache_file(filename: &PathBuf, modules: &mut Vec<(String, bool, String)>) {
-    let file: File = match File::open(filename) {
-        Ok(file) => file,
-        Err(_) => {
-            crash(
-                super::super::CRASH_COULDNT_OPEN_CACHE_FILE,
-                &format!(
-                    "parse_modules_in_cache: couldn't open the required index file: {:?}",
-                    filename
-                ),
-            );
-            return;
-        }
-    };
-    let mut reader = BufReader::new(file);
-    let decoded: Vec<Module> = decode_from(&mut reader, bincode::SizeLimit::Infinite).unwrap();
+    let mut result: Vec<JsonLoadedModule> = Vec::new();
+    for name in loadedmodules {
+        let (description, default, deprecated) = match decoded.iter().find(|module| module.name == *name) {
+            Some(module) => (module.description.clone(), module.default, module.deprecated.clone()),
+            None => (String::new(), false, String::from("0")),
+        };
 
-    for module in decoded {
-        modules.push((module.name, module.default, module.deprecated));
+        result.push(JsonLoadedModule {
+            name: name.to_string(),
+            description,
+            default,
+            deprecated_state: get_deprecated_state(&deprecated).to_string(),
+            deprecated: if deprecated == "0" { String::new() } else { deprecated },
+            dependency: script::is_dependency(name),
+        });
     }
+
+    println!("{}", json::encode(&result).unwrap());
 }
 
 fn find_char_boundary(s: &str, i: usize) -> Option<usize> {
@@ -556,31 +1335,12 @@ pub fn get_module_list(arg: &str, rsmod: &Rsmodule, opts: &AvailableOptions) {
     let mut longest_name = 0;
     let mut decoded: Vec<Module> = Vec::new();
     for modulepath in modulepaths.clone() {
-        let file: File = match File::open(format!("{}/{}{}", modulepath, MODULECACHE, release_debug())) {
-            Ok(file) => file,
-            Err(_) => {
-                echo(
-                    &format!("  {}: {} doesn't contain an index.", bold(shell, "WARNING"), modulepath),
-                    shell,
-                );
-                if update(&modulepath, shell) {
-                    match File::open(format!("{}/{}{}", modulepath, MODULECACHE, release_debug())) {
-                        Ok(file) => file,
-                        Err(_) => {
-                            // for some unknown reason we cannot open the file
-                            // while we could generate it in the update function
-                            // let's be honest: we're screwed
-                            continue;
-                        }
-                    }
-                } else {
-                    continue;
-                }
-            }
+        // a missing, stale or corrupt index is rebuilt
+        let decoded_file: Vec<Module> = match read_cache(&modulepath, shell, true) {
+            Some((decoded_file, _)) => decoded_file,
+            None => continue,
         };
 
-        let mut reader = BufReader::new(file);
-        let decoded_file: Vec<Module> = decode_from(&mut reader, bincode::SizeLimit::Infinite).unwrap();
         for item in decoded_file {
             decoded.push(item);
         }
@@ -844,9 +1604,35 @@ struct AddOpts {
 struct MakeOpts {
     #[options(help = "Print this help message")]
     help: bool,
+
+    #[options(no_short, help = "Parse all the modulefiles again, not only the new or changed ones")]
+    full: bool,
 }
 
-// Options accepted for the `module cache add` command
+// Options accepted for the `module cache delete` command
+#[derive(Debug, Options)]
+struct DeleteOpts {
+    #[options(help = "Print this help message")]
+    help: bool,
+
+    #[options(no_short, help = "Single path from $MODULEPATH (required)")]
+    modulepath: String,
+
+    #[options(no_short, help = "Name of the module (required)")]
+    name: String,
+}
+
+// Options accepted for the `module cache verify` command
+#[derive(Debug, Options)]
+struct VerifyOpts {
+    #[options(help = "Print this help message")]
+    help: bool,
+
+    #[options(no_short, help = "Fix the problems that are found without asking")]
+    fix: bool,
+}
+
+// Options accepted for the `module cache edit` command
 #[derive(Debug, Options)]
 struct EditOpts {
     #[options(help = "Print this help message")]
@@ -879,6 +1665,10 @@ enum Command {
     Make(MakeOpts),
     #[options(help = "Edit an entry in the cache file")]
     Edit(EditOpts),
+    #[options(help = "Delete an entry from the cache file")]
+    Delete(DeleteOpts),
+    #[options(help = "Check if the cache file agrees with the modulefiles")]
+    Verify(VerifyOpts),
 }
 
 #[derive(Debug, Default, Options)]
@@ -893,7 +1683,7 @@ struct CacheOptions {
 fn print_help(args: &[String], shell: &str, command: &str) {
     let mut cmd = command;
     if command.is_empty() {
-        cmd = "[make|add|edit|delete]";
+        cmd = "[make|add|edit|delete|verify]";
     }
 
     if shell == "noshell" || shell == "python" || shell == "perl" {
@@ -906,6 +1696,10 @@ fn print_help(args: &[String], shell: &str, command: &str) {
         eprintln!("{}", AddOpts::usage());
     } else if cmd == "edit" {
         eprintln!("{}", EditOpts::usage());
+    } else if cmd == "delete" {
+        eprintln!("{}", DeleteOpts::usage());
+    } else if cmd == "verify" {
+        eprintln!("{}", VerifyOpts::usage());
     } else {
         eprintln!("{}", CacheOptions::usage());
     }
@@ -1003,6 +1797,17 @@ fn get_modify_opts(rsmod: &Rsmodule, command: String) -> ModifyOpts {
         modifyopts.description = addopts.description;
         modifyopts.default = addopts.default;
         modifyopts.deprecated = addopts.deprecated;
+    } else if command == "delete" {
+        let deleteopts = match DeleteOpts::parse_args_default(&args[4..]) {
+            Ok(opts) => opts,
+            Err(e) => {
+                print_help(&args, rsmod.shell, "");
+                eprintln!("{}: {}", args[0], e);
+                return Default::default();
+            }
+        };
+        modifyopts.modulepath = deleteopts.modulepath;
+        modifyopts.name = deleteopts.name;
     } else {
         let editopts = match EditOpts::parse_args_default(&args[4..]) {
             Ok(opts) => opts,
@@ -1042,14 +1847,37 @@ pub fn run(rsmod: &Rsmodule) {
     if_let_some!(command = opts.command_name(), ());
 
     if command == "make" {
+        let full = match opts.command {
+            Some(Command::Make(ref makeopts)) => makeopts.full,
+            _ => false,
+        };
+
         let modulepaths = get_module_paths(false);
         for modulepath in modulepaths {
             if modulepath != "" {
-                update(&modulepath, rsmod.shell);
+                make(&modulepath, rsmod.shell, full);
+            }
+        }
+        return;
+    } else if command == "verify" {
+        let fix = match opts.command {
+            Some(Command::Verify(ref verifyopts)) => {
+                if verifyopts.help {
+                    print_help(&args, rsmod.shell, "verify");
+                    return;
+                }
+                verifyopts.fix
+            }
+            _ => false,
+        };
+
+        for modulepath in get_module_paths(false) {
+            if modulepath != "" {
+                verify(&modulepath, rsmod.shell, fix);
             }
         }
         return;
-    } else if command == "add" || command == "edit" {
+    } else if command == "add" || command == "edit" || command == "delete" {
         let modopts = get_modify_opts(&rsmod, command.to_string());
 
         if !modopts.name.is_empty() && !modopts.modulepath.is_empty() {
@@ -1065,3 +1893,542 @@ pub fn run(rsmod: &Rsmodule) {
         print_help(&args, rsmod.shell, "");
     }
 }
+
+// returns the variables and binaries of a module that match,
+// None when its name, description, variables and binaries don't match
+// caches made before the format had a version only contain the modules
+fn warn_without_metadata(modulepath: &str, shell: &str, modules: &[Module], metadata: &[ModuleMetadata]) {
+    if !modules.is_empty() && metadata.is_empty() {
+        echo(
+            &format!(
+                "  {}: the index of {} is too old to search in, run {} to update it.",
+                bold(shell, "WARNING"),
+                modulepath,
+                bold(shell, "module cache make")
+            ),
+            shell,
+        );
+    }
+}
+
+fn spider_match(module: &Module, data: &ModuleMetadata, is_match: &dyn Fn(&str) -> bool) -> Option<(Vec<String>, Vec<String>)> {
+    let description_matches = data.description.iter().any(|line| is_match(line));
+    let variables: Vec<String> = data.variables.iter().filter(|var| is_match(var)).cloned().collect();
+    let binaries: Vec<String> = data.binaries.iter().filter(|bin| is_match(bin)).cloned().collect();
+
+    if !is_match(&module.name) && !description_matches && variables.is_empty() && binaries.is_empty() {
+        return None;
+    }
+
+    Some((variables, binaries))
+}
+
+// searches the module names, descriptions, variables and binaries
+// without running the modulefiles, everything comes from the cache
+pub fn spider(search: &[String], rsmod: &Rsmodule, opts: &SpiderOptions) {
+    let shell: &str = rsmod.shell;
+
+    let mut regexes: Vec<Regex> = Vec::new();
+    for arg in search {
+        let pattern = if opts.regex {
+            arg.to_string()
+        } else {
+            format!("(?i){}", regex::escape(arg))
+        };
+        match Regex::new(&pattern) {
+            Ok(re) => regexes.push(re),
+            Err(_) => {
+                crash(super::super::CRASH_INVALID_REGEX, "Invalid regular expression");
+                return;
+            }
+        }
+    }
+    let is_match = |value: &str| regexes.iter().any(|re| re.is_match(value));
+
+    let mut result: Vec<JsonSpiderModule> = Vec::new();
+    for modulepath in get_module_paths(false) {
+        let (modules, metadata) = match read_cache(&modulepath, shell, false) {
+            Some(cache) => cache,
+            None => continue,
+        };
+        warn_without_metadata(&modulepath, shell, &modules, &metadata);
+
+        for module in modules {
+            if get_deprecated_state(&module.deprecated) == "after" {
+                continue;
+            }
+
+            let empty = ModuleMetadata {
+                name: module.name.clone(),
+                description: vec![module.description.clone()],
+                variables: Vec::new(),
+                binaries: Vec::new(),
+                executables: Vec::new(),
+                mtime: 0,
+                size: 0,
+                version_mtime: 0,
+            };
+            let data = metadata.iter().find(|data| data.name == module.name).unwrap_or(&empty);
+
+            let (variables, binaries) = match spider_match(&module, data, &is_match) {
+                Some(matches) => matches,
+                None => continue,
+            };
+
+            result.push(JsonSpiderModule {
+                loaded: is_module_loaded(&module.name, true),
+                name: module.name,
+                description: data.description.clone(),
+                default: module.default,
+                variables,
+                binaries,
+            });
+        }
+    }
+
+    result.sort_by(|a, b| natord::compare(&a.name, &b.name));
+    result.dedup_by(|a, b| a.name == b.name);
+
+    if shell == "json" {
+        println!("{}", json::encode(&result).unwrap());
+        return;
+    }
+
+    if shell == "noshell" || shell == "python" || shell == "perl" {
+        for module in &result {
+            println!("{}", module.name);
+        }
+        return;
+    }
+
+    echo("", shell);
+    if result.is_empty() {
+        echo(&format!("  No modules found for {}.", bold(shell, &search.join(" "))), shell);
+        echo("", shell);
+        return;
+    }
+
+    echo(&format!("  Modules that match {}:", bold(shell, &search.join(" "))), shell);
+    echo("", shell);
+    for module in &result {
+        let default = if module.default { " (D)" } else { "" };
+        let name = if module.loaded {
+            format!("{}", bold(shell, &module.name))
+        } else {
+            module.name.clone()
+        };
+        echo(&format!("  * {}{}", name, default), shell);
+        // the first line and the other lines that match
+        for (i, line) in module.description.iter().enumerate() {
+            if i == 0 || is_match(line) {
+                echo(&format!("      {}", line), shell);
+            }
+        }
+        for variable in &module.variables {
+            echo(&format!("      {} {}", bold(shell, "variable:"), variable), shell);
+        }
+        for binary in &module.binaries {
+            echo(&format!("      {} {}", bold(shell, "binary:"), binary), shell);
+        }
+    }
+    echo("", shell);
+}
+
+// the executables of a module that match one of the patterns
+fn which_match(data: &ModuleMetadata, patterns: &[Pattern]) -> Vec<String> {
+    data.executables
+        .iter()
+        .filter(|executable| patterns.iter().any(|pattern| pattern.matches(executable)))
+        .cloned()
+        .collect()
+}
+
+// D for the default module followed by # or R when it is deprecated,
+// a default module can be deprecated too
+fn which_flags(default: bool, deprecated_state: &str) -> String {
+    let default = if default { 'D' } else { ' ' };
+    let deprecated = match deprecated_state {
+        "after" => 'R',
+        "before" => '#',
+        _ => ' ',
+    };
+
+    format!("{}{}", default, deprecated)
+}
+
+// lists the modules that provide an executable, the search strings can be globs
+pub fn which(search: &[String], rsmod: &Rsmodule) {
+    let shell: &str = rsmod.shell;
+
+    let mut patterns: Vec<Pattern> = Vec::new();
+    for arg in search {
+        match Pattern::new(arg) {
+            Ok(pattern) => patterns.push(pattern),
+            Err(e) => {
+                crash(super::super::CRASH_INVALID_REGEX, &format!("Invalid pattern {} ({})", arg, e));
+                return;
+            }
+        }
+    }
+
+    let mut result: Vec<JsonWhichModule> = Vec::new();
+    for modulepath in get_module_paths(false) {
+        let (modules, metadata) = match read_cache(&modulepath, shell, false) {
+            Some(cache) => cache,
+            None => continue,
+        };
+        warn_without_metadata(&modulepath, shell, &modules, &metadata);
+
+        for module in modules {
+            let executables: Vec<String> = match metadata.iter().find(|data| data.name == module.name) {
+                Some(data) => which_match(data, &patterns),
+                None => continue,
+            };
+
+            if executables.is_empty() {
+                continue;
+            }
+
+            result.push(JsonWhichModule {
+                deprecated_state: get_deprecated_state(&module.deprecated).to_string(),
+                deprecated: if module.deprecated == "0" {
+                    String::new()
+                } else {
+                    module.deprecated.clone()
+                },
+                loaded: is_module_loaded(&module.name, true),
+                name: module.name,
+                executables,
+                default: module.default,
+            });
+        }
+    }
+
+    result.sort_by(|a, b| natord::compare(&a.name, &b.name));
+    result.dedup_by(|a, b| a.name == b.name);
+
+    if shell == "json" {
+        println!("{}", json::encode(&result).unwrap());
+        return;
+    }
+
+    if shell == "noshell" || shell == "python" || shell == "perl" {
+        for module in &result {
+            println!("{}", module.name);
+        }
+        return;
+    }
+
+    echo("", shell);
+    if result.is_empty() {
+        echo(&format!("  No module provides {}.", bold(shell, &search.join(" "))), shell);
+        echo("", shell);
+        return;
+    }
+
+    echo(&format!("  {} is provided by:", bold(shell, &search.join(" "))), shell);
+    echo("", shell);
+
+    let longest_name = result.iter().map(|module| module.name.len()).max().unwrap_or(0);
+    for module in &result {
+        let flag = which_flags(module.default, &module.deprecated_state);
+        let padding = " ".repeat(longest_name - module.name.len());
+        let name = if module.loaded {
+            format!("{}", bold(shell, &module.name))
+        } else {
+            module.name.clone()
+        };
+        echo(
+            &format!("  {} {}{} | {}", flag, name, padding, module.executables.join(", ")),
+            shell,
+        );
+    }
+
+    echo("", shell);
+    echo(
+        &format!("  {} D means that the module is set as the default module.", bold(shell, "*")),
+        shell,
+    );
+    echo(
+        &format!(
+            "  {} # means that the module is marked as deprecated and will be removed in the future.",
+            bold(shell, "*")
+        ),
+        shell,
+    );
+    echo(
+        &format!(
+            "  {} R means that the module is deprecated and has been removed.",
+            bold(shell, "*")
+        ),
+        shell,
+    );
+    echo("", shell);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        encode_cache, find_problems, find_unchanged, folders_stamp, is_personal_cache, is_personal_cache_outdated, lock_cache,
+        read_cache_file, select_cache_file, spider_match, which_flags, which_match, with_metadata, write_and_rename, CacheError,
+        Module, ModuleMetadata, CACHE_MAGIC,
+    };
+    use bincode::rustc_serialize::encode_into;
+    use glob::Pattern;
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+    use std::path::Path;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+
+    fn metadata(name: &str) -> ModuleMetadata {
+        ModuleMetadata {
+            name: name.to_string(),
+            description: vec![String::from("Basic Local Alignment Search Tool"), String::from("NCBI")],
+            variables: vec![String::from("BLASTDB"), String::from("PATH")],
+            binaries: vec![String::from("blastn"), String::from("makeblastdb")],
+            executables: vec![String::from("blastn"), String::from("makeblastdb")],
+            mtime: 1,
+            size: 2,
+            version_mtime: 3,
+        }
+    }
+
+    #[test]
+    fn _spider_match() {
+        let module = Module::from(
+            String::from("blast/2.1"),
+            String::from("Basic Local Alignment Search Tool"),
+            true,
+            String::from("0"),
+        );
+        let data = metadata("blast/2.1");
+        let search = |search: &'static str| move |value: &str| value.to_lowercase().contains(search);
+
+        // the name or the description, nothing else matches
+        assert_eq!(
+            Some((Vec::new(), Vec::new())),
+            spider_match(&module, &data, &search("blast/"))
+        );
+        assert_eq!(Some((Vec::new(), Vec::new())), spider_match(&module, &data, &search("ncbi")));
+        assert_eq!(
+            Some((vec![String::from("BLASTDB")], vec![String::from("makeblastdb")])),
+            spider_match(&module, &data, &search("db"))
+        );
+        assert_eq!(
+            Some((Vec::new(), vec![String::from("blastn")])),
+            spider_match(&module, &data, &search("blastn"))
+        );
+        assert_eq!(None, spider_match(&module, &data, &search("hmmer")));
+    }
+
+    #[test]
+    fn _which() {
+        let data = metadata("blast/2.1");
+        let patterns = vec![Pattern::new("blast*").unwrap(), Pattern::new("*db").unwrap()];
+        assert_eq!(
+            vec![String::from("blastn"), String::from("makeblastdb")],
+            which_match(&data, &patterns)
+        );
+        assert_eq!(true, which_match(&data, &[Pattern::new("hmmer").unwrap()]).is_empty());
+
+        assert_eq!("D ", which_flags(true, "not"));
+        assert_eq!("D#", which_flags(true, "before"));
+        assert_eq!("DR", which_flags(true, "after"));
+        assert_eq!(" #", which_flags(false, "before"));
+        assert_eq!("  ", which_flags(false, "not"));
+    }
+
+    #[test]
+    fn _find_unchanged() {
+        let modules = vec![
+            Module::from(String::from("blast/2.1"), String::from("blast"), true, String::from("0")),
+            Module::from(String::from("hmmer/3.3"), String::from("hmmer"), false, String::from("0")),
+        ];
+        // hmmer was added by module cache add, it has no metadata
+        let previous = with_metadata(modules, &[metadata("blast/2.1")]);
+        assert_eq!(1, previous.len());
+        assert_eq!("blast/2.1", previous[0].0.name);
+
+        assert_eq!(true, find_unchanged(&previous, "blast/2.1", (1, 2, 3)).is_some());
+        // the modulefile changed
+        assert_eq!(true, find_unchanged(&previous, "blast/2.1", (4, 2, 3)).is_none());
+        assert_eq!(true, find_unchanged(&previous, "blast/2.1", (1, 5, 3)).is_none());
+        // the .version file changed, the default can be another one
+        assert_eq!(true, find_unchanged(&previous, "blast/2.1", (1, 2, 6)).is_none());
+        assert_eq!(true, find_unchanged(&previous, "hmmer/3.3", (1, 2, 3)).is_none());
+    }
+
+    #[test]
+    fn _find_problems() {
+        let module = |name: &str, default: bool, description: &str| {
+            Module::from(String::from(name), String::from(description), default, String::from("0"))
+        };
+        let current = vec![
+            (module("blast/2.1", true, "blast"), metadata("blast/2.1")),
+            (module("hmmer/3.3", false, "hmmer"), metadata("hmmer/3.3")),
+        ];
+
+        let cached = vec![module("blast/2.1", true, "blast"), module("hmmer/3.3", false, "hmmer")];
+        assert_eq!(true, find_problems(&cached, &current).is_empty());
+
+        let cached = vec![module("blast/2.1", false, "old blast"), module("hmmer/3.2", false, "hmmer")];
+        assert_eq!(
+            vec![
+                "hmmer/3.2: there is no modulefile for this module",
+                "blast/2.1: is the default in .version, but not in the cache",
+                "blast/2.1: the description in the cache differs from the modulefile",
+                "hmmer/3.3: is missing from the cache",
+            ],
+            find_problems(&cached, &current)
+        );
+
+        let cached = vec![module("blast/2.1", true, "blast"), module("hmmer/3.3", true, "hmmer")];
+        assert_eq!(
+            vec!["hmmer/3.3: is the default in the cache, but not in .version"],
+            find_problems(&cached, &current)
+        );
+    }
+
+    #[test]
+    fn _read_cache_file() {
+        let dir = env::temp_dir().join(format!("rsmodules-test-cache-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join(".modulecache");
+
+        let modules = vec![Module::from(
+            String::from("blast/2.1"),
+            String::from("blast"),
+            true,
+            String::from("0"),
+        )];
+        let metadata = vec![metadata("blast/2.1")];
+
+        assert_eq!(Some(CacheError::Missing), read_cache_file(&filename).err());
+
+        let buffer = encode_cache(&modules, &metadata);
+        fs::write(&filename, &buffer).unwrap();
+        let (read_modules, read_metadata) = read_cache_file(&filename).unwrap();
+        assert_eq!(modules, read_modules);
+        assert_eq!(1, read_metadata.len());
+
+        // a cache made by module cache add or make that wrote fewer bytes
+        // than the previous one, without truncating the file
+        let mut longer = buffer.clone();
+        longer.extend_from_slice(&[0, 0, 0]);
+        fs::write(&filename, &longer).unwrap();
+        assert_eq!(Some(CacheError::Corrupt), read_cache_file(&filename).err());
+
+        // cut off in the middle of the modules
+        fs::write(&filename, &buffer[..CACHE_MAGIC.len() + 6]).unwrap();
+        assert_eq!(Some(CacheError::Corrupt), read_cache_file(&filename).err());
+
+        fs::write(&filename, b"not a cache").unwrap();
+        assert_eq!(Some(CacheError::Corrupt), read_cache_file(&filename).err());
+
+        // made by a newer version of rsmodules
+        let mut newer: Vec<u8> = CACHE_MAGIC.to_vec();
+        encode_into(&2u32, &mut newer, bincode::SizeLimit::Infinite).unwrap();
+        encode_into(&modules, &mut newer, bincode::SizeLimit::Infinite).unwrap();
+        fs::write(&filename, &newer).unwrap();
+        assert_eq!(Some(CacheError::Stale(2)), read_cache_file(&filename).err());
+
+        // made before the cache had a header, still usable without the metadata
+        let mut headerless: Vec<u8> = Vec::new();
+        encode_into(&modules, &mut headerless, bincode::SizeLimit::Infinite).unwrap();
+        fs::write(&filename, &headerless).unwrap();
+        let (read_modules, read_metadata) = read_cache_file(&filename).unwrap();
+        assert_eq!(modules, read_modules);
+        assert_eq!(true, read_metadata.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn _select_cache_file() {
+        let dir = env::temp_dir().join(format!("rsmodules-test-personal-{}", process::id()));
+        fs::create_dir_all(dir.join("modules").join("blast")).unwrap();
+        let modulepath = dir.join("modules").to_string_lossy().to_string();
+
+        let shared = select_cache_file(&modulepath, |_| true);
+        assert_eq!(false, is_personal_cache(&modulepath, &shared));
+
+        // a modulepath we cannot write in
+        let personal = select_cache_file(&modulepath, |_| false);
+        assert_eq!(true, is_personal_cache(&modulepath, &personal));
+        assert_eq!(false, personal.starts_with(&modulepath));
+        // the folder is only made when the cache is written
+        assert_eq!(personal, select_cache_file(&modulepath, |_| false));
+
+        // a cache that somebody else made is always used
+        fs::write(&shared, b"").unwrap();
+        assert_eq!(shared, select_cache_file(&modulepath, |_| false));
+
+        // the stamp of a personal cache, outside of modulepath
+        let stamp = dir.join("cache.stamp");
+        let filename = dir.join("cache").to_string_lossy().to_string();
+        assert_eq!(true, is_personal_cache_outdated(&modulepath, &filename));
+        fs::write(&stamp, format!("{}", folders_stamp(&modulepath))).unwrap();
+        assert_eq!(false, is_personal_cache_outdated(&modulepath, &filename));
+
+        // a modulefile added to a folder changes its mtime, wait until
+        // the clock of the filesystem has moved on
+        thread::sleep(Duration::from_millis(50));
+        fs::write(dir.join("modules").join("blast").join("2.1"), b"").unwrap();
+        assert_eq!(true, is_personal_cache_outdated(&modulepath, &filename));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn _write_and_rename() {
+        let dir = env::temp_dir().join(format!("rsmodules-test-write-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join(".modulecache").to_string_lossy().to_string();
+        let tmp_filename = format!("{}.tmp", filename);
+
+        let module = |name: &str| Module::from(String::from(name), String::new(), false, String::from("0"));
+        let modules = vec![module("blast/2.1"), module("hmmer/3.3"), module("samtools/1.9")];
+        let long = encode_cache(&modules, &[metadata("blast/2.1"), metadata("hmmer/3.3")]);
+        write_and_rename(&tmp_filename, &filename, &long).unwrap();
+
+        // module cache delete makes the cache smaller, nothing of the old one is left
+        let short = encode_cache(&modules[..1], &[metadata("blast/2.1")]);
+        write_and_rename(&tmp_filename, &filename, &short).unwrap();
+        assert_eq!(short, fs::read(&filename).unwrap());
+        assert_eq!(1, read_cache_file(Path::new(&filename)).unwrap().0.len());
+        assert_eq!(false, Path::new(&tmp_filename).exists());
+
+        // the temporary file cannot be made, the cache is left alone
+        fs::create_dir_all(&tmp_filename).unwrap();
+        assert_eq!(true, write_and_rename(&tmp_filename, &filename, &long).is_err());
+        assert_eq!(short, fs::read(&filename).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn _lock_cache() {
+        let dir = env::temp_dir().join(format!("rsmodules-test-lock-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let modulepath = dir.to_string_lossy().to_string();
+
+        let other = File::open(&dir).unwrap();
+        // SAFETY: the fd belongs to other, which is open until the end of the test
+        let try_lock = || unsafe { libc::flock(other.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) == 0 };
+
+        let lock = lock_cache(&modulepath, "noshell");
+        assert_eq!(true, lock.is_some());
+        assert_eq!(false, try_lock());
+        drop(lock);
+        assert_eq!(true, try_lock());
+
+        // no lock file is left behind
+        assert_eq!(0, fs::read_dir(&dir).unwrap().count());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,415 @@
+/*
+MIT License
+
+Copyright (c) 2017 Frederik Delaere
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+use super::super::bold;
+use super::{
+    cache, command, echo, get_module_list, get_other_version_of_loaded_module, is_module_loaded, purge, script, Rsmodule,
+    ENV_LOADEDMODULES,
+};
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+extern crate shellexpand;
+
+use rustc_serialize::json;
+
+// a collection is a file with one module per line, in load order
+// modules that were loaded by another module are marked with # dependency
+static COLLECTIONS_DIR: &str = "~/.rsmodules/collections";
+static DEFAULT_COLLECTION: &str = "default";
+// disabled collections get this suffix and are ignored
+static DISABLED_SUFFIX: &str = "~";
+
+fn spaces(shell: &str) -> &'static str {
+    if shell == "noshell" || shell == "perl" || shell == "python" || shell == "r" || shell == "json" {
+        ""
+    } else {
+        "  "
+    }
+}
+
+fn message(msg: &str, shell: &str) {
+    if shell != "noshell" {
+        echo("", shell);
+    }
+    echo(&format!("{}{}", spaces(shell), msg), shell);
+    if shell != "noshell" {
+        echo("", shell);
+    }
+}
+
+// returns the path of the collection file, or None when the name is not valid
+fn get_collection_path(name: &str, shell: &str) -> Option<PathBuf> {
+    if name.contains('/') || name.starts_with('.') || name.ends_with(DISABLED_SUFFIX) {
+        message(&format!("{} is not a valid collection name.", bold(shell, name)), shell);
+        return None;
+    }
+
+    let mut path = PathBuf::from(shellexpand::tilde(COLLECTIONS_DIR).to_string());
+    path.push(name);
+
+    Some(path)
+}
+
+fn get_name<'a>(rsmod: &Rsmodule<'a>) -> &'a str {
+    let args: Vec<&str> = rsmod.arg.split_whitespace().collect();
+
+    if args.is_empty() {
+        DEFAULT_COLLECTION
+    } else {
+        args[0]
+    }
+}
+
+// LOADEDMODULES has the last loaded module first
+fn get_loaded_modules_in_load_order() -> Vec<String> {
+    let mut modules: Vec<String> = match env::var(ENV_LOADEDMODULES) {
+        Ok(list) => list
+            .split(':')
+            .filter(|module| !module.is_empty())
+            .map(|module| module.to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    modules.reverse();
+    modules
+}
+
+pub fn save(rsmod: &mut Rsmodule) {
+    let name = get_name(rsmod);
+    let path = match get_collection_path(name, rsmod.shell) {
+        Some(path) => path,
+        None => return,
+    };
+
+    let modules = get_loaded_modules_in_load_order();
+    if modules.is_empty() {
+        message("There are no modules loaded, nothing to save.", rsmod.shell);
+        return;
+    }
+
+    let dir = shellexpand::tilde(COLLECTIONS_DIR).to_string();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("Cannot create folder {} ({})", dir, e);
+        return;
+    }
+
+    let mut file: File = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Cannot write to file {} ({})", path.display(), e);
+            return;
+        }
+    };
+
+    for module in &modules {
+        let line = collection_line(module, script::is_dependency(module));
+        if let Err(e) = writeln!(file, "{}", line) {
+            eprintln!("Cannot write to file {} ({})", path.display(), e);
+            return;
+        }
+    }
+
+    message(
+        &format!("The loaded modules are saved in the collection {}", bold(rsmod.shell, name)),
+        rsmod.shell,
+    );
+}
+
+fn collection_line(module: &str, dependency: bool) -> String {
+    if dependency {
+        format!("{} # dependency", module)
+    } else {
+        module.to_string()
+    }
+}
+
+// returns the modules and if they were loaded as a dependency
+fn read_collection(path: &PathBuf) -> Option<Vec<(String, bool)>> {
+    match File::open(path) {
+        Ok(file) => parse_collection(BufReader::new(file)),
+        Err(_) => None,
+    }
+}
+
+fn parse_collection<R: BufRead>(reader: R) -> Option<Vec<(String, bool)>> {
+    let mut modules: Vec<(String, bool)> = Vec::new();
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return None,
+        };
+        let mut parts = line.splitn(2, '#');
+        let module = parts.next().unwrap_or("").trim();
+        let dependency = parts.next().unwrap_or("").trim() == "dependency";
+        if !module.is_empty() {
+            modules.push((module.to_string(), dependency));
+        }
+    }
+
+    Some(modules)
+}
+
+pub fn restore(rsmod: &mut Rsmodule) {
+    let name = get_name(rsmod);
+    let path = match get_collection_path(name, rsmod.shell) {
+        Some(path) => path,
+        None => return,
+    };
+
+    let modules = match read_collection(&path) {
+        Some(modules) => modules,
+        None => {
+            message(
+                &format!("The collection {} doesn't exist.", bold(rsmod.shell, name)),
+                rsmod.shell,
+            );
+            return;
+        }
+    };
+
+    let available = get_module_list(rsmod.shell);
+
+    let mut to_load: Vec<&str> = Vec::new();
+    let mut dependencies: Vec<&str> = Vec::new();
+    for (module, dependency) in &modules {
+        match available.iter().find(|available| &available.0 == module) {
+            Some(available) => {
+                let state = cache::get_deprecated_state(&available.2);
+                if state == "after" {
+                    message(
+                        &format!(
+                            "{}: {} was removed at {} and will not be restored.",
+                            bold(rsmod.shell, "WARNING"),
+                            bold(rsmod.shell, module),
+                            available.2
+                        ),
+                        rsmod.shell,
+                    );
+                    continue;
+                } else if state == "before" {
+                    message(
+                        &format!(
+                            "{}: {} is deprecated and will be removed after {}.",
+                            bold(rsmod.shell, "WARNING"),
+                            bold(rsmod.shell, module),
+                            available.2
+                        ),
+                        rsmod.shell,
+                    );
+                }
+                // dependencies are loaded again by the module that needs them
+                if *dependency {
+                    dependencies.push(module);
+                } else {
+                    to_load.push(module);
+                }
+            }
+            None => {
+                message(
+                    &format!(
+                        "{}: {} doesn't exist anymore and will not be restored.",
+                        bold(rsmod.shell, "WARNING"),
+                        bold(rsmod.shell, module)
+                    ),
+                    rsmod.shell,
+                );
+            }
+        }
+    }
+
+    purge(rsmod);
+
+    for module in to_load {
+        // in case it was loaded as a dependency of a previous module
+        if is_module_loaded(module, true) {
+            continue;
+        }
+
+        let mut rsmod_command: Rsmodule = Rsmodule {
+            cmd: "load",
+            typed_command: "restore",
+            arg: module,
+            search_path: rsmod.search_path,
+            shell: rsmod.shell,
+            shell_width: rsmod.shell_width,
+            dry_run: rsmod.dry_run.clone(),
+        };
+        command(&mut rsmod_command);
+    }
+
+    // the module that needs it decides which version is loaded, that
+    // can be another one than the version that was saved
+    for module in dependencies {
+        if is_module_loaded(module, true) {
+            continue;
+        }
+        let loaded = get_other_version_of_loaded_module(module);
+        let msg = if loaded.is_empty() {
+            format!(
+                "{}: the dependency {} was not loaded again.",
+                bold(rsmod.shell, "WARNING"),
+                bold(rsmod.shell, module)
+            )
+        } else {
+            format!(
+                "{}: the dependency {} was saved, but {} is loaded now.",
+                bold(rsmod.shell, "WARNING"),
+                bold(rsmod.shell, module),
+                bold(rsmod.shell, &loaded)
+            )
+        };
+        message(&msg, rsmod.shell);
+    }
+}
+
+fn get_collection_names() -> Vec<String> {
+    let dir = shellexpand::tilde(COLLECTIONS_DIR).to_string();
+    let mut names: Vec<String> = Vec::new();
+
+    if let Ok(entries) = fs::read_dir(&dir) {
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+
+            if !entry.path().is_file() {
+                continue;
+            }
+
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with('.') && !name.ends_with(DISABLED_SUFFIX) {
+                names.push(name);
+            }
+        }
+    }
+
+    names.sort();
+    names
+}
+
+pub fn savelist(shell: &str) {
+    let names = get_collection_names();
+
+    if shell == "json" {
+        println!("{}", json::encode(&names).unwrap());
+        return;
+    }
+
+    if shell == "noshell" {
+        for name in &names {
+            echo(name, shell);
+        }
+        return;
+    }
+
+    echo("", shell);
+    if names.is_empty() {
+        echo("  There are no saved collections.", shell);
+    } else {
+        echo("  Saved collections:", shell);
+        echo("", shell);
+        for name in &names {
+            echo(&format!("  * {}", bold(shell, name)), shell);
+        }
+    }
+    echo("", shell);
+}
+
+// the collection file is kept as name~ so it can still be recovered
+pub fn disable(rsmod: &mut Rsmodule) {
+    let name = get_name(rsmod);
+    let path = match get_collection_path(name, rsmod.shell) {
+        Some(path) => path,
+        None => return,
+    };
+
+    if !path.is_file() {
+        message(
+            &format!("The collection {} doesn't exist.", bold(rsmod.shell, name)),
+            rsmod.shell,
+        );
+        return;
+    }
+
+    let disabled = PathBuf::from(format!("{}{}", path.display(), DISABLED_SUFFIX));
+    if let Err(e) = fs::rename(&path, &disabled) {
+        eprintln!("Cannot rename {} to {} ({})", path.display(), disabled.display(), e);
+        return;
+    }
+
+    message(
+        &format!("The collection {} is disabled.", bold(rsmod.shell, name)),
+        rsmod.shell,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collection_line, get_collection_path, parse_collection};
+
+    #[test]
+    fn _parse_collection() {
+        let collection = "gcc/12.1 # dependency\nblast/2.1\n\n  hmmer/3.3  \n# a comment\nzlib/1.2 # something else\n";
+        assert_eq!(
+            Some(vec![
+                (String::from("gcc/12.1"), true),
+                (String::from("blast/2.1"), false),
+                (String::from("hmmer/3.3"), false),
+                (String::from("zlib/1.2"), false),
+            ]),
+            parse_collection(collection.as_bytes())
+        );
+    }
+
+    #[test]
+    fn _collection_line() {
+        let lines = format!(
+            "{}\n{}\n",
+            collection_line("gcc/12.1", true),
+            collection_line("blast/2.1", false)
+        );
+        assert_eq!(
+            Some(vec![(String::from("gcc/12.1"), true), (String::from("blast/2.1"), false)]),
+            parse_collection(lines.as_bytes())
+        );
+    }
+
+    #[test]
+    fn _get_collection_path() {
+        assert_eq!(
+            true,
+            get_collection_path("default", "noshell")
+                .unwrap()
+                .ends_with("collections/default")
+        );
+        assert_eq!(true, get_collection_path("../default", "noshell").is_none());
+        assert_eq!(true, get_collection_path(".hidden", "noshell").is_none());
+        assert_eq!(true, get_collection_path("default~", "noshell").is_none());
+    }
+}
@@ -0,0 +1,471 @@
+/*
+MIT License
+
+Copyright (c) 2017 Frederik Delaere
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+use super::super::bold;
+use super::{command, echo, is_module_loaded, save_env, script, Rsmodule, ENV_LOADEDMODULES};
+use bincode::rustc_serialize::{decode, encode};
+use bincode::SizeLimit;
+use chrono::{Local, TimeZone};
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use rustc_serialize::json;
+use std::env;
+use std::io::Write;
+
+// the history lives in the environment, so every shell session has its own
+static ENV_HISTORY: &str = "RSMODULES_HISTORY"; // name of an env var
+static HISTORY_SIZE: usize = 20;
+
+// the commands that change the loaded modules and can be undone
+static UNDOABLE: [&str; 7] = ["load", "unload", "switch", "purge", "refresh", "refurbish", "restore"];
+
+// the loaded modules in load order, and which of them were loaded as a dependency
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug, PartialEq)]
+pub struct State {
+    modules: Vec<String>,
+    dependencies: Vec<String>,
+}
+
+impl State {
+    pub fn current() -> State {
+        let mut modules: Vec<String> = match env::var(ENV_LOADEDMODULES) {
+            Ok(list) => list
+                .split(':')
+                .filter(|module| !module.is_empty())
+                .map(|module| module.to_string())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        modules.reverse();
+
+        let dependencies: Vec<String> = modules
+            .iter()
+            .filter(|module| script::is_dependency(module))
+            .cloned()
+            .collect();
+
+        State { modules, dependencies }
+    }
+
+    // undo and redo only load and unload modules, a state that has the same
+    // modules in another order can't be restored and counts as unchanged
+    fn is_same(&self, other: &State) -> bool {
+        let sorted = |list: &[String]| {
+            let mut list = list.to_vec();
+            list.sort();
+            list
+        };
+
+        sorted(&self.modules) == sorted(&other.modules) && sorted(&self.dependencies) == sorted(&other.dependencies)
+    }
+}
+
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+struct Entry {
+    time: i64,
+    command: String,
+    before: State,
+    after: State,
+}
+
+// position is the number of entries that are not undone
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+struct History {
+    position: usize,
+    entries: Vec<Entry>,
+}
+
+// what module history prints when the json shell is used
+#[derive(RustcEncodable)]
+struct JsonEntry {
+    time: String,
+    command: String,
+    undone: bool,
+}
+
+impl History {
+    // the commands that were undone are forgotten
+    fn push(&mut self, entry: Entry) {
+        self.entries.truncate(self.position);
+        self.entries.push(entry);
+
+        if self.entries.len() > HISTORY_SIZE {
+            let overflow = self.entries.len() - HISTORY_SIZE;
+            self.entries.drain(..overflow);
+        }
+        self.position = self.entries.len();
+    }
+
+    // returns the state before the command count steps back, None when there is nothing to undo
+    fn undo(&mut self, count: usize) -> Option<State> {
+        if self.position == 0 {
+            return None;
+        }
+
+        self.position = self.position.saturating_sub(count);
+        Some(self.entries[self.position].before.clone())
+    }
+
+    // returns the state after the command count steps forward, None when there is nothing to redo
+    fn redo(&mut self, count: usize) -> Option<State> {
+        if self.position == self.entries.len() {
+            return None;
+        }
+
+        self.position = (self.position + count).min(self.entries.len());
+        Some(self.entries[self.position - 1].after.clone())
+    }
+}
+
+fn get_history() -> History {
+    match env::var(ENV_HISTORY) {
+        Ok(encoded) => decode_history(&encoded),
+        Err(_) => History {
+            position: 0,
+            entries: Vec::new(),
+        },
+    }
+}
+
+fn decode_history(encoded: &str) -> History {
+    let empty = History {
+        position: 0,
+        entries: Vec::new(),
+    };
+
+    let decoded = match encoded.from_base64() {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            show_warning!("Cannot decode ${} ({})", ENV_HISTORY, e);
+            return empty;
+        }
+    };
+
+    match decode(&decoded) {
+        Ok(history) => history,
+        Err(e) => {
+            show_warning!("Cannot decode ${} ({})", ENV_HISTORY, e);
+            empty
+        }
+    }
+}
+
+fn encode_history(history: &History) -> Option<String> {
+    match encode(history, SizeLimit::Infinite) {
+        Ok(encoded) => Some(encoded.to_base64(STANDARD)),
+        Err(e) => {
+            show_warning!("Cannot encode ${} ({})", ENV_HISTORY, e);
+            None
+        }
+    }
+}
+
+fn save_history(history: &History, shell: &str) {
+    if let Some(encoded) = encode_history(history) {
+        save_env(ENV_HISTORY, &encoded, shell);
+    }
+}
+
+fn format_time(time: i64) -> String {
+    Local.timestamp(time, 0).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+pub fn is_undoable(cmd: &str) -> bool {
+    UNDOABLE.contains(&cmd)
+}
+
+// adds a command to the history, the commands that were undone are forgotten
+pub fn add(cmd: &str, before: State, shell: &str) {
+    // a command that changed nothing, like a conflict or a purge without loaded
+    // modules, would waste an undo and push a real entry out of the history
+    let after = State::current();
+    if after.is_same(&before) {
+        return;
+    }
+
+    let mut history = get_history();
+    history.push(Entry {
+        time: Local::now().timestamp(),
+        command: cmd.trim().to_string(),
+        before,
+        after,
+    });
+
+    save_history(&history, shell);
+}
+
+// loads and unloads modules until the loaded modules match the state
+fn go_to(rsmod: &mut Rsmodule, state: &State, typed_command: &str) {
+    let current = State::current();
+    let mut actions: Vec<(&str, String)> = Vec::new();
+
+    // the last loaded module is unloaded first
+    for module in current.modules.iter().rev() {
+        if !state.modules.contains(module) {
+            actions.push(("unload", module.to_string()));
+        }
+    }
+
+    // the dependencies are loaded by the modules that need them
+    for module in &state.modules {
+        if !state.dependencies.contains(module) && !current.modules.contains(module) {
+            actions.push(("load", module.to_string()));
+        }
+    }
+
+    for module in &state.modules {
+        if state.dependencies.contains(module) && !current.modules.contains(module) {
+            actions.push(("load", module.to_string()));
+        }
+    }
+
+    for (cmd, module) in actions {
+        // unloading a module can unload its dependencies and
+        // loading a module can load its dependencies
+        let loaded = is_module_loaded(&module, true);
+        if (cmd == "unload" && !loaded) || (cmd == "load" && loaded) {
+            continue;
+        }
+
+        let mut rsmod_command: Rsmodule = Rsmodule {
+            cmd,
+            typed_command,
+            arg: &module,
+            search_path: rsmod.search_path,
+            shell: rsmod.shell,
+            shell_width: rsmod.shell_width,
+            dry_run: rsmod.dry_run.clone(),
+        };
+        command(&mut rsmod_command);
+    }
+}
+
+fn get_count(rsmod: &Rsmodule) -> Option<usize> {
+    let args: Vec<&str> = rsmod.arg.split_whitespace().collect();
+
+    if args.is_empty() {
+        return Some(1);
+    }
+
+    match args[0].parse::<usize>() {
+        Ok(count) if count > 0 => Some(count),
+        _ => {
+            echo("", rsmod.shell);
+            echo(
+                &format!("  {} is not a valid number.", bold(rsmod.shell, args[0])),
+                rsmod.shell,
+            );
+            echo("", rsmod.shell);
+            None
+        }
+    }
+}
+
+pub fn undo(rsmod: &mut Rsmodule) {
+    let count = match get_count(rsmod) {
+        Some(count) => count,
+        None => return,
+    };
+
+    let mut history = get_history();
+    let state = match history.undo(count) {
+        Some(state) => state,
+        None => {
+            echo("", rsmod.shell);
+            echo("  There is nothing to undo.", rsmod.shell);
+            echo("", rsmod.shell);
+            return;
+        }
+    };
+
+    go_to(rsmod, &state, "undo");
+    save_history(&history, rsmod.shell);
+}
+
+pub fn redo(rsmod: &mut Rsmodule) {
+    let count = match get_count(rsmod) {
+        Some(count) => count,
+        None => return,
+    };
+
+    let mut history = get_history();
+    let state = match history.redo(count) {
+        Some(state) => state,
+        None => {
+            echo("", rsmod.shell);
+            echo("  There is nothing to redo.", rsmod.shell);
+            echo("", rsmod.shell);
+            return;
+        }
+    };
+
+    go_to(rsmod, &state, "redo");
+    save_history(&history, rsmod.shell);
+}
+
+pub fn list(shell: &str) {
+    let history = get_history();
+
+    if shell == "json" {
+        let entries: Vec<JsonEntry> = history
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| JsonEntry {
+                time: format_time(entry.time),
+                command: entry.command.clone(),
+                undone: i >= history.position,
+            })
+            .collect();
+        println!("{}", json::encode(&entries).unwrap());
+        return;
+    }
+
+    if shell == "noshell" {
+        for (i, entry) in history.entries.iter().enumerate() {
+            let undone = if i >= history.position { " (undone)" } else { "" };
+            echo(
+                &format!("{} {} {}{}", i + 1, format_time(entry.time), entry.command, undone),
+                shell,
+            );
+        }
+        return;
+    }
+
+    echo("", shell);
+    if history.entries.is_empty() {
+        echo("  There is no history yet.", shell);
+        echo("", shell);
+        return;
+    }
+
+    echo("  Module history:", shell);
+    echo("", shell);
+    for (i, entry) in history.entries.iter().enumerate() {
+        let undone = if i >= history.position { " (undone)" } else { "" };
+        echo(
+            &format!(
+                "  {:>3}  {}  {}{}",
+                i + 1,
+                format_time(entry.time),
+                bold(shell, &entry.command),
+                undone
+            ),
+            shell,
+        );
+    }
+    echo("", shell);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_history, encode_history, Entry, History, State, HISTORY_SIZE};
+
+    fn state(modules: &[&str], dependencies: &[&str]) -> State {
+        State {
+            modules: modules.iter().map(|module| module.to_string()).collect(),
+            dependencies: dependencies.iter().map(|module| module.to_string()).collect(),
+        }
+    }
+
+    fn entry(command: &str, before: State, after: State) -> Entry {
+        Entry {
+            time: 0,
+            command: command.to_string(),
+            before,
+            after,
+        }
+    }
+
+    // load a, load b (which loads c), unload a
+    fn history() -> History {
+        let mut history = History {
+            position: 0,
+            entries: Vec::new(),
+        };
+        history.push(entry("load a", state(&[], &[]), state(&["a"], &[])));
+        history.push(entry("load b", state(&["a"], &[]), state(&["a", "c", "b"], &["c"])));
+        history.push(entry("unload a", state(&["a", "c", "b"], &["c"]), state(&["c", "b"], &["c"])));
+        history
+    }
+
+    #[test]
+    fn _encode_decode() {
+        let mut history = history();
+        history.undo(1);
+
+        let decoded = decode_history(&encode_history(&history).unwrap());
+        assert_eq!(2, decoded.position);
+        assert_eq!(3, decoded.entries.len());
+        assert_eq!("load b", decoded.entries[1].command);
+        assert_eq!(state(&["a", "c", "b"], &["c"]), decoded.entries[1].after);
+
+        // a broken history is an empty one
+        let decoded = decode_history("not base64 !");
+        assert_eq!(0, decoded.position);
+        assert_eq!(true, decoded.entries.is_empty());
+    }
+
+    #[test]
+    fn _undo_redo() {
+        let mut history = history();
+        assert_eq!(None, history.redo(1));
+
+        assert_eq!(Some(state(&["a", "c", "b"], &["c"])), history.undo(1));
+        assert_eq!(2, history.position);
+        assert_eq!(Some(state(&[], &[])), history.undo(5));
+        assert_eq!(0, history.position);
+        assert_eq!(None, history.undo(1));
+
+        assert_eq!(Some(state(&["a", "c", "b"], &["c"])), history.redo(2));
+        assert_eq!(2, history.position);
+
+        // a new command forgets what was undone
+        history.push(entry(
+            "load d",
+            state(&["a", "c", "b"], &["c"]),
+            state(&["a", "c", "b", "d"], &["c"]),
+        ));
+        assert_eq!(3, history.entries.len());
+        assert_eq!(3, history.position);
+        assert_eq!("load d", history.entries[2].command);
+        assert_eq!(None, history.redo(1));
+    }
+
+    #[test]
+    fn _size() {
+        let mut history = history();
+        for i in 0..HISTORY_SIZE {
+            history.push(entry(&format!("load {}", i), state(&[], &[]), state(&["a"], &[])));
+        }
+        assert_eq!(HISTORY_SIZE, history.entries.len());
+        assert_eq!(HISTORY_SIZE, history.position);
+        assert_eq!("load 0", history.entries[0].command);
+    }
+
+    #[test]
+    fn _is_same() {
+        assert_eq!(true, state(&["a", "b"], &["b"]).is_same(&state(&["b", "a"], &["b"])));
+        assert_eq!(false, state(&["a", "b"], &["b"]).is_same(&state(&["a", "b"], &[])));
+        assert_eq!(false, state(&["a"], &[]).is_same(&state(&["a", "b"], &[])));
+    }
+}
@@ -0,0 +1,620 @@
+/*
+MIT License
+
+Copyright (c) 2017 Frederik Delaere
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+use super::{cache, echo, failed, get_module_paths, get_modulefile, lua, script, tcl, version, Rsmodule};
+use chrono::NaiveDate;
+use rhai::{Engine, EvalAltResult};
+use std::fs;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// rhai words that can be followed by a (
+static KEYWORDS: &[&str] = &["if", "else", "while", "loop", "return", "let", "fn", "break", "true", "false"];
+
+// the characters rhai can read outside of a string
+static SYMBOLS: &str = "{}()[]+-*/;:,.=<>!|&^%~";
+
+// a problem on a line of a modulefile, line 0 is the file as a whole
+type Problem = (usize, String);
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Name(String),
+    Str(String),
+    Symbol(char),
+}
+
+// a function that a modulefile calls, only the arguments
+// that are a string are known
+struct Call {
+    line: usize,
+    name: String,
+    args: Vec<Option<String>>,
+}
+
+struct Lint<'a> {
+    shell: &'a str,
+    modules: Option<Vec<String>>,
+    checked: usize,
+    failed: usize,
+    missing: usize,
+}
+
+// the tokens of a rhai script with their line numbers, just enough to find the function calls.
+// What rhai can't read is an error here, because rhai prints those errors to stdout
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, Problem> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<(usize, Token)> = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+
+        if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            let start = line;
+            i += 2;
+            while !(chars.get(i) == Some(&'*') && chars.get(i + 1) == Some(&'/')) {
+                match chars.get(i) {
+                    Some('\n') => line += 1,
+                    Some(_) => {}
+                    None => return Err((start, String::from("this comment is never closed"))),
+                }
+                i += 1;
+            }
+            i += 2;
+        } else if c == '\'' {
+            return Err((
+                line,
+                String::from("rhai prints what is between single quotes, use double quotes"),
+            ));
+        } else if c == '"' {
+            let start = line;
+            let mut value = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('\\') => {
+                        match chars.get(i + 1) {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some('r') => value.push('\r'),
+                            Some('"') => value.push('"'),
+                            Some('\\') => value.push('\\'),
+                            // \x41, \u0041 and \U00000041, the value doesn't matter here
+                            Some('x') | Some('u') | Some('U') => {}
+                            Some(escaped) => return Err((line, format!("\\{} is not an escape rhai knows", escaped))),
+                            None => {}
+                        }
+                        i += 2;
+                        continue;
+                    }
+                    Some(quote) if *quote == c => break,
+                    Some(other) => {
+                        if *other == '\n' {
+                            line += 1;
+                        }
+                        value.push(*other);
+                    }
+                    None => return Err((start, String::from("this string is never closed"))),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push((start, Token::Str(value)));
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let mut name = String::new();
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                name.push(chars[i]);
+                i += 1;
+            }
+            tokens.push((line, Token::Name(name)));
+        } else if c.is_whitespace() {
+            if c == '\n' {
+                line += 1;
+            }
+            i += 1;
+        } else if SYMBOLS.contains(c) {
+            tokens.push((line, Token::Symbol(c)));
+            i += 1;
+        } else {
+            return Err((line, format!("rhai doesn't know the character {}", c)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+// returns the function calls and the names of the functions the script defines itself
+fn find_calls(tokens: &[(usize, Token)]) -> (Vec<Call>, Vec<String>) {
+    let mut calls: Vec<Call> = Vec::new();
+    let mut functions: Vec<String> = Vec::new();
+
+    for (i, (line, token)) in tokens.iter().enumerate() {
+        let name = match token {
+            Token::Name(name) => name,
+            _ => continue,
+        };
+        if tokens.get(i + 1).map(|(_, token)| token) != Some(&Token::Symbol('(')) {
+            continue;
+        }
+
+        match i.checked_sub(1).map(|previous| &tokens[previous].1) {
+            // a method, like x.len()
+            Some(Token::Symbol('.')) => continue,
+            Some(Token::Name(previous)) if previous == "fn" => {
+                functions.push(name.to_string());
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut args: Vec<Option<String>> = Vec::new();
+        let mut arg: Vec<&Token> = Vec::new();
+        let mut depth = 0;
+        for (_, token) in tokens.iter().skip(i + 2) {
+            match token {
+                Token::Symbol('(') | Token::Symbol('[') | Token::Symbol('{') => depth += 1,
+                Token::Symbol(')') | Token::Symbol(']') | Token::Symbol('}') if depth == 0 => break,
+                Token::Symbol(')') | Token::Symbol(']') | Token::Symbol('}') => depth -= 1,
+                Token::Symbol(',') if depth == 0 => {
+                    args.push(literal(&arg));
+                    arg.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            arg.push(token);
+        }
+        if !arg.is_empty() {
+            args.push(literal(&arg));
+        }
+
+        calls.push(Call {
+            line: *line,
+            name: name.to_string(),
+            args,
+        });
+    }
+
+    (calls, functions)
+}
+
+fn literal(arg: &[&Token]) -> Option<String> {
+    match arg {
+        [Token::Str(value)] => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+// runs f with stdout going to /dev/null, for eval shells everything on
+// stdout is code for the shell
+fn quietly<T>(f: impl FnOnce() -> T) -> T {
+    let _ = io::stdout().flush();
+    let null = File::options().write(true).open("/dev/null");
+    // SAFETY: dup only reads the fd table, stdout is fd 1 for the whole
+    // run and the copy is ours until it's closed below
+    let saved = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    let redirected = match null {
+        // SAFETY: both fds are open, null lives until the end of this
+        // function and dup2 closes the old fd 1 before it reuses it
+        Ok(ref null) if saved >= 0 => unsafe { libc::dup2(null.as_raw_fd(), libc::STDOUT_FILENO) >= 0 },
+        _ => false,
+    };
+
+    let result = f();
+
+    let _ = io::stdout().flush();
+    if saved >= 0 {
+        // SAFETY: saved is the copy of the real stdout made above, nothing
+        // else owns it, so putting it back and closing it can't affect other fds
+        unsafe {
+            if redirected {
+                libc::dup2(saved, libc::STDOUT_FILENO);
+            }
+            libc::close(saved);
+        }
+    }
+
+    result
+}
+
+// rhai parses the whole script before it runs it, the return in front
+// makes sure nothing is executed. Every parse error becomes an ErrorFunctionArgMismatch
+// and rhai prints what it couldn't parse to stdout
+fn parses(source: &str) -> bool {
+    let mut engine = Engine::new();
+    let result = quietly(|| engine.eval::<()>(&format!("return; {}", source)));
+    !matches!(result, Err(EvalAltResult::ErrorFunctionArgMismatch))
+}
+
+// rhai doesn't tell where a syntax error is, so we look for the longest
+// beginning of the script that parses when its open brackets are closed
+fn find_syntax_error(source: &str, tokens: &[(usize, Token)]) -> Option<Problem> {
+    if parses(source) {
+        return None;
+    }
+
+    let lines: Vec<&str> = source.lines().collect();
+    for end in (0..=lines.len()).rev() {
+        let mut start = lines[..end].join("\n");
+        let start_tokens = match tokenize(&start) {
+            Ok(tokens) => tokens,
+            Err(_) => continue,
+        };
+
+        let mut closing: Vec<char> = Vec::new();
+        for (_, token) in start_tokens {
+            match token {
+                Token::Symbol('(') => closing.push(')'),
+                Token::Symbol('[') => closing.push(']'),
+                Token::Symbol('{') => closing.push('}'),
+                Token::Symbol(c) if closing.last() == Some(&c) => {
+                    closing.pop();
+                }
+                _ => {}
+            }
+        }
+        for c in closing.iter().rev() {
+            start.push('\n');
+            start.push(*c);
+        }
+
+        if parses(&start) {
+            // the error is on the next line that isn't empty or a comment
+            return Some(match tokens.iter().find(|(line, _)| *line > end) {
+                Some((line, _)) => (*line, String::from("syntax error")),
+                None => (lines.len(), String::from("syntax error, a bracket is never closed")),
+            });
+        }
+    }
+
+    Some((1, String::from("syntax error")))
+}
+
+// the checks for rhai modulefiles, the calls are found without running the script
+fn check_rhai(source: &str, problems: &mut Vec<Problem>) -> Vec<Call> {
+    for (number, line) in source.lines().enumerate() {
+        if line.contains('\t') {
+            problems.push((number + 1, String::from("contains a tab, rhai scripts can only use spaces")));
+        }
+    }
+
+    let tokens = match tokenize(source) {
+        Ok(tokens) => tokens,
+        Err(problem) => {
+            problems.push(problem);
+            return Vec::new();
+        }
+    };
+
+    if let Some(problem) = find_syntax_error(source, &tokens) {
+        problems.push(problem);
+    }
+
+    let (calls, functions) = find_calls(&tokens);
+    for call in &calls {
+        let name = call.name.as_str();
+        if !script::FUNCTIONS.contains(&name) && !KEYWORDS.contains(&name) && !functions.contains(&call.name) {
+            problems.push((call.line, format!("unknown function {}()", name)));
+        }
+    }
+
+    calls
+}
+
+// tcl and lua modulefiles are evaluated, the calls they make are remembered
+fn check_evaluated(path: &Path, problems: &mut Vec<Problem>) -> Vec<Call> {
+    let mut calls: Vec<Call> = Vec::new();
+    let mut call = |name: &str, args: &[String]| {
+        calls.push(Call {
+            line: 0,
+            name: name.to_string(),
+            args: args.iter().map(|arg| Some(arg.to_string())).collect(),
+        });
+        String::new()
+    };
+
+    let result = if lua::is_modulefile(path) {
+        lua::run(path, "info", &mut call)
+    } else {
+        tcl::run(path, "info", &mut call)
+    };
+    if let Err(e) = result {
+        problems.push((0, e));
+    }
+
+    calls
+}
+
+impl<'a> Lint<'a> {
+    // the names of the modules in all the caches of $MODULEPATH
+    fn modules(&mut self) -> &[String] {
+        if self.modules.is_none() {
+            let mut modules: Vec<(String, bool, String)> = Vec::new();
+            for modulepath in get_module_paths(true) {
+                cache::parse_modules_cache_file(&modulepath, self.shell, &mut modules);
+            }
+            self.modules = Some(modules.into_iter().map(|(name, _, _)| name).collect());
+        }
+
+        self.modules.as_deref().unwrap_or(&[])
+    }
+
+    fn is_cached(&mut self, module: &str) -> bool {
+        let name = version::name(module);
+        let folder = format!("{}/", name);
+        self.modules()
+            .iter()
+            .any(|cached| cached == name || cached.starts_with(&folder))
+    }
+
+    fn check_calls(&mut self, calls: &[Call], problems: &mut Vec<Problem>) {
+        for call in calls {
+            let first = call.args.first().cloned().unwrap_or_default();
+            let second = call.args.get(1).cloned().unwrap_or_default();
+
+            match (call.name.as_str(), first, second) {
+                ("deprecated", Some(date), _) if NaiveDate::parse_from_str(&date, "%Y-%m-%d").is_err() => {
+                    problems.push((call.line, format!("deprecated(\"{}\") is not a YYYY-MM-DD date", date)));
+                }
+                // relative paths depend on where the module is loaded
+                ("prepend_path", Some(var), Some(path)) | ("append_path", Some(var), Some(path))
+                    if path.starts_with('/') && !Path::new(&path).exists() =>
+                {
+                    problems.push((
+                        call.line,
+                        format!("{}(\"{}\", \"{}\"): {} doesn't exist", call.name, var, path, path),
+                    ));
+                }
+                ("load", Some(modules), _) => {
+                    for module in modules.split_whitespace() {
+                        if !self.is_cached(module) {
+                            problems.push((call.line, format!("load(\"{}\"): {} is not in any cache", modules, module)));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !calls.iter().any(|call| call.name == "description") {
+            problems.push((0, String::from("has no description()")));
+        }
+    }
+
+    fn check(&mut self, path: &Path) {
+        let mut problems: Vec<Problem> = Vec::new();
+
+        let calls = if tcl::is_modulefile(path) || lua::is_modulefile(path) {
+            check_evaluated(path, &mut problems)
+        } else {
+            match fs::read_to_string(path) {
+                Ok(source) => check_rhai(&source, &mut problems),
+                Err(e) => {
+                    problems.push((0, e.to_string()));
+                    Vec::new()
+                }
+            }
+        };
+        // the description is only missing when we could read it
+        if problems.is_empty() || !calls.is_empty() {
+            self.check_calls(&calls, &mut problems);
+        }
+
+        self.checked += 1;
+        if !problems.is_empty() {
+            self.failed += 1;
+        }
+        self.report(&path.to_string_lossy(), problems);
+    }
+
+    fn report(&self, name: &str, mut problems: Vec<Problem>) {
+        if problems.is_empty() {
+            return;
+        }
+
+        problems.sort_by_key(|(line, _)| *line);
+        for (line, problem) in &problems {
+            if *line == 0 {
+                echo(&format!("{}: {}", name, problem), self.shell);
+            } else {
+                echo(&format!("{}:{}: {}", name, line, problem), self.shell);
+            }
+        }
+    }
+
+    fn check_folder(&mut self, folder: &Path) {
+        let mut files: Vec<PathBuf> = Vec::new();
+
+        for entry in WalkDir::new(folder).into_iter().filter_map(|e| e.ok()) {
+            if entry.path().is_dir() {
+                continue;
+            }
+            // the cache, .version and .modulerc files and other hidden files aren't modulefiles
+            let relative = entry.path().strip_prefix(folder).unwrap_or_else(|_| entry.path());
+            if relative
+                .components()
+                .any(|part| part.as_os_str().to_string_lossy().starts_with('.'))
+            {
+                continue;
+            }
+            files.push(entry.path().to_path_buf());
+        }
+
+        files.sort();
+        for file in files {
+            self.check(&file);
+        }
+    }
+
+    // a file, a folder with modulefiles or the name of a module
+    fn check_arg(&mut self, arg: &str) {
+        let path = Path::new(arg);
+        if path.is_file() {
+            self.check(path);
+            return;
+        } else if path.is_dir() {
+            self.check_folder(path);
+            return;
+        }
+
+        let mut found = false;
+        for modulepath in get_module_paths(true) {
+            let modulefile = get_modulefile(&modulepath, arg);
+            if modulefile.is_file() {
+                self.check(&modulefile);
+                found = true;
+            } else if modulefile.is_dir() {
+                self.check_folder(&modulefile);
+                found = true;
+            }
+        }
+
+        if !found {
+            self.missing += 1;
+            self.report(arg, vec![(0, String::from("is not a file, a folder or a module"))]);
+        }
+    }
+}
+
+pub fn run(rsmod: &Rsmodule) {
+    let mut lint = Lint {
+        shell: rsmod.shell,
+        modules: None,
+        checked: 0,
+        failed: 0,
+        missing: 0,
+    };
+
+    let args = shell_words::split(rsmod.arg).unwrap_or_default();
+    if args.is_empty() {
+        for modulepath in get_module_paths(false) {
+            lint.check_folder(Path::new(&modulepath));
+        }
+    } else {
+        for arg in &args {
+            lint.check_arg(arg);
+        }
+    }
+
+    // when only unknown arguments were given, there's nothing to summarize
+    if lint.checked > 0 || lint.missing == 0 {
+        if lint.failed == 0 {
+            echo(
+                &format!("Checked {} modulefiles, no problems found.", lint.checked),
+                rsmod.shell,
+            );
+        } else {
+            echo(
+                &format!("Checked {} modulefiles, {} of them have problems.", lint.checked, lint.failed),
+                rsmod.shell,
+            );
+        }
+    }
+
+    // the arguments that weren't found aren't counted as modulefiles
+    if lint.missing > 0 {
+        echo(
+            &format!("{} of the arguments are not a file, a folder or a module.", lint.missing),
+            rsmod.shell,
+        );
+    }
+
+    if lint.failed > 0 || lint.missing > 0 {
+        failed(rsmod.shell);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_rhai, Lint, Problem};
+
+    fn lint(source: &str) -> Vec<Problem> {
+        let mut lint = Lint {
+            shell: "noshell",
+            modules: Some(Vec::new()),
+            checked: 0,
+            failed: 0,
+            missing: 0,
+        };
+        let mut problems = Vec::new();
+        let calls = check_rhai(source, &mut problems);
+        lint.check_calls(&calls, &mut problems);
+        problems
+    }
+
+    #[test]
+    fn syntax_error() {
+        let source = "description(\"test\");\nsetenv(\"A\", \"1\"\nprepend_path(\"PATH\", \"bin\");\n";
+        let mut problems = Vec::new();
+        check_rhai(source, &mut problems);
+        assert_eq!(problems, vec![(3, String::from("syntax error"))]);
+
+        let source = "description(\"test\");\nif true {\n    setenv(\"A\", \"1\");\n";
+        let mut problems = Vec::new();
+        check_rhai(source, &mut problems);
+        assert_eq!(problems, vec![(3, String::from("syntax error, a bracket is never closed"))]);
+    }
+
+    #[test]
+    fn unknown_function() {
+        let source = "description(\"test\");\nsetenvv(\"A\", \"1\");\nfn helper(x) {\n    x\n}\nhelper(1);\n";
+        assert_eq!(lint(source), vec![(2, String::from("unknown function setenvv()"))]);
+    }
+
+    #[test]
+    fn tab() {
+        let source = "description(\"test\");\nif true {\n\tsetenv(\"A\", \"1\");\n}\n";
+        assert_eq!(
+            true,
+            lint(source).contains(&(3, String::from("contains a tab, rhai scripts can only use spaces")))
+        );
+    }
+
+    #[test]
+    fn deprecated_date() {
+        let source = "description(\"test\");\ndeprecated(\"31-12-2020\");\n";
+        assert_eq!(
+            lint(source),
+            vec![(2, String::from("deprecated(\"31-12-2020\") is not a YYYY-MM-DD date"))]
+        );
+
+        let source = "description(\"test\");\ndeprecated(\"2020-12-31\");\n";
+        assert_eq!(lint(source), vec![]);
+    }
+
+    #[test]
+    fn missing_description() {
+        let source = "setenv(\"A\", \"1\");\n";
+        assert_eq!(lint(source), vec![(0, String::from("has no description()"))]);
+    }
+}
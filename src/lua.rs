@@ -0,0 +1,377 @@
+/*
+MIT License
+
+Copyright (c) 2017 Frederik Delaere
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+// Lmod modulefiles, these are lua scripts with a .lua extension. The Lmod
+// functions (setenv, prepend_path, depends_on, ...) are passed on to the
+// functions that the rhai modulefiles use, like the tcl modulefiles do.
+
+use super::script::Call;
+use mlua::{FromLua, Lua, LuaOptions, StdLib, Value, Variadic};
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+pub fn is_modulefile(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "lua")
+}
+
+// the arguments of setenv("VAR", "value") and setenv{"VAR", "value"}
+fn get_strings(lua: &Lua, args: Variadic<Value>) -> mlua::Result<Vec<String>> {
+    let args: Vec<Value> = match args.first() {
+        Some(Value::Table(table)) => table.clone().sequence_values().collect::<mlua::Result<_>>()?,
+        _ => args.into_iter().collect(),
+    };
+
+    // numbers are converted to strings
+    args.into_iter()
+        .filter(|arg| !arg.is_nil())
+        .map(|arg| String::from_lua(arg, lua))
+        .collect()
+}
+
+// the delimiter of prepend_path{"PATH", "/opt/bin", delim=";"}
+fn get_delimiter(args: &Variadic<Value>, position: usize) -> mlua::Result<String> {
+    let delimiter = match args.first() {
+        Some(Value::Table(table)) => table.get::<_, Option<String>>("delim")?,
+        _ => match args.get(position) {
+            Some(Value::String(delimiter)) => Some(delimiter.to_str()?.to_string()),
+            _ => None,
+        },
+    };
+
+    Ok(delimiter.unwrap_or_else(|| String::from(":")))
+}
+
+// the cache is made on worker threads and module lint checks the modulefiles of
+// somebody else, so only loading or unloading a module can use io and os,
+// os.getenv is the only function of os that is still there
+fn restricted_lua() -> mlua::Result<Lua> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+
+    {
+        let globals = lua.globals();
+        globals.set("dofile", Value::Nil)?;
+        globals.set("loadfile", Value::Nil)?;
+
+        let os = lua.create_table()?;
+        os.set("getenv", lua.create_function(|_, var: String| Ok(env::var(var).ok()))?)?;
+        globals.set("os", os)?;
+    }
+
+    Ok(lua)
+}
+
+// evaluates an Lmod modulefile for action: load, unload, info or description
+pub fn run(path: &Path, action: &str, call: &mut Call) -> Result<(), String> {
+    let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+    run_script(&contents, path, action, call)
+}
+
+fn run_script(contents: &str, path: &Path, action: &str, call: &mut Call) -> Result<(), String> {
+    // what mode() returns in Lmod
+    let mode = match action {
+        "load" | "unload" => action,
+        "description" => "whatis",
+        _ => "show",
+    };
+
+    // name/version.lua
+    let version = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = path
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let full_name = format!("{}/{}", name, version);
+    let filename = path.to_string_lossy().into_owned();
+
+    let call = RefCell::new(call);
+    let forward = |function: &str, args: &[String]| -> String { (call.borrow_mut())(function, args) };
+
+    let lua = if mode == "load" || mode == "unload" {
+        Lua::new()
+    } else {
+        restricted_lua().map_err(|e| e.to_string())?
+    };
+    let result = lua.scope(|scope| {
+        let globals = lua.globals();
+
+        // functions that pass all their arguments on, one at a time
+        for (lmod, function) in &[
+            ("load", "load"),
+            ("try_load", "load"),
+            ("always_load", "load"),
+            ("depends_on", "load"),
+            ("unload", "unload"),
+            ("conflict", "conflict"),
+        ] {
+            let forward = &forward;
+            globals.set(
+                *lmod,
+                scope.create_function(move |lua, args: Variadic<Value>| {
+                    for module in get_strings(lua, args)? {
+                        forward(function, &[module]);
+                    }
+                    Ok(())
+                })?,
+            )?;
+        }
+
+        // functions with a fixed number of arguments
+        for (lmod, function, count) in &[
+            ("setenv", "setenv", 2),
+            ("pushenv", "setenv", 2),
+            ("unsetenv", "unsetenv", 1),
+            ("set_alias", "set_alias", 2),
+            ("family", "family", 1),
+            ("whatis", "description", 1),
+            ("source_sh", "source", 2),
+        ] {
+            let forward = &forward;
+            globals.set(
+                *lmod,
+                scope.create_function(move |lua, args: Variadic<Value>| {
+                    let args = get_strings(lua, args)?;
+                    if args.len() < *count {
+                        return Err(mlua::Error::RuntimeError(format!("{} needs {} arguments", lmod, count)));
+                    }
+                    forward(function, &args[..*count]);
+                    Ok(())
+                })?,
+            )?;
+        }
+
+        // the values can be lists, separated by the delimiter
+        for function in &["prepend_path", "append_path", "remove_path"] {
+            let forward = &forward;
+            globals.set(
+                *function,
+                scope.create_function(move |lua, args: Variadic<Value>| {
+                    let delimiter = get_delimiter(&args, 2)?;
+                    let args = get_strings(lua, args)?;
+                    if args.len() < 2 {
+                        return Err(mlua::Error::RuntimeError(format!("{} needs 2 arguments", function)));
+                    }
+
+                    let mut values: Vec<&str> = args[1].split(delimiter.as_str()).filter(|v| !v.is_empty()).collect();
+                    // every prepend_path call puts its value in front
+                    if *function == "prepend_path" {
+                        values.reverse();
+                    }
+                    for value in values {
+                        forward(function, &[args[0].to_string(), value.to_string()]);
+                    }
+                    Ok(())
+                })?,
+            )?;
+        }
+
+        globals.set(
+            "help",
+            scope.create_function(|lua, args: Variadic<Value>| {
+                // module info shows the help text
+                if mode == "show" {
+                    for text in get_strings(lua, args)? {
+                        for line in text.lines() {
+                            forward("description", &[line.to_string()]);
+                        }
+                    }
+                }
+                Ok(())
+            })?,
+        )?;
+
+        let is_loaded = scope.create_function(|_, module: String| Ok(forward("is_loaded", &[module]) == "1"))?;
+        globals.set("isloaded", is_loaded.clone())?;
+        globals.set("is_loaded", is_loaded)?;
+
+        // print goes to stdout, where the shell expects commands
+        let message = scope.create_function(|lua, args: Variadic<Value>| {
+            forward("print", &[get_strings(lua, args)?.concat()]);
+            Ok(())
+        })?;
+        globals.set("print", message.clone())?;
+        globals.set("LmodMessage", message.clone())?;
+        globals.set("LmodWarning", message)?;
+        globals.set(
+            "LmodError",
+            lua.create_function(|lua, args: Variadic<Value>| -> mlua::Result<()> {
+                Err(mlua::Error::RuntimeError(get_strings(lua, args)?.concat()))
+            })?,
+        )?;
+
+        globals.set("mode", scope.create_function(move |_, ()| Ok(mode))?)?;
+        globals.set("myModuleName", scope.create_function(|_, ()| Ok(name.clone()))?)?;
+        globals.set("myModuleVersion", scope.create_function(|_, ()| Ok(version.clone()))?)?;
+        globals.set("myModuleFullName", scope.create_function(|_, ()| Ok(full_name.clone()))?)?;
+        globals.set("myFileName", scope.create_function(|_, ()| Ok(filename.clone()))?)?;
+        globals.set(
+            "pathJoin",
+            lua.create_function(|lua, args: Variadic<Value>| {
+                let parts: Vec<String> = get_strings(lua, args)?.into_iter().filter(|part| !part.is_empty()).collect();
+                Ok(parts.join("/").replace("//", "/"))
+            })?,
+        )?;
+
+        // these don't change anything we care about
+        for function in &["add_property", "remove_property", "execute", "extensions"] {
+            globals.set(*function, lua.create_function(|_, _: Variadic<Value>| Ok(()))?)?;
+        }
+
+        lua.load(contents)
+            .set_name(format!("={}", path.file_name().unwrap_or_default().to_string_lossy()))
+            .exec()
+    });
+
+    result.map_err(|e| match e {
+        mlua::Error::RuntimeError(msg) | mlua::Error::SyntaxError { message: msg, .. } => msg,
+        mlua::Error::CallbackError { cause, .. } => cause.to_string(),
+        e => e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_script;
+    use std::path::Path;
+
+    // the functions a modulefile called, with their arguments
+    fn evaluate(contents: &str, action: &str) -> (Vec<(String, Vec<String>)>, Result<(), String>) {
+        let mut calls: Vec<(String, Vec<String>)> = Vec::new();
+        let mut call = |name: &str, args: &[String]| {
+            calls.push((name.to_string(), args.to_vec()));
+            String::new()
+        };
+        let result = run_script(contents, Path::new("/modules/blast/2.5.0.lua"), action, &mut call);
+
+        (calls, result)
+    }
+
+    fn call(name: &str, args: &[&str]) -> (String, Vec<String>) {
+        (name.to_string(), args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn table_and_positional_arguments() {
+        let source = "setenv(\"A\", \"1\")\nsetenv{\"B\", \"2\"}\nsetenv(\"C\", 3)\nload(\"gcc\", \"zlib\")\n";
+        let (calls, result) = evaluate(source, "load");
+        assert_eq!(Ok(()), result);
+        assert_eq!(
+            calls,
+            vec![
+                call("setenv", &["A", "1"]),
+                call("setenv", &["B", "2"]),
+                call("setenv", &["C", "3"]),
+                call("load", &["gcc"]),
+                call("load", &["zlib"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn paths() {
+        let source = "prepend_path(\"PATH\", \"/a:/b\")\nappend_path(\"PATH\", \"/c:/d\")\n\
+                      prepend_path{\"LIST\", \"e;f\", delim=\";\"}\nappend_path(\"LIST\", \"g,h\", \",\")\n";
+        let (calls, _) = evaluate(source, "load");
+        assert_eq!(
+            calls,
+            vec![
+                call("prepend_path", &["PATH", "/b"]),
+                call("prepend_path", &["PATH", "/a"]),
+                call("append_path", &["PATH", "/c"]),
+                call("append_path", &["PATH", "/d"]),
+                call("prepend_path", &["LIST", "f"]),
+                call("prepend_path", &["LIST", "e"]),
+                call("append_path", &["LIST", "g"]),
+                call("append_path", &["LIST", "h"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn module_name() {
+        let source = "setenv(\"NAME\", myModuleName())\nsetenv(\"VERSION\", myModuleVersion())\n\
+                      setenv(\"FULL\", myModuleFullName())\nsetenv(\"MODE\", mode())\n";
+        let (calls, _) = evaluate(source, "load");
+        assert_eq!(
+            calls,
+            vec![
+                call("setenv", &["NAME", "blast"]),
+                call("setenv", &["VERSION", "2.5.0"]),
+                call("setenv", &["FULL", "blast/2.5.0"]),
+                call("setenv", &["MODE", "load"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn help_in_show_mode() {
+        let source = "whatis(\"Blast\")\nhelp([[first line\nsecond line]])\n";
+        let (calls, _) = evaluate(source, "info");
+        assert_eq!(
+            calls,
+            vec![
+                call("description", &["Blast"]),
+                call("description", &["first line"]),
+                call("description", &["second line"]),
+            ]
+        );
+
+        let (calls, _) = evaluate(source, "load");
+        assert_eq!(calls, vec![call("description", &["Blast"])]);
+    }
+
+    #[test]
+    fn restricted_stdlib() {
+        let source = "setenv(\"IO\", tostring(io ~= nil))\nsetenv(\"EXECUTE\", tostring(os.execute ~= nil))\n\
+                      setenv(\"GETENV\", tostring(os.getenv ~= nil))\n";
+        let (calls, _) = evaluate(source, "info");
+        assert_eq!(
+            calls,
+            vec![
+                call("setenv", &["IO", "false"]),
+                call("setenv", &["EXECUTE", "false"]),
+                call("setenv", &["GETENV", "true"]),
+            ]
+        );
+
+        let (calls, _) = evaluate(source, "load");
+        assert_eq!(
+            calls,
+            vec![
+                call("setenv", &["IO", "true"]),
+                call("setenv", &["EXECUTE", "true"]),
+                call("setenv", &["GETENV", "true"]),
+            ]
+        );
+    }
+}
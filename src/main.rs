@@ -51,6 +51,7 @@ extern crate glob;
 extern crate gumdrop;
 extern crate gumdrop_derive;
 extern crate is_executable;
+extern crate libc;
 extern crate mdcat;
 extern crate pbr;
 extern crate pulldown_cmark;
@@ -61,10 +62,11 @@ extern crate syntect;
 use ansi_term::Style;
 use std::collections::HashMap;
 use std::env;
-use std::fs::{remove_file, File};
-use std::io::Write;
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 use std::sync::{Arc, Mutex};
 
 lazy_static! {
@@ -78,7 +80,6 @@ static CRASH_FAILED_TO_CREATE_TEMPORARY_FILE: i32 = 2;
 static CRASH_FAILED_TO_WRITE_TO_TEMPORARY_FILE: i32 = 3;
 static CRASH_NO_CACHE_FILES_FOUND: i32 = 4;
 static CRASH_MODULE_NOT_FOUND: i32 = 5;
-static CRASH_COULDNT_OPEN_CACHE_FILE: i32 = 5;
 //static CRASH_NO_ARGS: i32 = 6;
 static CRASH_MODULEPATH_IS_FILE: i32 = 7;
 static CRASH_CANNOT_ADD_TO_ENV: i32 = 8;
@@ -87,6 +88,9 @@ static CRASH_GET_SHELL: i32 = 10;
 static CRASH_CREATE_ERROR: i32 = 11;
 static CRASH_INVALID_REGEX: i32 = 12;
 
+// the exit code when there was no crash, module lint fails when it found problems
+static EXIT_CODE: AtomicI32 = AtomicI32::new(0);
+
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
 
@@ -95,7 +99,19 @@ fn is_shell_supported(shell: &str) -> bool {
     // to stdout instead of the temp file
     // noshell is also useful for debugging purposes
 
-    let shell_list = vec!["tcsh", "csh", "bash", "zsh", "noshell", "python", "perl", "progressbar", "r"];
+    let shell_list = vec![
+        "tcsh",
+        "csh",
+        "bash",
+        "zsh",
+        "fish",
+        "noshell",
+        "json",
+        "python",
+        "perl",
+        "progressbar",
+        "r",
+    ];
 
     if shell_list.contains(&shell) {
         return true;
@@ -184,6 +200,25 @@ fn usage(in_eval: bool, subcommand_help: bool) {
             regular expression.",
     );
 
+    sch.insert(
+        "spider".to_owned(),
+        "spider [--regex] [search string]\t
+            Searches the module names, the descriptions, the variables
+            the modules set and the binaries they provide.
+            module keyword does the same.
+
+            When --regex or -r is specified the search term can be a
+            regular expression.",
+    );
+
+    sch.insert(
+        "which".to_owned(),
+        "which [executable(s)]\t
+            Lists the modules that provide an executable.
+            The name of the executable can contain wildcards,
+            eg: module which 'samtools*'",
+    );
+
     sch.insert(
         "info".to_owned(),
         "info [(partial) module name(s)]\t
@@ -194,21 +229,37 @@ fn usage(in_eval: bool, subcommand_help: bool) {
 
     sch.insert(
         "undo".to_owned(),
-        "undo\t
-            Undo the previous module command, only works for load, unload,
-            switch and purge.",
+        "undo [number]\t
+            Undo the previous module command, or the given number of
+            previous commands. Works for load, unload, switch, purge,
+            refresh, refurbish and restore.",
+    );
+
+    sch.insert(
+        "redo".to_owned(),
+        "redo [number]\t
+            Redo the previously undone module command, or the given
+            number of undone commands.",
+    );
+
+    sch.insert(
+        "history".to_owned(),
+        "history\t
+            Lists the previous module commands of this session.",
     );
 
     sch.insert(
         "cache".to_owned(),
-        "cache [--help] [make|add|edit|delete]\t
+        "cache [--help] [make|add|edit|delete|verify]\t
             Manipulate the contents of a .modulecache file.
 
-            make\t
+            make [--full]\t
 
             Updates the .modulecache file in all the paths that
             are found in the $MODULEPATH variable. This will only
             work if you have the correct permissions.
+            Only new or changed modulefiles are parsed again, use
+            --full to parse all the modulefiles.
             If you want a progress bar use the command:
             update_modules_cache instead of module cache make
 
@@ -225,6 +276,20 @@ fn usage(in_eval: bool, subcommand_help: bool) {
 
             Updating deprecated and description only updates this values in the cache
             not in the modulefile itself.
+
+            delete --modulepath [path] --name [modulename] \t
+
+            Removes a module from the .modulecache file
+            This will only work if you have the correct permissions.
+
+            verify [--fix] \t
+
+            Checks if the .modulecache files in $MODULEPATH still agree
+            with the modulefiles: modules without a modulefile, modulefiles
+            that are missing from the cache, default modules that differ
+            from the .version files and changed descriptions.
+            You are asked if these problems should be fixed, with --fix
+            they are fixed without asking.
             ",
     );
 /*
@@ -265,6 +330,49 @@ fn usage(in_eval: bool, subcommand_help: bool) {
             Manages the autoloading of modules when opening a new terminal.",
     );
 
+    sch.insert(
+        "save".to_owned(),
+        "save [name]\t
+            Saves the loaded modules in a collection, when no name
+            is given the collection is called default.",
+    );
+
+    sch.insert(
+        "restore".to_owned(),
+        "restore [name]\t
+            Unloads all loaded modules and loads the modules of a
+            collection, when no name is given the collection called
+            default is restored.",
+    );
+
+    sch.insert(
+        "savelist".to_owned(),
+        "savelist\t
+            Lists all the saved collections.",
+    );
+
+    sch.insert(
+        "disable".to_owned(),
+        "disable [name]\t
+            Disables a collection, the file is kept in
+            ~/.rsmodules/collections/ with a ~ appended to its name.",
+    );
+
+    sch.insert(
+        "use".to_owned(),
+        "use [-a] [-p] [folder(s)]\t
+            Adds folders with modulefiles to $MODULEPATH, with -a they
+            are appended instead of prepended. With -p $MODULEPATH is
+            also changed in the setup_rsmodules files.",
+    );
+
+    sch.insert(
+        "unuse".to_owned(),
+        "unuse [-p] [folder(s)]\t
+            Removes folders from $MODULEPATH. With -p $MODULEPATH is
+            also changed in the setup_rsmodules files.",
+    );
+
     sch.insert(
         "readme".to_owned(),
         "readme [(partial)modulename]\t
@@ -288,6 +396,37 @@ fn usage(in_eval: bool, subcommand_help: bool) {
             present in vi -e.",
     );
 
+    sch.insert(
+        "lint".to_owned(),
+        "lint [file(s), folder(s) or module name(s)]\t
+            Checks modulefiles for syntax errors, unknown functions,
+            tabs, deprecated() dates that aren't YYYY-MM-DD, paths that
+            don't exist, a missing description() and loads of modules
+            that are in no cache. Without arguments all the modulefiles
+            in $MODULEPATH are checked. Exits with 1 when problems are found.",
+    );
+
+    sch.insert(
+        "test".to_owned(),
+        "test [(partial)modulename] [expected result file]\t
+            Loads and unloads a module in a clean environment, without
+            changing your shell. Shows the variables, aliases, commands,
+            sourced files and modules it loads, and fails when the unload
+            doesn't restore the environment or when the result differs
+            from the expected result file. The output can be saved as
+            the expected result file.",
+    );
+
+    sch.insert(
+        "preview".to_owned(),
+        "preview [(partial) module name(s)]\t
+            Shows what loading the module(s) would change: the modules
+            that are loaded, unloaded or replaced, the variables, the
+            aliases, the sourced files and the commands. Your shell isn't
+            changed. This is the same as module load --dry-run, --dry-run
+            also works with unload, switch and purge.",
+    );
+
     let long_help: &str = &format!(
         "
 
@@ -344,6 +483,32 @@ fn usage(in_eval: bool, subcommand_help: bool) {
 
         * {}
 
+        * {}
+
+        * {}
+
+        * {}
+
+        * {}
+
+        * {}
+
+        * {}
+
+        * {}
+
+        * {}
+
+        * {}
+
+        * {}
+
+        * {}
+
+        * {}
+
+        * {}
+
         * {}
         ",
         help!(sch, "load"),
@@ -354,17 +519,30 @@ fn usage(in_eval: bool, subcommand_help: bool) {
         help!(sch, "refurbish"),
         help!(sch, "refresh"),
         help!(sch, "available"),
+        help!(sch, "spider"),
+        help!(sch, "which"),
         help!(sch, "info"),
         help!(sch, "undo"),
+        help!(sch, "redo"),
+        help!(sch, "history"),
         help!(sch, "cache"),
         //help!(sch, "addtocache"),
         //help!(sch, "makecache"),
         help!(sch, "create"),
         help!(sch, "delete"),
         help!(sch, "autoload"),
+        help!(sch, "save"),
+        help!(sch, "restore"),
+        help!(sch, "savelist"),
+        help!(sch, "disable"),
+        help!(sch, "use"),
+        help!(sch, "unuse"),
         help!(sch, "readme"),
         help!(sch, "cd"),
-        help!(sch, "edit")
+        help!(sch, "edit"),
+        help!(sch, "lint"),
+        help!(sch, "test"),
+        help!(sch, "preview")
     );
 
     let error_msg: &str;
@@ -394,26 +572,29 @@ fn usage(in_eval: bool, subcommand_help: bool) {
              //<load|unload|list|switch|purge|refurbish|refresh|available|undo|info|addtocache|makecache|delete|autoload|readme|cd|edit> [module \
         error_msg =
             "  Usage: module \
-             <load|unload|list|switch|purge|refurbish|refresh|available|undo|info|delete|autoload|readme|cd|edit> [module \
+             <load|unload|list|switch|purge|refurbish|refresh|available|spider|which|undo|redo|history|info|delete|autoload|save|restore|savelist|disable|use|unuse|readme|cd|edit|lint|test|preview> [module \
              name]";
     } else {
              //<load|unload|list|switch|purge|refurbish|refresh|available|undo|info|addtocache|makecache|delete|autoload|readme|cd|edit> [module \
         error_msg =
             "  Usage: rsmodules <shell> \
-             <load|unload|list|switch|purge|refurbish|refresh|available|undo|info|delete|autoload|readme|cd|edit> [module \
+             <load|unload|list|switch|purge|refurbish|refresh|available|spider|which|undo|redo|history|info|delete|autoload|save|restore|savelist|disable|use|unuse|readme|cd|edit|lint|test|preview> [module \
              name]";
     }
 
     eprintln!("{}", &error_msg);
     if !in_eval {
         eprintln!(
-            "  Supported shells: bash, zsh, csh, tcsh, python, perl and \
+            "  Supported shells: bash, zsh, fish, csh, tcsh, python, perl and \
              noshell"
         );
         eprintln!("");
         eprintln!("  When noshell is selected all output is printed to stdout,");
         eprintln!("  module available will then print a nice list without gaps,");
         eprintln!("  which makes your life easier when you want to parse this output.");
+        eprintln!("");
+        eprintln!("  When json is selected, available, list, info and autoload list");
+        eprintln!("  print a json document to stdout.");
     }
     eprintln!("{}", &long_help);
 }
@@ -426,6 +607,15 @@ fn set_global_tmpfile(tmp_file_path: String) {
 }
 
 fn run(args: &[String]) {
+    // --dry-run can be given before or after the subcommand
+    let dry_run = args.iter().skip(2).any(|arg| arg == "--dry-run");
+    let args: Vec<String> = args
+        .iter()
+        .enumerate()
+        .filter(|(i, arg)| *i < 2 || *arg != "--dry-run")
+        .map(|(_, arg)| arg.clone())
+        .collect();
+
     let command: &str;
     let tmp: String;
     let mut modulename: &str = "";
@@ -441,74 +631,6 @@ fn run(args: &[String]) {
 
     let modulepaths = rsmod::get_module_paths(false);
 
-    // create temporary file in the home folder
-    // if the file cannot be created try to create it
-    // in /tmp, if that fails, the program exits
-    //
-    // ~/.rsmodulestmpXXXXXXXX
-    // /tmp/.rsmodulestmpXXXXXXXX
-
-    let mut tmpfile: File;
-
-    let rstr: String = rand::thread_rng().gen_ascii_chars().take(8).collect();
-
-    let mut tmp_file_path: PathBuf;
-
-    match dirs::home_dir() {
-        Some(path) => tmp_file_path = path,
-        None => {
-            show_warning!(
-                "We were unable to find your home directory, checking if /tmp is an \
-                 option"
-            );
-
-            // this is wrong, as we try to use temp again a bit later
-            tmp_file_path = env::temp_dir();
-            // return;
-        }
-    };
-
-    let filename: String = format!(".rsmodulestmp{}", rstr);
-    let filename: &str = filename.as_ref();
-    tmp_file_path.push(filename);
-
-    match File::create(&tmp_file_path) {
-        Ok(file) => {
-            tmpfile = file;
-            set_global_tmpfile(tmp_file_path.to_str().unwrap().to_string());
-        }
-        Err(_) => {
-            // home exists but we can't create the temp file in it or
-            // worst case, /tmp exists but we can't create the temp file in it
-            tmp_file_path = env::temp_dir();
-            let filename: String = format!(".rsmodulestmp{}", rstr);
-            let filename: &str = filename.as_ref();
-            tmp_file_path.push(filename);
-
-            match File::create(&tmp_file_path) {
-                Ok(newfile) => {
-                    tmpfile = newfile;
-                    set_global_tmpfile(tmp_file_path.to_str().unwrap().to_string());
-                }
-                Err(e) => {
-                    rsmod::crash(
-                        CRASH_FAILED_TO_CREATE_TEMPORARY_FILE,
-                        &format!("Failed to create temporary file: {}", e),
-                    );
-                    return;
-                }
-            };
-        }
-    };
-
-    panic::set_hook(Box::new(|_| {
-        let tmp = lu!(TMPFILE_PATH);
-        let tmp_file_path = &*tmp;
-        remove_file(tmp_file_path).unwrap();
-    }));
-
-    let filename = tmp_file_path.to_str().unwrap().to_string();
-
     let mut quoted_string: String;
     let mut command_hit: &str = "";
     if args.len() >= 3 {
@@ -536,7 +658,11 @@ fn run(args: &[String]) {
         command_list.push("unload");
         command_list.push("rm");
         command_list.push("available");
+        command_list.push("spider");
+        command_list.push("keyword");
+        command_list.push("which");
         command_list.push("list");
+        command_list.push("li");
         command_list.push("purge");
         command_list.push("refurbish");
         command_list.push("refresh");
@@ -549,19 +675,27 @@ fn run(args: &[String]) {
         //command_list.push("makecache");
         command_list.push("help");
         command_list.push("undo");
+        command_list.push("redo");
+        command_list.push("history");
         command_list.push("autoload");
         command_list.push("readme");
         command_list.push("delete");
         command_list.push("create");
         command_list.push("cd");
         command_list.push("edit");
+        command_list.push("save");
+        command_list.push("restore");
+        command_list.push("savelist");
+        command_list.push("disable");
+        command_list.push("use");
+        command_list.push("unuse");
+        command_list.push("lint");
+        command_list.push("test");
+        command_list.push("preview");
         command_list.push("--help");
         command_list.push("-h");
         // TODO
         // "create" -> wizard to create a new mdoule
-        // "addmodulepath" -> wizard to add a path to $MODULEPATH
-        // "removemodulepath" -> wizard to remove a path from $MODULEPATH
-        //  ask to update /etc/profile.d or bashrc or personal_cshrc
         // "delete" -> deletes a modulefile
         // "update" -> when you have blast/12.3 as module
         //  module update blast 13.3 or module update blast/12.3 13.3
@@ -578,6 +712,12 @@ fn run(args: &[String]) {
         let mut num_hits: i32 = 0;
 
         for cmd in command_list {
+            // save is also the start of savelist
+            if cmd == command {
+                num_hits = 1;
+                command_hit = cmd;
+                break;
+            }
             if cmd.starts_with(command) {
                 num_hits += 1;
                 command_hit = cmd;
@@ -619,19 +759,22 @@ fn run(args: &[String]) {
             if command_hit == "display" || command_hit == "show" {
                 command_hit = "info";
             }
+            if command_hit == "keyword" {
+                command_hit = "spider";
+            }
+            // li is short for list, not for lint
+            if command_hit == "li" {
+                command_hit = "list";
+            }
+            // module preview blast is module load --dry-run blast
+            let dry_run = dry_run || command_hit == "preview";
+            if command_hit == "preview" {
+                command_hit = "load";
+            }
 
-            if command_hit == "load" || command_hit == "unload" {
-                // undo doesn't work for dependency loaded modules
-                let data = setenv(
-                    "RSMODULES_UNDO",
-                    &format!("{} {}", command_hit, modulename.to_string()),
-                    &shell,
-                );
-                crash_cleanup_if_err!(
-                    CRASH_FAILED_TO_WRITE_TO_TEMPORARY_FILE,
-                    tmpfile.write_all(data.as_bytes()),
-                    filename
-                );
+            if dry_run && !rsmod::preview::COMMANDS.contains(&command_hit) {
+                usage(true, false);
+                return;
             }
 
             if (command_hit == "load"
@@ -639,7 +782,10 @@ fn run(args: &[String]) {
                 || command_hit == "info"
                 || command_hit == "delete"
                 || command_hit == "readme"
-                || command_hit == "edit")
+                || command_hit == "edit"
+                || command_hit == "use"
+                || command_hit == "unuse"
+                || command_hit == "test")
                 && args.len() == 3
             {
                 usage(true, true);
@@ -651,35 +797,6 @@ fn run(args: &[String]) {
                 return;
             }
 
-            if command_hit == "switch" {
-                modulenames.reverse();
-                let data = setenv(
-                    "RSMODULES_UNDO",
-                    &format!("{} {}", command_hit, modulenames.join(" ")),
-                    &shell,
-                );
-                crash_cleanup_if_err!(
-                    CRASH_FAILED_TO_WRITE_TO_TEMPORARY_FILE,
-                    tmpfile.write_all(data.as_bytes()),
-                    filename
-                );
-            }
-
-            if command_hit == "purge" {
-                let loaded_list = rsmod::get_loaded_list();
-                let mut args: Vec<String> = Vec::new();
-                for (argument, _, _) in loaded_list {
-                    args.push(argument);
-                }
-                let loadedmodules = args.join(" ");
-                let data = setenv("RSMODULES_UNDO", &format!("unload {}", loadedmodules), &shell);
-                crash_cleanup_if_err!(
-                    CRASH_FAILED_TO_WRITE_TO_TEMPORARY_FILE,
-                    tmpfile.write_all(data.as_bytes()),
-                    filename
-                );
-            }
-
             let mut rsmod_command: Rsmodule = Rsmodule {
                 cmd: command_hit,
                 typed_command: command,
@@ -688,8 +805,19 @@ fn run(args: &[String]) {
                 shell: &shell,
                 //shell_width: shell_width,
                 shell_width,
+                dry_run: None,
             };
-            rsmod::command(&mut rsmod_command);
+
+            if dry_run {
+                rsmod::preview::run(&mut rsmod_command);
+            } else {
+                // remember what was loaded, so this command can be undone
+                let before = rsmod::history::State::current();
+                rsmod::command(&mut rsmod_command);
+                if rsmod::history::is_undoable(command_hit) {
+                    rsmod::history::add(&format!("{} {}", command_hit, modulename), before, &shell);
+                }
+            }
         }
 
         if !matches {
@@ -701,60 +829,234 @@ fn run(args: &[String]) {
     // this is used for scripts that want to parse the module av output
     // for example for tab completion
 
-    if shell != "noshell" && shell != "python" && shell != "perl" && shell != "progressbar" && shell != "r" {
-        // we want a self destructing tmpfile
-        // so it must delete itself at the end of the run
-        // if it crashes it will be deleted after the source stuff
-        // if the code that writes the file crashes it should clean up
+    if !rsmod::is_eval_shell(&shell) {
+        return;
+    }
+
+    let mut output_buffer = lu!(OUTPUT_BUFFER);
+    let output_buffer = &mut (*output_buffer);
 
-        let cmd = format!("\\rm -f {}\n", tmp_file_path.display());
+    // the generated code is printed for eval, unless the old behaviour
+    // is wanted: a file that is sourced and removes itself
+    // csh and tcsh always get the file, their eval `...` runs the output as
+    // one unquoted line, which globs and splits the values and the messages
+    let tmpfile = if env::var(rsmod::ENV_TMPFILE).is_ok() || shell == "csh" || shell == "tcsh" {
+        Some(create_tmpfile())
+    } else {
+        None
+    };
 
-        let mut output_buffer = lu!(OUTPUT_BUFFER);
-        let output_buffer = &mut (*output_buffer);
-        output_buffer.push(cmd);
+    match tmpfile {
+        Some((mut tmpfile, tmp_file_path)) => {
+            // we want a self destructing tmpfile
+            // so it must delete itself at the end of the run
+            // if it crashes it will be deleted after the source stuff
+            // if the code that writes the file crashes it should clean up
+            let filename = tmp_file_path.to_str().unwrap().to_string();
+            let quoted_filename = quote(&tmp_file_path.to_string_lossy(), &shell);
+
+            // fish doesn't know the \ prefix to bypass aliases, it uses 'command'
+            let cmd = if shell == "fish" {
+                format!("command rm -f {}\n", quoted_filename)
+            } else {
+                format!("\\rm -f {}\n", quoted_filename)
+            };
+            output_buffer.push(cmd);
 
-        for line in output_buffer {
-            crash_cleanup_if_err!(
-                CRASH_FAILED_TO_WRITE_TO_TEMPORARY_FILE,
-                tmpfile.write_all(line.as_bytes()),
-                filename
-            );
+            for line in output_buffer {
+                crash_cleanup_if_err!(
+                    CRASH_FAILED_TO_WRITE_TO_TEMPORARY_FILE,
+                    tmpfile.write_all(line.as_bytes()),
+                    filename
+                );
+            }
+
+            // source tmpfile
+            println!("source {}", quoted_filename);
+            // doesn't this make more sense than creating a
+            // self destructing file ?
+            println!("rm -f {}", quoted_filename);
         }
+        None => {
+            for line in output_buffer {
+                print!("{}", line);
+            }
+        }
+    }
+}
 
-        // source tmpfile
-        println!("source {}", tmp_file_path.display());
-        // doesn't this make more sense than creating a
-        // self destructing file ?
-        println!("rm -f {}", tmp_file_path.display());
+// creates ~/.rsmodulestmpXXXXXXXX, or /tmp/.rsmodulestmpXXXXXXXX when that fails,
+// only we can read it and an existing file is never reused
+fn create_tmpfile() -> (File, PathBuf) {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    match dirs::home_dir() {
+        Some(path) => dirs.push(path),
+        None => show_warning!(
+            "We were unable to find your home directory, checking if /tmp is an \
+             option"
+        ),
+    };
+    dirs.push(env::temp_dir());
+
+    let mut error = String::new();
+    for dir in &dirs {
+        // try again with another name when the file already exists
+        for _ in 0..10 {
+            let rstr: String = rand::thread_rng().gen_ascii_chars().take(8).collect();
+            let tmp_file_path = dir.join(format!(".rsmodulestmp{}", rstr));
+
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&tmp_file_path)
+            {
+                Ok(file) => {
+                    set_global_tmpfile(tmp_file_path.to_str().unwrap().to_string());
+
+                    panic::set_hook(Box::new(|_| {
+                        let tmp = lu!(TMPFILE_PATH);
+                        let tmp_file_path = &*tmp;
+                        remove_file(tmp_file_path).unwrap();
+                    }));
+
+                    return (file, tmp_file_path);
+                }
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    error = e.to_string();
+                    break;
+                }
+            }
+        }
+    }
+
+    rsmod::crash(
+        CRASH_FAILED_TO_CREATE_TEMPORARY_FILE,
+        &format!("Failed to create temporary file: {}", error),
+    );
+    unreachable!();
+}
+
+// returns value as a string literal for the shell, quotes included, so
+// the shell doesn't expand or run anything in it
+pub fn quote(value: &str, shell: &str) -> String {
+    if shell == "bash" || shell == "zsh" {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    } else if shell == "tcsh" || shell == "csh" {
+        // csh also does history substitution and doesn't allow a newline in single quotes
+        format!("'{}'", value.replace('\'', "'\\''").replace('!', "\\!").replace('\n', "\\\n"))
+    } else if shell == "fish" || shell == "perl" {
+        format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+    } else if shell == "python" || shell == "r" {
+        format!(
+            "\"{}\"",
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+                .replace('\r', "\\r")
+        )
     } else {
-        remove_file(tmp_file_path.to_str().unwrap().to_string()).unwrap();
+        value.to_string()
     }
 }
 
+// variable and alias names are written without quotes, so only these are allowed
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+// the code that sets a variable, with a newline at the end
+// nothing is returned for a name that isn't valid
 pub fn setenv(var: &str, val: &str, shell: &str) -> String {
     let mut data: String = String::new();
+    if !is_valid_name(var) {
+        return data;
+    }
+
+    let val = quote(val, shell);
     if shell == "bash" || shell == "zsh" {
-        data = format!("export {}=\"{}\"\n", var, val);
+        data = format!("export {}={}\n", var, val);
     } else if shell == "tcsh" || shell == "csh" {
-        data = format!("setenv {} \"{}\"\n", var, val);
+        data = format!("setenv {} {}\n", var, val);
+    } else if shell == "fish" {
+        data = format!("set -gx {} {};\n", var, val);
     } else if shell == "python" {
-        data = format!("os.environ[\"{}\"] = \"{}\";\n", var, val);
+        data = format!("os.environ[\"{}\"] = {};\n", var, val);
     } else if shell == "r" {
-        data = format!(
-            "old_path <- Sys.getenv(\"{}\")
-
-        Sys.setenv({} = paste(old_path, \"{}\", sep = \":\"))",
-            var, var, val
-        );
+        data = format!("Sys.setenv({} = {})\n", var, val);
     } else if shell == "perl" {
-        data = format!("$ENV{{{}}}=\"{}\";\n", var, val);
+        data = format!("$ENV{{{}}}={};\n", var, val);
     }
 
     data
 }
 
+// the code that removes a variable, like the other commands of a modulefile
+// it has no newline at the end
+pub fn unsetenv(var: &str, shell: &str) -> String {
+    if !is_valid_name(var) {
+        String::new()
+    } else if shell == "bash" || shell == "zsh" {
+        format!("unset {}", quote(var, shell))
+    } else if shell == "fish" {
+        format!("set -e {}", var)
+    } else if shell == "perl" {
+        format!("delete $ENV{{{}}};", var)
+    } else if shell == "python" {
+        format!("os.environ[\"{}\"] = \"\";\ndel os.environ[\"{}\"];", var, var)
+    } else if shell == "r" {
+        format!("Sys.unsetenv({})", quote(var, shell))
+    } else {
+        format!("unsetenv {}", quote(var, "csh"))
+    }
+}
+
+// the code that defines an alias, python and perl don't have aliases
+pub fn alias(name: &str, val: &str, shell: &str) -> Option<String> {
+    if !is_valid_name(name) {
+        None
+    } else if shell == "fish" || shell == "tcsh" || shell == "csh" {
+        Some(format!("alias {} {}", name, quote(val, shell)))
+    } else if shell != "python" && shell != "perl" {
+        Some(format!("alias {}={}", name, quote(val, shell)))
+    } else {
+        None
+    }
+}
+
+// the code that removes an alias, fish aliases are functions
+pub fn unalias(name: &str, shell: &str) -> Option<String> {
+    if !is_valid_name(name) {
+        None
+    } else if shell == "bash" || shell == "zsh" || shell == "tcsh" || shell == "csh" {
+        Some(format!("unalias {}", name))
+    } else if shell == "fish" {
+        Some(format!("functions -e {}", name))
+    } else {
+        None
+    }
+}
+
+// the code that prints a line, without a newline at the end
+pub fn print(line: &str, shell: &str) -> String {
+    if shell == "python" || shell == "r" {
+        format!("print({})", quote(line, shell))
+    } else if shell == "perl" {
+        format!("print({} . \"\\n\");", quote(line, shell))
+    } else {
+        format!("echo {}", quote(line, shell))
+    }
+}
+
 fn bold<'a>(shell: &str, msg: &'a str) -> ansi_term::ANSIGenericString<'a, str> {
     if shell == "noshell"
+        || shell == "json"
         || shell == "perl"
         || shell == "r"
         || shell == "python"
@@ -799,19 +1101,140 @@ fn main() {
     }
 
     run(&args);
+
+    let exit_code = EXIT_CODE.load(Ordering::Relaxed);
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::is_shell_supported;
+    use super::{alias, is_valid_name, print, quote, setenv, unalias, unsetenv};
 
     #[test]
     fn supported_shells() {
         assert_eq!(false, is_shell_supported("randomshellname"));
         assert_eq!(true, is_shell_supported("bash"));
         assert_eq!(true, is_shell_supported("zsh"));
+        assert_eq!(true, is_shell_supported("fish"));
+        assert_eq!(true, is_shell_supported("json"));
         assert_eq!(true, is_shell_supported("tcsh"));
         assert_eq!(true, is_shell_supported("csh"));
         assert_eq!(true, is_shell_supported("r"));
     }
+
+    #[test]
+    fn quoted_values() {
+        let value = "a\"b$c`d!e'f\\g\nh";
+        for (shell, quoted) in &[
+            ("bash", "'a\"b$c`d!e'\\''f\\g\nh'"),
+            ("zsh", "'a\"b$c`d!e'\\''f\\g\nh'"),
+            ("tcsh", "'a\"b$c`d\\!e'\\''f\\g\\\nh'"),
+            ("csh", "'a\"b$c`d\\!e'\\''f\\g\\\nh'"),
+            ("fish", "'a\"b$c`d!e\\'f\\\\g\nh'"),
+            ("perl", "'a\"b$c`d!e\\'f\\\\g\nh'"),
+            ("python", "\"a\\\"b$c`d!e'f\\\\g\\nh\""),
+            ("r", "\"a\\\"b$c`d!e'f\\\\g\\nh\""),
+            ("noshell", value),
+            ("json", value),
+        ] {
+            assert_eq!(*quoted, quote(value, shell), "{}", shell);
+        }
+        assert_eq!("''", quote("", "bash"));
+        assert_eq!("'/usr/bin'", quote("/usr/bin", "fish"));
+    }
+
+    #[test]
+    fn setenv_quotes_values() {
+        assert_eq!("export X='$(id)'\n", setenv("X", "$(id)", "bash"));
+        assert_eq!("export X='$(id)'\n", setenv("X", "$(id)", "zsh"));
+        assert_eq!("setenv X '`id`\\!'\n", setenv("X", "`id`!", "tcsh"));
+        assert_eq!("setenv X '`id`\\!'\n", setenv("X", "`id`!", "csh"));
+        assert_eq!("set -gx X '(id)';\n", setenv("X", "(id)", "fish"));
+        assert_eq!("os.environ[\"X\"] = \"\\\"\";\n", setenv("X", "\"", "python"));
+        assert_eq!("$ENV{X}='@x';\n", setenv("X", "@x", "perl"));
+        assert_eq!("Sys.setenv(X = \"$x\")\n", setenv("X", "$x", "r"));
+    }
+
+    #[test]
+    fn valid_names() {
+        assert_eq!(true, is_valid_name("PATH"));
+        assert_eq!(true, is_valid_name("_x1"));
+        assert_eq!(false, is_valid_name(""));
+        assert_eq!(false, is_valid_name("1X"));
+        assert_eq!(false, is_valid_name("X-Y"));
+        assert_eq!(false, is_valid_name("X;id"));
+        assert_eq!(false, is_valid_name("X Y"));
+
+        for shell in &["bash", "zsh", "tcsh", "csh", "fish", "python", "perl", "r"] {
+            assert_eq!("", setenv("X;id", "1", shell), "{}", shell);
+            assert_eq!("", setenv("$(id)", "1", shell), "{}", shell);
+            assert_eq!("", unsetenv("X\"]; import os; x[\"", shell), "{}", shell);
+            assert_eq!("", unsetenv("X;id", shell), "{}", shell);
+            assert_eq!(None, alias("l;id", "ls", shell), "{}", shell);
+            assert_eq!(None, unalias("l;id", shell), "{}", shell);
+        }
+    }
+
+    #[test]
+    fn unsetenv_for_every_shell() {
+        assert_eq!("unset 'X'", unsetenv("X", "bash"));
+        assert_eq!("unset 'X'", unsetenv("X", "zsh"));
+        assert_eq!("unsetenv 'X'", unsetenv("X", "tcsh"));
+        assert_eq!("unsetenv 'X'", unsetenv("X", "csh"));
+        assert_eq!("set -e X", unsetenv("X", "fish"));
+        assert_eq!("delete $ENV{X};", unsetenv("X", "perl"));
+        assert_eq!("os.environ[\"X\"] = \"\";\ndel os.environ[\"X\"];", unsetenv("X", "python"));
+        assert_eq!("Sys.unsetenv(\"X\")", unsetenv("X", "r"));
+    }
+
+    #[test]
+    fn alias_quotes_values() {
+        let value = "ls $(id) 'x'!";
+        assert_eq!(
+            Some(String::from("alias l='ls $(id) '\\''x'\\''!'")),
+            alias("l", value, "bash")
+        );
+        assert_eq!(
+            Some(String::from("alias l='ls $(id) '\\''x'\\''!'")),
+            alias("l", value, "zsh")
+        );
+        assert_eq!(
+            Some(String::from("alias l 'ls $(id) '\\''x'\\''\\!'")),
+            alias("l", value, "tcsh")
+        );
+        assert_eq!(
+            Some(String::from("alias l 'ls $(id) '\\''x'\\''\\!'")),
+            alias("l", value, "csh")
+        );
+        assert_eq!(Some(String::from("alias l 'ls $(id) \\'x\\'!'")), alias("l", value, "fish"));
+        assert_eq!(None, alias("l", value, "python"));
+        assert_eq!(None, alias("l", value, "perl"));
+    }
+
+    #[test]
+    fn unalias_for_every_shell() {
+        assert_eq!(Some(String::from("unalias l")), unalias("l", "bash"));
+        assert_eq!(Some(String::from("unalias l")), unalias("l", "zsh"));
+        assert_eq!(Some(String::from("unalias l")), unalias("l", "tcsh"));
+        assert_eq!(Some(String::from("unalias l")), unalias("l", "csh"));
+        assert_eq!(Some(String::from("functions -e l")), unalias("l", "fish"));
+        assert_eq!(None, unalias("l", "python"));
+        assert_eq!(None, unalias("l", "perl"));
+    }
+
+    #[test]
+    fn print_quotes_lines() {
+        let line = "it's $HOME!";
+        assert_eq!("echo 'it'\\''s $HOME!'", print(line, "bash"));
+        assert_eq!("echo 'it'\\''s $HOME!'", print(line, "zsh"));
+        assert_eq!("echo 'it'\\''s $HOME\\!'", print(line, "tcsh"));
+        assert_eq!("echo 'it'\\''s $HOME\\!'", print(line, "csh"));
+        assert_eq!("echo 'it\\'s $HOME!'", print(line, "fish"));
+        assert_eq!("print('it\\'s $HOME!' . \"\\n\");", print(line, "perl"));
+        assert_eq!("print(\"it's $HOME!\")", print(line, "python"));
+        assert_eq!("print(\"it's $HOME!\")", print(line, "r"));
+    }
 }
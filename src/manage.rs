@@ -61,7 +61,8 @@ pub fn delete(rsmod: &Rsmodule) {
     let toremove: Vec<&str> = rsmod.arg.split_whitespace().collect();
     for module in &toremove {
         for path in rsmod.search_path.iter() {
-            let filename: &str = &format!("{}/{}", path, module);
+            let modulefile = super::get_modulefile(path, module);
+            let filename: &str = &modulefile.to_string_lossy();
             if Path::new(filename).is_file() {
                 if interactive {
                     if is_yes(&read_input_shell(
@@ -97,7 +98,7 @@ pub fn delete(rsmod: &Rsmodule) {
                 }
             }
         } else {
-            println!(
+            eprintln!(
                 "Removal of {} was succesful. Don't forget to update the module cache.",
                 rsmod.arg
             );
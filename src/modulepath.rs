@@ -0,0 +1,238 @@
+/*
+MIT License
+
+Copyright (c) 2017 Frederik Delaere
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+use super::super::bold;
+use super::super::wizard::{is_yes, read_input_shell, update_modulepath, PathAction};
+use super::{cache, echo, get_module_paths, save_env, update_hierarchy, Rsmodule};
+use std::env;
+use std::path::Path;
+
+extern crate shellexpand;
+
+use gumdrop::Options;
+
+#[derive(Debug, Default, Options)]
+struct UseOptions {
+    #[options(free, help = "Space separated list of folders with modulefiles")]
+    paths: Vec<String>,
+
+    #[options(short = "a", help = "Append the folders to $MODULEPATH instead of prepending them")]
+    append: bool,
+
+    #[options(short = "p", help = "Also change $MODULEPATH in the setup_rsmodules files")]
+    persist: bool,
+
+    #[options(help = "Print this help message")]
+    help: bool,
+}
+
+#[derive(Debug, Default, Options)]
+struct UnuseOptions {
+    #[options(free, help = "Space separated list of folders with modulefiles")]
+    paths: Vec<String>,
+
+    #[options(short = "p", help = "Also change $MODULEPATH in the setup_rsmodules files")]
+    persist: bool,
+
+    #[options(help = "Print this help message")]
+    help: bool,
+}
+
+fn spaces(shell: &str) -> &'static str {
+    if shell == "noshell" || shell == "perl" || shell == "python" || shell == "r" || shell == "json" {
+        ""
+    } else {
+        "  "
+    }
+}
+
+fn message(msg: &str, shell: &str) {
+    if shell != "noshell" {
+        echo("", shell);
+    }
+    echo(&format!("{}{}", spaces(shell), msg), shell);
+    if shell != "noshell" {
+        echo("", shell);
+    }
+}
+
+fn parse_options<T: Options>(rsmod: &Rsmodule, usage: &str) -> Option<T> {
+    let args: Vec<&str> = rsmod.arg.split_whitespace().collect();
+
+    let opts = match T::parse_args_default(&args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}", e);
+            return None;
+        }
+    };
+
+    // without a folder there is nothing to do
+    if opts.help_requested() || args.iter().all(|arg| arg.starts_with('-')) {
+        eprintln!("Usage: {}", usage);
+        eprintln!();
+        eprintln!("{}", T::usage());
+        return None;
+    }
+
+    Some(opts)
+}
+
+// $MODULEPATH contains absolute paths, without a trailing slash
+fn get_absolute_path(path: &str) -> String {
+    let path = shellexpand::tilde(path).to_string();
+    let mut path = if Path::new(&path).is_absolute() {
+        path
+    } else {
+        match env::current_dir() {
+            Ok(dir) => format!("{}/{}", dir.display(), path),
+            Err(_) => path,
+        }
+    };
+
+    while path.len() > 1 && path.ends_with('/') {
+        path.pop();
+    }
+
+    path
+}
+
+// the entries of $MODULEPATH as they are, so a ~ in it is kept
+fn get_raw_module_paths() -> Vec<String> {
+    match env::var("MODULEPATH") {
+        Ok(list) => list
+            .split(':')
+            .filter(|path| !path.is_empty())
+            .map(|path| path.to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn persist(path: &str, action: PathAction, shell: &str) {
+    if update_modulepath(path, action) {
+        message("The setup_rsmodules files are updated.", shell);
+    } else {
+        message(
+            &format!("{}: the setup_rsmodules files could not be updated.", bold(shell, "WARNING")),
+            shell,
+        );
+    }
+}
+
+// offer to index a folder that doesn't contain a cache file yet, we don't ask
+// when a personal index is made anyway or when the shell can't answer
+fn check_cache(path: &str, shell: &str) {
+    let cachefile = cache::cache_file(path);
+    if Path::new(&cachefile).exists() || shell == "noshell" || shell == "json" {
+        return;
+    }
+
+    let interactive = shell != "python" && shell != "perl" && shell != "r";
+    if interactive
+        && !cache::is_personal_cache(path, &cachefile)
+        && is_yes(&read_input_shell(
+            &format!("{} doesn't contain an index, do you want to create it now ? [Y/n]: ", path),
+            shell,
+        ))
+    {
+        cache::update(path, shell);
+    } else {
+        message(
+            &format!(
+                "Don't forget to run {} or {}.",
+                bold(shell, "module cache make"),
+                bold(shell, "update_modules_cache")
+            ),
+            shell,
+        );
+    }
+}
+
+pub fn use_path(rsmod: &mut Rsmodule) {
+    let opts: UseOptions = match parse_options(rsmod, "module use [-a] [-p] <folder(s)>") {
+        Some(opts) => opts,
+        None => return,
+    };
+
+    let previous_search_path = get_module_paths(true);
+    let mut modulepaths = get_raw_module_paths();
+
+    for path in &opts.paths {
+        let path = get_absolute_path(path);
+
+        if !Path::new(&path).is_dir() {
+            message(&format!("{} is not a folder.", bold(rsmod.shell, &path)), rsmod.shell);
+            continue;
+        }
+
+        // a path that is already used is moved to the front or the back
+        modulepaths.retain(|existing| shellexpand::tilde(existing) != path.as_str());
+        if opts.append {
+            modulepaths.push(path.clone());
+        } else {
+            modulepaths.insert(0, path.clone());
+        }
+
+        check_cache(&path, rsmod.shell);
+
+        if opts.persist {
+            let action = if opts.append {
+                PathAction::Append
+            } else {
+                PathAction::Prepend
+            };
+            persist(&path, action, rsmod.shell);
+        }
+    }
+
+    save_env("MODULEPATH", &modulepaths.join(":"), rsmod.shell);
+    update_hierarchy(rsmod, &previous_search_path);
+}
+
+pub fn unuse_path(rsmod: &mut Rsmodule) {
+    let opts: UnuseOptions = match parse_options(rsmod, "module unuse [-p] <folder(s)>") {
+        Some(opts) => opts,
+        None => return,
+    };
+
+    let previous_search_path = get_module_paths(true);
+    let mut modulepaths = get_raw_module_paths();
+
+    for path in &opts.paths {
+        let path = get_absolute_path(path);
+
+        let len = modulepaths.len();
+        modulepaths.retain(|existing| shellexpand::tilde(existing) != path.as_str());
+        if modulepaths.len() == len {
+            message(&format!("{} is not in MODULEPATH.", bold(rsmod.shell, &path)), rsmod.shell);
+        }
+
+        if opts.persist {
+            persist(&path, PathAction::Remove, rsmod.shell);
+        }
+    }
+
+    save_env("MODULEPATH", &modulepaths.join(":"), rsmod.shell);
+    update_hierarchy(rsmod, &previous_search_path);
+}
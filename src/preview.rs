@@ -0,0 +1,254 @@
+/*
+MIT License
+
+Copyright (c) 2017 Frederik Delaere
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+use super::script::{self, Context};
+use super::{command, echo, Rsmodule, ENV_LOADEDMODULES};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::rc::Rc;
+
+// the commands that can be run with --dry-run
+pub static COMMANDS: &[&str] = &["load", "unload", "switch", "purge"];
+
+// variables rsmodules uses for its own bookkeeping start with this
+static BOOKKEEPING_PREFIX: &str = "RSMODULES_";
+
+pub type Environment = BTreeMap<String, String>;
+
+// what a dry run would change, the modulefiles are evaluated without touching
+// the environment and the changes they record are applied to a copy of it
+#[derive(Debug)]
+pub struct Preview {
+    before: Environment,
+    after: Environment,
+    // the aliases, sourced files and commands the shell would get
+    lines: Vec<String>,
+}
+
+impl Preview {
+    pub fn new() -> Preview {
+        let before: Environment = env::vars().collect();
+        Preview {
+            after: before.clone(),
+            before,
+            lines: Vec::new(),
+        }
+    }
+
+    // the changes of a module that would be loaded or unloaded
+    pub fn add(&mut self, context: &Context, module: &str, action: &str) {
+        script::apply_delta(context, &mut self.after);
+
+        let mut loaded: Vec<String> = self
+            .after
+            .get(ENV_LOADEDMODULES)
+            .map(|list| list.as_str())
+            .unwrap_or("")
+            .split(':')
+            .filter(|name| !name.is_empty() && *name != module)
+            .map(|name| name.to_string())
+            .collect();
+        if action == "load" {
+            loaded.insert(0, module.to_string());
+        }
+        self.after.insert(ENV_LOADEDMODULES.to_string(), loaded.join(":"));
+
+        self.lines.extend(script::get_preview(context));
+    }
+
+    fn changes(&self) -> Vec<String> {
+        let mut lines = diff(&self.before, &self.after);
+        lines.extend(self.lines.iter().cloned());
+        lines
+    }
+}
+
+// the loaded modules, the first one was loaded first
+fn get_loaded(environment: &Environment) -> Vec<String> {
+    let loaded = environment.get(ENV_LOADEDMODULES).map(|list| list.as_str()).unwrap_or("");
+    loaded
+        .split(':')
+        .rev()
+        .filter(|module| !module.is_empty())
+        .map(|module| module.to_string())
+        .collect()
+}
+
+fn is_path(var: &str, old: &str, new: &str) -> bool {
+    var.ends_with("PATH") || old.contains(':') || new.contains(':')
+}
+
+// the entries that were added to or removed from a path like variable
+fn diff_path(var: &str, old: &str, new: &str) -> Vec<String> {
+    let old_entries: Vec<&str> = old.split(':').filter(|entry| !entry.is_empty()).collect();
+    let new_entries: Vec<&str> = new.split(':').filter(|entry| !entry.is_empty()).collect();
+
+    let mut lines: Vec<String> = Vec::new();
+    for entry in &new_entries {
+        if !old_entries.contains(entry) {
+            lines.push(format!("+ {} {}", var, entry));
+        }
+    }
+    for entry in &old_entries {
+        if !new_entries.contains(entry) {
+            lines.push(format!("- {} {}", var, entry));
+        }
+    }
+
+    // only the order changed
+    if lines.is_empty() {
+        lines.push(format!("~ {}={} (was {})", var, new, old));
+    }
+
+    lines
+}
+
+fn diff(before: &Environment, after: &Environment) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    let loaded_before = get_loaded(before);
+    let loaded_after = get_loaded(after);
+    for module in &loaded_before {
+        if !loaded_after.contains(module) {
+            lines.push(format!("- module {}", module));
+        }
+    }
+    for module in &loaded_after {
+        if !loaded_before.contains(module) {
+            lines.push(format!("+ module {}", module));
+        }
+    }
+
+    let vars: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    for var in vars {
+        if var == ENV_LOADEDMODULES || var.starts_with(BOOKKEEPING_PREFIX) {
+            continue;
+        }
+        match (before.get(var), after.get(var)) {
+            (Some(old), Some(new)) if old != new => {
+                if is_path(var, old, new) {
+                    lines.append(&mut diff_path(var, old, new));
+                } else {
+                    lines.push(format!("~ {}={} (was {})", var, new, old));
+                }
+            }
+            (None, Some(new)) => lines.push(format!("+ {}={}", var, new)),
+            (Some(old), None) => lines.push(format!("- {} (was {})", var, old)),
+            _ => {}
+        }
+    }
+
+    lines
+}
+
+// runs the command the way it always runs, but shows what would change
+// instead of giving the shell the code that changes it
+pub fn run(rsmod: &mut Rsmodule) {
+    let title = format!("module {} {}", rsmod.cmd, rsmod.arg);
+    let preview = Rc::new(RefCell::new(Preview::new()));
+    rsmod.dry_run = Some(Rc::clone(&preview));
+    command(rsmod);
+    rsmod.dry_run = None;
+
+    let lines = preview.borrow().changes();
+
+    let shell = rsmod.shell;
+    let spaces = if shell == "noshell" || shell == "perl" || shell == "python" || shell == "r" {
+        ""
+    } else {
+        "  "
+    };
+
+    if shell != "noshell" {
+        echo("", shell);
+    }
+    if lines.is_empty() {
+        echo(&format!("{}{} would change nothing.", spaces, title.trim_end()), shell);
+    } else {
+        echo(&format!("{}{} would change:", spaces, title.trim_end()), shell);
+        for line in lines {
+            echo(&format!("{}  {}", spaces, line), shell);
+        }
+    }
+    if shell != "noshell" {
+        echo("", shell);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, diff_path, Environment};
+
+    fn environment(vars: &[(&str, &str)]) -> Environment {
+        vars.iter().map(|(var, val)| (var.to_string(), val.to_string())).collect()
+    }
+
+    #[test]
+    fn _diff_path() {
+        assert_eq!(
+            vec!["+ PATH /opt/blast/bin"],
+            diff_path("PATH", "/usr/bin:/bin", "/opt/blast/bin:/usr/bin:/bin")
+        );
+        assert_eq!(vec!["- PATH /bin"], diff_path("PATH", "/usr/bin:/bin", "/usr/bin"));
+        assert_eq!(
+            vec!["+ PATH /opt/blast/bin", "- PATH /bin"],
+            diff_path("PATH", "/usr/bin:/bin", "/opt/blast/bin:/usr/bin")
+        );
+        // only the order changed
+        assert_eq!(
+            vec!["~ PATH=/bin:/usr/bin (was /usr/bin:/bin)"],
+            diff_path("PATH", "/usr/bin:/bin", "/bin:/usr/bin")
+        );
+    }
+
+    #[test]
+    fn _diff() {
+        let before = environment(&[
+            ("LOADEDMODULES", "gcc/12.1"),
+            ("PATH", "/usr/bin:/bin"),
+            ("CC", "gcc"),
+            ("BLASTDB", "/data/blast"),
+            ("RSMODULES_DELTA", "AAAA"),
+        ]);
+        let after = environment(&[
+            ("LOADEDMODULES", "blast/2.1:gcc/12.1"),
+            ("PATH", "/opt/blast/bin:/usr/bin:/bin"),
+            ("CC", "icc"),
+            ("BLAST_THREADS", "4"),
+            ("RSMODULES_DELTA", "BBBB"),
+        ]);
+
+        assert_eq!(
+            vec![
+                "+ module blast/2.1",
+                "- BLASTDB (was /data/blast)",
+                "+ BLAST_THREADS=4",
+                "~ CC=icc (was gcc)",
+                "+ PATH /opt/blast/bin",
+            ],
+            diff(&before, &after)
+        );
+        assert_eq!(true, diff(&before, &before).is_empty());
+    }
+}
@@ -23,14 +23,21 @@ SOFTWARE.
 */
 use super::bold;
 use super::output;
+use super::print;
+use super::quote;
+use super::setenv;
+// for the modules in rsmod that stop with the usage or an error
+use super::{usage, CRASH_MODULE_NOT_FOUND};
 use glob::glob_with;
 use glob::MatchOptions;
 use gumdrop::Options;
-use script::DeprecatedState;
+use script::Context;
+use std::cell::RefCell;
 use std::env;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str::FromStr;
 use std::sync::atomic::Ordering;
 
@@ -39,19 +46,43 @@ use mdcat;
 use mdcat::{ResourceAccess, TerminalCapabilities, TerminalSize};
 use pulldown_cmark::Parser;
 use std::fs::File;
-use std::fs::OpenOptions;
 use std::io::prelude::*;
 use std::io::stdin;
 use syntect::parsing::SyntaxSet;
 
 mod autoload;
 mod cache;
+mod collection;
+pub mod history;
+mod lint;
+mod lua;
 mod manage;
+mod modulepath;
+pub mod preview;
 mod script;
+mod tcl;
+mod test;
+mod version;
 
 static DEFAULT_MODULE_PATH: &str = "/usr/local";
 pub static ENV_LOADEDMODULES: &str = "LOADEDMODULES"; // name of an env var
-static ENV_UNDO: &str = "RSMODULES_UNDO"; // name of an env var
+pub static ENV_DELTA: &str = "RSMODULES_DELTA"; // name of an env var
+pub static ENV_DEPENDENCIES: &str = "RSMODULES_DEPENDENCIES"; // name of an env var
+pub static ENV_INACTIVE: &str = "RSMODULES_INACTIVE"; // name of an env var
+pub static ENV_FAMILY: &str = "RSMODULES_FAMILY_"; // prefix of an env var
+pub static ENV_SWAP_FAMILY: &str = "RSMODULES_SWAP_FAMILY"; // name of an env var
+pub static ENV_TMPFILE: &str = "RSMODULES_TMPFILE"; // name of an env var
+
+#[cfg(test)]
+lazy_static! {
+    // the tests that change the environment of this process take turns
+    static ref ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+}
+
+#[cfg(test)]
+pub fn lock_env() -> std::sync::MutexGuard<'static, ()> {
+    ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
 
 #[derive(Debug, Default, Options)]
 pub struct AvailableOptions {
@@ -71,14 +102,27 @@ pub struct AvailableOptions {
     regex: bool,
 }
 
+#[derive(Debug, Default, Options)]
+pub struct SpiderOptions {
+    #[options(free, help = "Space separated list of search strings")]
+    search: Vec<String>,
+
+    #[options(help = "Print this help message")]
+    help: bool,
+
+    #[options(short = "r", help = "Use a regex to search")]
+    regex: bool,
+}
+
 #[derive(Debug)]
 pub struct Rsmodule<'a> {
     pub cmd: &'a str,                 // load|list|avail|...
     pub typed_command: &'a str,       // load|list|avail|...
     pub arg: &'a str,                 // blast/12.1 | blast | blast/12
     pub search_path: &'a Vec<String>, // module paths
-    pub shell: &'a str,               // tcsh|csh|bash|zsh
+    pub shell: &'a str,               // tcsh|csh|bash|zsh|fish
     pub shell_width: usize,
+    pub dry_run: Option<Rc<RefCell<preview::Preview>>>, // what a dry run would change
 }
 
 pub fn crash(signal: i32, message: &str) {
@@ -92,6 +136,21 @@ pub fn crash(signal: i32, message: &str) {
     crash!(signal, "{}", message);
 }
 
+// the shells that eval the code we generate, the other ones
+// get their output directly on stdout
+pub fn is_eval_shell(shell: &str) -> bool {
+    shell == "bash" || shell == "zsh" || shell == "fish" || shell == "tcsh" || shell == "csh"
+}
+
+// makes the exit code 1, so ci jobs fail. The module function
+// returns the status of the last command it evaluated
+pub fn failed(shell: &str) {
+    super::EXIT_CODE.store(1, Ordering::Relaxed);
+    if is_eval_shell(shell) {
+        output(String::from("false\n"));
+    }
+}
+
 pub fn get_module_paths(silent: bool) -> Vec<String> {
     let mut modulepath: String = String::from(DEFAULT_MODULE_PATH);
     let mut modulepaths: Vec<String> = Vec::new();
@@ -125,24 +184,11 @@ pub fn get_module_list(shell: &str) -> Vec<(String, bool, String)> {
     let modulepaths = get_module_paths(false);
 
     for path in modulepaths {
-        // test if cachefiles exist in the paths
-        // if they don't and we have write permission in that folder
-        // we should create the cache
-        let mut testpath = PathBuf::from(&path);
-        testpath.push(format!("{}{}", cache::MODULECACHE, cache::release_debug()));
-
-        if testpath.exists() {
-            cache::parse_modules_cache_file(&testpath, &mut modules);
+        // if the cachefile in a path is missing, stale or corrupt
+        // and we have write permission in that folder
+        // the cache is created again
+        if cache::parse_modules_cache_file(&path, shell, &mut modules) {
             found_cachefile = true;
-        } else {
-            echo(
-                &format!("  {}: {} doesn't contain an index.", bold(shell, "WARNING"), path),
-                shell,
-            );
-            if cache::update(&path, shell) {
-                cache::parse_modules_cache_file(&testpath, &mut modules);
-                found_cachefile = true;
-            }
         }
     }
 
@@ -227,7 +273,9 @@ pub fn command(rsmod: &mut Rsmodule) {
             return;
         }
 
-        if !opts.search.is_empty() {
+        if rsmod.shell == "json" {
+            cache::get_module_list_json(&opts.search, &opts);
+        } else if !opts.search.is_empty() {
             for arg in &opts.search {
                 cache::get_module_list(arg, &rsmod, &opts);
             }
@@ -299,7 +347,11 @@ pub fn command(rsmod: &mut Rsmodule) {
         }
         */
     } else if rsmod.cmd == "undo" {
-        undo(rsmod);
+        history::undo(rsmod);
+    } else if rsmod.cmd == "redo" {
+        history::redo(rsmod);
+    } else if rsmod.cmd == "history" {
+        history::list(rsmod.shell);
     } else if rsmod.cmd == "delete" {
         manage::delete(rsmod);
     } else if rsmod.cmd == "create" {
@@ -308,13 +360,56 @@ pub fn command(rsmod: &mut Rsmodule) {
         autoload(rsmod);
     } else if rsmod.cmd == "readme" {
         module_action(rsmod, "readme");
+    } else if rsmod.cmd == "save" {
+        collection::save(rsmod);
+    } else if rsmod.cmd == "restore" {
+        collection::restore(rsmod);
+    } else if rsmod.cmd == "savelist" {
+        collection::savelist(rsmod.shell);
+    } else if rsmod.cmd == "disable" {
+        collection::disable(rsmod);
+    } else if rsmod.cmd == "spider" {
+        let args: Vec<&str> = rsmod.arg.split_whitespace().collect();
+
+        let opts = match SpiderOptions::parse_args_default(&args) {
+            Ok(opts) => opts,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
+
+        if opts.help || opts.search.is_empty() {
+            eprintln!("Usage: module spider [OPTIONS] [search]");
+            eprintln!();
+            eprintln!("{}", SpiderOptions::usage());
+            return;
+        }
+
+        cache::spider(&opts.search, rsmod, &opts);
+    } else if rsmod.cmd == "which" {
+        let args: Vec<String> = rsmod.arg.split_whitespace().map(|arg| arg.to_string()).collect();
+        if args.is_empty() {
+            super::usage(true, true);
+            return;
+        }
+
+        cache::which(&args, rsmod);
+    } else if rsmod.cmd == "lint" {
+        lint::run(rsmod);
+    } else if rsmod.cmd == "test" {
+        test::run(rsmod);
+    } else if rsmod.cmd == "use" {
+        modulepath::use_path(rsmod);
+    } else if rsmod.cmd == "unuse" {
+        modulepath::unuse_path(rsmod);
     }
 }
 
 pub fn get_module_description(path: &PathBuf, action: &str) -> Vec<String> {
-    script::run(path, action);
+    let context = script::run(path, action);
 
-    script::get_description()
+    script::get_description(&context)
 }
 
 fn find_root(path: &str, previous_path: &str, selected_module: &str) -> String {
@@ -377,13 +472,13 @@ fn read_input<T: AsRef<str>>(filename: T) -> std::io::Result<(PathBuf, String)>
     }
 }
 
-fn cd(selected_module: &str) -> Vec<String> {
+fn cd(context: &Context, selected_module: &str, shell: &str) -> Vec<String> {
     if selected_module.is_empty() {
         return Vec::new();
     }
 
     let mut lines: Vec<String> = Vec::new();
-    let paths = script::get_readme_paths();
+    let paths = script::get_readme_paths(context);
 
     let mut root_paths: Vec<String> = Vec::new();
 
@@ -395,13 +490,13 @@ fn cd(selected_module: &str) -> Vec<String> {
         root_paths.push(tmp);
     }
 
-    lines.push(format!("cd {}", root_paths[0]));
+    lines.push(format!("cd {}", quote(&root_paths[0], shell)));
     lines
 }
 
-fn get_readme(selected_module: &str, shell: &str) -> Vec<String> {
+fn get_readme(context: &Context, selected_module: &str, shell: &str) -> Vec<String> {
     // first check if there are manfiles
-    let manpaths = script::get_readme_manpaths();
+    let manpaths = script::get_readme_manpaths(context);
 
     let parts: Vec<&str> = selected_module.split('/').collect();
 
@@ -420,7 +515,7 @@ fn get_readme(selected_module: &str, shell: &str) -> Vec<String> {
     if !mans.is_empty() {
         let mut lines: Vec<String> = Vec::new();
 
-        lines.push(format!("man -P cat {}", mans[0]));
+        lines.push(format!("man -P cat {}", quote(&mans[0], shell)));
 
         return lines;
     }
@@ -428,7 +523,7 @@ fn get_readme(selected_module: &str, shell: &str) -> Vec<String> {
     // no manpages
     // so check for readmes
 
-    let paths = script::get_readme_paths();
+    let paths = script::get_readme_paths(context);
 
     let mut readme_paths: Vec<String> = Vec::new();
 
@@ -462,41 +557,36 @@ fn get_readme(selected_module: &str, shell: &str) -> Vec<String> {
     let mut counter = 0;
     for readme in &readmes {
         if counter == 0 {
-            let mdtmpfile = format!("{}{}", lu!(super::TMPFILE_PATH), ".md");
             lines.push("echo ''".to_string());
-            lines.push(format!("echo '  {}{}'", bold(shell, "Showing readme file: "), readme));
+            let msg = format!("  {}{}", bold(shell, "Showing readme file: "), readme);
+            lines.push(format!("echo {}", quote(&msg, shell)));
             lines.push("echo ''".to_string());
             let path = Path::new(readme);
 
             let markdown = path.extension() != None && path.extension().unwrap() == "md";
 
-            match OpenOptions::new().write(true).create(true).truncate(true).open(&mdtmpfile) {
-                Ok(fileresult) => {
-                    let mut file: File = fileresult;
-                    if markdown && (shell == "zsh" || shell == "bash") && env::var("NO_COLOR").is_err() {
-                        let (base_dir, input) = read_input(readme).unwrap();
-                        let parser = Parser::new(&input);
-
-                        let syntax_set = SyntaxSet::load_defaults_newlines();
-                        mdcat::push_tty(
-                            &mut file,
-                            TerminalCapabilities::detect(),
-                            TerminalSize::detect().unwrap_or_default(),
-                            parser,
-                            &base_dir,
-                            ResourceAccess::LocalOnly,
-                            syntax_set,
-                        )
-                        .unwrap();
-
-                        lines.push(format!("echo \"`cat {}`\"", mdtmpfile));
-                        lines.push(format!("rm -f {}", mdtmpfile));
-                    } else {
-                        lines.push(format!("cat {}", readme));
-                    }
-                }
-                Err(_) => lines.push(format!("cat {}", readme)),
-            };
+            if markdown && (shell == "zsh" || shell == "bash") && env::var("NO_COLOR").is_err() {
+                let (base_dir, input) = read_input(readme).unwrap();
+                let parser = Parser::new(&input);
+
+                // rendered in memory, the shell gets it as one string
+                let mut rendered: Vec<u8> = Vec::new();
+                let syntax_set = SyntaxSet::load_defaults_newlines();
+                mdcat::push_tty(
+                    &mut rendered,
+                    TerminalCapabilities::detect(),
+                    TerminalSize::detect().unwrap_or_default(),
+                    parser,
+                    &base_dir,
+                    ResourceAccess::LocalOnly,
+                    syntax_set,
+                )
+                .unwrap();
+
+                lines.push(format!("echo {}", quote(&String::from_utf8_lossy(&rendered), shell)));
+            } else {
+                lines.push(format!("cat {}", quote(readme, shell)));
+            }
 
             if readmes.len() > 1 {
                 lines.push("echo ''".to_string());
@@ -504,7 +594,7 @@ fn get_readme(selected_module: &str, shell: &str) -> Vec<String> {
                 lines.push("echo ''".to_string());
             }
         } else {
-            lines.push(format!("echo '  - {}'", readme));
+            lines.push(format!("echo {}", quote(&format!("  - {}", readme), shell)));
         }
         counter += 1;
     }
@@ -522,7 +612,7 @@ fn get_readme(selected_module: &str, shell: &str) -> Vec<String> {
         ));
         lines.push("echo ''".to_string());
         for path in &readme_paths {
-            lines.push(format!("echo '  - {}'", path));
+            lines.push(format!("echo {}", quote(&format!("  - {}", path), shell)));
         }
     }
 
@@ -531,33 +621,64 @@ fn get_readme(selected_module: &str, shell: &str) -> Vec<String> {
     lines
 }
 
-fn run_modulefile(path: &PathBuf, rsmod: &mut Rsmodule, selected_module: &str, action: &str) {
-    script::run(path, action);
+// returns the modules of the same family that were replaced by this one
+fn run_modulefile(path: &PathBuf, rsmod: &mut Rsmodule, selected_module: &str, action: &str) -> Vec<String> {
+    // modules loaded by an older version have no recorded changes,
+    // those are unloaded by evaluating the modulefile in reverse
+    let delta = if action == "unload" {
+        script::run_delta(selected_module, rsmod.dry_run.clone())
+    } else {
+        None
+    };
+    let mut context = match delta {
+        Some(context) => context,
+        None => script::run_with(path, action, rsmod.dry_run.clone()),
+    };
+
+    // a dry run shows what would change, the shell gets nothing and the
+    // loaded modules are left alone, the shell wouldn't get the changes
+    // of a module that conflicts either
+    if let Some(preview) = &rsmod.dry_run {
+        if script::is_applied(&context) {
+            preview.borrow_mut().add(&context, selected_module, action);
+        }
+        return script::get_replaced_modules(&context);
+    }
 
     let data = if action == "info" {
-        script::get_info(rsmod.shell, selected_module)
+        script::get_info(&context, rsmod.shell, selected_module)
     } else if action == "cd" {
-        cd(selected_module)
+        cd(&context, selected_module, rsmod.shell)
     } else if action == "readme" {
-        get_readme(selected_module, rsmod.shell)
+        get_readme(&context, selected_module, rsmod.shell)
     } else {
-        script::get_output(selected_module, action, rsmod.shell)
+        // modules loaded by a modulefile, by refresh or after a change of $MODULEPATH
+        // are not loaded on request of the user
+        let explicit = rsmod.typed_command != "refresh" && rsmod.typed_command != "swap" && !script::is_loading_dependency();
+        script::get_output(&mut context, selected_module, action, rsmod.shell, explicit)
     };
 
     for mut line in data {
-        if rsmod.shell != "perl" {
+        if rsmod.shell != "perl" && rsmod.shell != "json" {
             line = format!("{}\n", line);
         }
 
-        if rsmod.shell == "noshell" || rsmod.shell == "python" || rsmod.shell == "perl" || rsmod.shell == "r" {
+        if rsmod.shell == "noshell"
+            || rsmod.shell == "python"
+            || rsmod.shell == "perl"
+            || rsmod.shell == "r"
+            || rsmod.shell == "json"
+        {
             println!("{}", line);
         } else {
             output(line);
         }
     }
+
+    script::get_replaced_modules(&context)
 }
 
-fn module_action(rsmod: &mut Rsmodule, action: &str) {
+fn get_reversed_modules(rsmod: &Rsmodule, action: &str) -> Vec<(String, bool, String)> {
     // when unloading we only want a list of the loaded modules
     // for matching modulenames :
     // we have: blast/1.2 and blast/1.3 (D) while blast/1.2 is loaded
@@ -571,100 +692,147 @@ fn module_action(rsmod: &mut Rsmodule, action: &str) {
     };
 
     reversed_modules.reverse();
+    reversed_modules
+}
 
-    if rsmod.cmd != "cd" && rsmod.arg == "" {
-        super::usage(true, true);
-        return;
-    }
+// the module that load picks for mdl and its modulefile, reversed_modules is the
+// list of modules with the last one first. A partial name (blast or blast/x86_64)
+// picks the default version, or the latest one
+pub fn find_module(
+    mdl: &str,
+    search_path: &[String],
+    reversed_modules: &[(String, bool, String)],
+) -> Result<(String, PathBuf), String> {
+    // python@>=3.8 is replaced by the version that satisfies the constraint,
+    // a version that is already loaded is preferred
+    let resolved: String;
+    let mdl: &str = if version::has_constraint(mdl) {
+        resolved = version::resolve(mdl, reversed_modules)?;
+        &resolved
+    } else {
+        mdl
+    };
 
-    //let mut selected_module = rsmod.arg;
+    let mut selected_module = mdl;
     let mut modulefile: PathBuf = PathBuf::new();
-    let mut found: bool;
-
-    let modules: Vec<&str> = rsmod.arg.split_whitespace().collect();
-
-    for mdl in modules {
-        let mut selected_module = mdl;
-        found = false;
-
-        // check if module file exists
-        // run over modulepaths, check if a folder/file exists with the wanted 'module' var
-
-        // if not, maybe check if its a partial match
-        // blast -> blast/x86_64/1.0 and blast/x86_64/2.0
-        // then we need to load the Default version
-        // or just the latest one
-
-        'outer: for modulepath in rsmod.search_path {
-            let testpath = format!("{}/{}", modulepath, mdl);
-            if Path::new(&testpath).exists() {
-                // we got it, now we need to figure out if its a partial match or not
-                if Path::new(&testpath).is_file() {
-                    found = true;
-                    modulefile = PathBuf::from(&testpath);
-                } else {
-                    for module in &reversed_modules {
-                        // we got a partial match, now we need to find the default module
-                        // for this folder or subfolders
-                        // loop through all the modules and get the first one
-                        // that matches starts_with
-
-                        // partial matches only work for file/folder names
-                        // blast or blast/x86_64 but not blas or blast/x86_
-                        // because of the above 'exists()' check
-
-                        // prevent that: module load blast loads blastz
-                        let splitter: Vec<&str> = module.0.split(mdl).collect();
-                        if splitter.len() > 1 {
-                            if found && module.0.starts_with(&format!("{}/", mdl)) && module.1 == true {
-                                selected_module = module.0.as_ref();
-                                let testpath = format!("{}/{}", modulepath, module.0);
-                                // if a modulefile is already found, don't overwrite it with a
-                                // possible non existing file
-                                if Path::new(&testpath).exists() && Path::new(&testpath).is_file() {
-                                    modulefile = PathBuf::from(&testpath);
-                                    break 'outer;
-                                }
-                            }
-
-                            if found && !module.0.starts_with(mdl) {
+    let mut found = false;
+
+    // check if module file exists
+    // run over modulepaths, check if a folder/file exists with the wanted 'module' var
+
+    // if not, maybe check if its a partial match
+    // blast -> blast/x86_64/1.0 and blast/x86_64/2.0
+    // then we need to load the Default version
+    // or just the latest one
+
+    'outer: for modulepath in search_path {
+        let testpath = get_modulefile(modulepath, mdl);
+        if Path::new(&testpath).exists() {
+            // we got it, now we need to figure out if its a partial match or not
+            if Path::new(&testpath).is_file() {
+                found = true;
+                modulefile = PathBuf::from(&testpath);
+            } else {
+                for module in reversed_modules {
+                    // we got a partial match, now we need to find the default module
+                    // for this folder or subfolders
+                    // loop through all the modules and get the first one
+                    // that matches starts_with
+
+                    // partial matches only work for file/folder names
+                    // blast or blast/x86_64 but not blas or blast/x86_
+                    // because of the above 'exists()' check
+
+                    // prevent that: module load blast loads blastz
+                    let splitter: Vec<&str> = module.0.split(mdl).collect();
+                    if splitter.len() > 1 {
+                        if found && module.0.starts_with(&format!("{}/", mdl)) && module.1 == true {
+                            selected_module = module.0.as_ref();
+                            let testpath = get_modulefile(modulepath, &module.0);
+                            // if a modulefile is already found, don't overwrite it with a
+                            // possible non existing file
+                            if Path::new(&testpath).exists() && Path::new(&testpath).is_file() {
+                                modulefile = PathBuf::from(&testpath);
                                 break 'outer;
                             }
+                        }
+
+                        if found && !module.0.starts_with(mdl) {
+                            break 'outer;
+                        }
 
-                            if !found && splitter[1].starts_with('/') && module.0.starts_with(mdl) {
-                                selected_module = module.0.as_ref();
-                                found = true;
-                                let testpath = format!("{}/{}", modulepath, module.0);
-                                // if a modulefile is already found, don't overwrite it with a
-                                // possible non existing file
-                                if Path::new(&testpath).exists() && Path::new(&testpath).is_file() {
-                                    modulefile = PathBuf::from(&testpath);
-                                }
-                                // don't break out of the outer loop, there might be a module
-                                // file marked as D
-                                //break 'outer;
+                        if !found && splitter[1].starts_with('/') && module.0.starts_with(mdl) {
+                            selected_module = module.0.as_ref();
+                            found = true;
+                            let testpath = get_modulefile(modulepath, &module.0);
+                            // if a modulefile is already found, don't overwrite it with a
+                            // possible non existing file
+                            if Path::new(&testpath).exists() && Path::new(&testpath).is_file() {
+                                modulefile = PathBuf::from(&testpath);
                             }
+                            // don't break out of the outer loop, there might be a module
+                            // file marked as D
+                            //break 'outer;
                         }
                     }
                 }
             }
         }
+    }
+
+    if !found {
+        return Err(format!("Module {} not found.", selected_module));
+    }
+
+    Ok((selected_module.to_string(), modulefile))
+}
+
+fn module_action(rsmod: &mut Rsmodule, action: &str) {
+    if rsmod.cmd != "cd" && rsmod.arg == "" {
+        usage(true, true);
+        return;
+    }
+
+    let mut reversed_modules = get_reversed_modules(rsmod, action);
+
+    // a module can change $MODULEPATH, so the search path and the
+    // list of modules are read again when that happens
+    let mut search_path: Vec<String> = rsmod.search_path.clone();
+    let mut modulepath_changed = false;
+
+    let modules: Vec<&str> = rsmod.arg.split_whitespace().collect();
+
+    // module info a b prints one json array with a document for every module
+    let mut json_info: Vec<String> = Vec::new();
+
+    for mdl in modules {
+        if modulepath_changed {
+            search_path = get_module_paths(true);
+            reversed_modules = get_reversed_modules(rsmod, action);
+            modulepath_changed = false;
+        }
+        let previous_search_path = search_path.clone();
+
+        let (selected_module, modulefile) = match find_module(mdl, &search_path, &reversed_modules) {
+            Ok(module) => module,
+            // a module that is loaded can be unloaded without its modulefile
+            Err(_) if action == "unload" => (mdl.to_string(), PathBuf::new()),
+            Err(msg) => {
+                crash(CRASH_MODULE_NOT_FOUND, &msg);
+                continue;
+            }
+        };
+        let selected_module: &str = &selected_module;
 
         if action == "edit" {
             if env::var("EDITOR").is_ok() {
-                output(format!("$EDITOR {}\n", modulefile.display()));
+                output(format!("$EDITOR {}\n", quote(&modulefile.to_string_lossy(), rsmod.shell)));
             } else {
-                output(format!("vi {}\n", modulefile.display()));
+                output(format!("vi {}\n", quote(&modulefile.to_string_lossy(), rsmod.shell)));
             }
             continue;
         }
 
-        if !found && action != "unload" {
-            crash(
-                super::CRASH_MODULE_NOT_FOUND,
-                &format!("Module {} not found.", selected_module),
-            );
-        }
         // check of another version is already loaded
         // and replace it with the current one
 
@@ -677,33 +845,17 @@ fn module_action(rsmod: &mut Rsmodule, action: &str) {
             other = get_other_version_of_loaded_module(tmp_selected_module);
 
             if other != "" && other != selected_module {
-                for modulepath in rsmod.search_path {
-                    let testpath = format!("{}/{}", modulepath, other);
-                    let deprecated_check_path = format!("{}/{}", modulepath, selected_module);
+                for modulepath in &search_path {
+                    let testpath = get_modulefile(modulepath, &other);
+                    let deprecated_check_path = get_modulefile(modulepath, selected_module);
 
                     if Path::new(&testpath).exists() && Path::new(&testpath).is_file() {
                         let tmpmodulefile: PathBuf = PathBuf::from(&testpath);
                         // unload the module as we found the path to the file
                         // unless we are trying to load a deprecated module
 
-                        script::run(&PathBuf::from(&deprecated_check_path), "deprecated");
-                        let is_deprecated: bool;
-
-                        {
-                            // we ne need a different scope, or DEPRECATED is locked
-                            let deprecated = lu!(script::DEPRECATED);
-                            match deprecated.state {
-                                DeprecatedState::After => {
-                                    is_deprecated = true;
-                                }
-                                DeprecatedState::Before => {
-                                    is_deprecated = false;
-                                }
-                                DeprecatedState::Not => {
-                                    is_deprecated = false;
-                                }
-                            }
-                        }
+                        let context = script::run(&deprecated_check_path, "deprecated");
+                        let is_deprecated = script::is_deprecated(&context);
 
                         if !is_deprecated {
                             run_modulefile(&tmpmodulefile, rsmod, other.as_ref(), "unload");
@@ -720,6 +872,7 @@ fn module_action(rsmod: &mut Rsmodule, action: &str) {
             run_modulefile(&modulefile, rsmod, selected_module, "unload");
             // load the module again
             run_modulefile(&modulefile, rsmod, selected_module, "load");
+            modulepath_changed = update_hierarchy(rsmod, &previous_search_path);
             continue;
         }
 
@@ -728,33 +881,291 @@ fn module_action(rsmod: &mut Rsmodule, action: &str) {
             continue;
         }
 
+        if action == "info" && rsmod.shell == "json" {
+            let context = script::run(&modulefile, action);
+            json_info.push(script::get_info_json(&context, selected_module));
+            continue;
+        }
+
         // finaly load|unload|info the module
         output(format!("# {} {}\n", action, selected_module));
-        run_modulefile(&modulefile, rsmod, selected_module, action);
+        let replaced_family = run_modulefile(&modulefile, rsmod, selected_module, action);
+
+        if action == "unload" {
+            unload_dependencies(rsmod, selected_module);
+        }
+
+        if action == "load" || action == "unload" {
+            modulepath_changed = update_hierarchy(rsmod, &previous_search_path);
+        }
 
         if replaced_module && other != "" && selected_module != "" {
-            let spaces = if rsmod.shell == "noshell" || rsmod.shell == "perl" || rsmod.shell == "python" || rsmod.shell == "r" {
-                ""
-            } else {
-                "  "
-            };
+            replaced_message(&other, selected_module, rsmod.shell);
+        }
+
+        for other in replaced_family {
+            replaced_message(&other, selected_module, rsmod.shell);
+        }
+    }
+
+    if action == "info" && rsmod.shell == "json" {
+        println!("[{}]", json_info.join(","));
+    }
+}
+
+fn replaced_message(other: &str, selected_module: &str, shell: &str) {
+    let spaces = if shell == "noshell" || shell == "perl" || shell == "python" || shell == "r" {
+        ""
+    } else {
+        "  "
+    };
+
+    let msg: String = format!(
+        "{}The previously loaded module {} has been replaced \
+         with {}",
+        spaces,
+        bold(shell, other),
+        bold(shell, selected_module)
+    );
+    if shell != "noshell" {
+        echo("", shell);
+    }
+    echo(&msg, shell);
+    if shell != "noshell" {
+        echo("", shell);
+    }
+}
 
-            let msg: String = format!(
-                "{}The previously loaded module {} has been replaced \
-                 with {}",
-                spaces,
-                bold(rsmod.shell, &other),
-                bold(rsmod.shell, selected_module)
-            );
-            if rsmod.shell != "noshell" {
-                echo("", rsmod.shell);
+// unloads the modules that were only loaded because selected_module needed them
+fn unload_dependencies(rsmod: &mut Rsmodule, selected_module: &str) {
+    let unneeded = script::get_unneeded_dependencies(selected_module);
+    if rsmod.dry_run.is_none() {
+        script::prune_dependencies(selected_module, rsmod.shell);
+    }
+
+    for dependency in unneeded {
+        let mut rsmod_command: Rsmodule = Rsmodule {
+            cmd: "unload",
+            typed_command: "unload",
+            arg: &dependency,
+            search_path: rsmod.search_path,
+            shell: rsmod.shell,
+            shell_width: rsmod.shell_width,
+            dry_run: rsmod.dry_run.clone(),
+        };
+        command(&mut rsmod_command);
+    }
+}
+
+// sets an env var for the rest of this run and for the shell that called us
+pub fn save_env(var: &str, val: &str, shell: &str) {
+    env::set_var(var, val);
+
+    if shell == "python" || shell == "perl" {
+        print!("{}", setenv(var, val, shell));
+    } else if shell != "noshell" && shell != "json" && shell != "progressbar" && shell != "r" {
+        output(setenv(var, val, shell));
+    }
+}
+
+// modules that were unloaded because they are not in $MODULEPATH anymore
+fn get_inactive_modules() -> Vec<String> {
+    match env::var(ENV_INACTIVE) {
+        Ok(list) => list
+            .split(':')
+            .filter(|module| !module.is_empty())
+            .map(|module| module.to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn save_inactive_modules(modules: &[String], shell: &str) {
+    if modules.is_empty() && env::var(ENV_INACTIVE).is_err() {
+        return;
+    }
+    save_env(ENV_INACTIVE, &modules.join(":"), shell);
+}
+
+// the modulefile of a module in modulepath, the .lua extension of
+// Lmod modulefiles isn't part of the name of the module
+pub fn get_modulefile(modulepath: &str, module: &str) -> PathBuf {
+    let modulefile = PathBuf::from(format!("{}/{}", modulepath, module));
+    if !modulefile.is_file() {
+        let lua_modulefile = PathBuf::from(format!("{}/{}.lua", modulepath, module));
+        if lua_modulefile.is_file() {
+            return lua_modulefile;
+        }
+    }
+
+    modulefile
+}
+
+// returns the name that loads this module, or another version of it, from the search path
+fn find_in_search_path(module: &str, search_path: &[String]) -> Option<String> {
+    let name: Vec<&str> = module.split('/').collect();
+
+    if search_path
+        .iter()
+        .any(|modulepath| get_modulefile(modulepath, module).is_file())
+    {
+        Some(module.to_string())
+    } else if search_path
+        .iter()
+        .any(|modulepath| Path::new(&format!("{}/{}", modulepath, name[0])).exists())
+    {
+        Some(name[0].to_string())
+    } else {
+        None
+    }
+}
+
+// the inactive modules that are available again, with the name that loads them,
+// the ones that just became inactive stay inactive
+fn find_activated(inactive: &[String], deactivated: &[String], search_path: &[String]) -> Vec<(String, String)> {
+    inactive
+        .iter()
+        .filter(|module| !deactivated.contains(module))
+        .filter_map(|module| find_in_search_path(module, search_path).map(|name| (module.to_string(), name)))
+        .collect()
+}
+
+fn hierarchy_message(msg: &str, modules: &[String], shell: &str) {
+    if modules.is_empty() {
+        return;
+    }
+
+    let spaces = if shell == "noshell" || shell == "perl" || shell == "python" || shell == "r" {
+        ""
+    } else {
+        "  "
+    };
+
+    if shell != "noshell" {
+        echo("", shell);
+    }
+    echo(&format!("{}{}", spaces, msg), shell);
+    for module in modules {
+        echo(&format!("{}  * {}", spaces, bold(shell, module)), shell);
+    }
+    if shell != "noshell" {
+        echo("", shell);
+    }
+}
+
+// hierarchical modules: when a module (e.g. a compiler) changes $MODULEPATH,
+// the loaded modules that came from a path that was removed are unloaded and
+// loaded again from the new paths, or they become inactive when they are not
+// available there. Inactive modules are loaded again as soon as they are available.
+// returns true when $MODULEPATH was changed
+fn update_hierarchy(rsmod: &mut Rsmodule, previous_search_path: &[String]) -> bool {
+    let search_path = get_module_paths(true);
+    if search_path.as_slice() == previous_search_path {
+        return false;
+    }
+
+    // the module that loads this one as a dependency takes care of it
+    if script::is_loading_dependency() {
+        return true;
+    }
+
+    let removed_paths: Vec<&String> = previous_search_path
+        .iter()
+        .filter(|path| !search_path.contains(path))
+        .collect();
+
+    let mut inactive = get_inactive_modules();
+    let mut reloaded: Vec<String> = Vec::new();
+    let mut deactivated: Vec<String> = Vec::new();
+    let mut activated: Vec<String> = Vec::new();
+
+    // the last loaded module first
+    let loaded: Vec<String> = match env::var(ENV_LOADEDMODULES) {
+        Ok(list) => list
+            .split(':')
+            .filter(|module| !module.is_empty())
+            .map(|module| module.to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    let mut to_load: Vec<String> = Vec::new();
+    for module in loaded.iter().rev() {
+        let modulefile = match removed_paths
+            .iter()
+            .map(|path| get_modulefile(path, module))
+            .find(|path| path.is_file())
+        {
+            Some(modulefile) => modulefile,
+            None => continue,
+        };
+
+        // it could already be unloaded as a dependency of another module
+        if !is_module_loaded(module, true) {
+            continue;
+        }
+
+        output(format!("# unload {}\n", module));
+        run_modulefile(&modulefile, rsmod, module, "unload");
+        unload_dependencies(rsmod, module);
+
+        match find_in_search_path(module, &search_path) {
+            Some(name) => {
+                to_load.push(name);
+                reloaded.push(module.to_string());
             }
-            echo(&msg, rsmod.shell);
-            if rsmod.shell != "noshell" {
-                echo("", rsmod.shell);
+            None => {
+                inactive.retain(|x| x != module);
+                inactive.push(module.to_string());
+                deactivated.push(module.to_string());
             }
         }
     }
+
+    for (module, name) in find_activated(&inactive, &deactivated, &search_path) {
+        to_load.push(name);
+        activated.push(module);
+    }
+    inactive.retain(|module| !activated.contains(module));
+
+    for module in &to_load {
+        // another version of this module could be loaded by now
+        let name: Vec<&str> = module.split('/').collect();
+        if is_module_loaded(module, true) || !get_other_version_of_loaded_module(name[0]).is_empty() {
+            continue;
+        }
+
+        let mut rsmod_command: Rsmodule = Rsmodule {
+            cmd: "load",
+            typed_command: "swap",
+            arg: module,
+            search_path: &search_path,
+            shell: rsmod.shell,
+            shell_width: rsmod.shell_width,
+            dry_run: rsmod.dry_run.clone(),
+        };
+        command(&mut rsmod_command);
+    }
+
+    save_inactive_modules(&inactive, rsmod.shell);
+
+    hierarchy_message(
+        "The following modules have been reloaded because MODULEPATH has changed:",
+        &reloaded,
+        rsmod.shell,
+    );
+    hierarchy_message(
+        "The following modules are not available anymore and are now inactive:",
+        &deactivated,
+        rsmod.shell,
+    );
+    hierarchy_message(
+        "The following inactive modules are available again and have been loaded:",
+        &activated,
+        rsmod.shell,
+    );
+
+    true
 }
 
 pub fn is_module_loaded(name: &str, only_full_match: bool) -> bool {
@@ -842,19 +1253,15 @@ pub fn is_other_version_of_module_loaded(name: &str) -> bool {
 }
 
 pub fn echo(line: &str, shell: &str) {
-    //FIXME: if line contains \n and shell is csh or tcsh
-    // escape it
     if shell == "noshell" || shell == "progressbar" {
         println!("{}", line);
-    } else if shell == "python" {
-        println!("print(\"{}\")", line);
-    } else if shell == "r" {
-        println!("print(\"{}\")", line);
-    } else if shell == "perl" {
-        println!("print(\"{}\\n\");", line);
+    } else if shell == "json" {
+        // stdout is reserved for the json document
+        eprintln!("{}", line);
+    } else if shell == "python" || shell == "r" || shell == "perl" {
+        println!("{}", print(line, shell));
     } else {
-        let data = format!("echo \"{}\"\n", line);
-        output(data);
+        output(format!("{}\n", print(line, shell)));
     }
 }
 
@@ -894,6 +1301,11 @@ fn list(rsmod: &mut Rsmodule) {
     // display loaded modules in loaded order
     loadedmodules.reverse();
 
+    if rsmod.shell == "json" {
+        cache::get_loaded_module_list_json(&loadedmodules);
+        return;
+    }
+
     if !loadedmodules.is_empty() {
         if rsmod.shell != "noshell" {
             echo("", rsmod.shell);
@@ -921,6 +1333,16 @@ fn list(rsmod: &mut Rsmodule) {
     }
     if rsmod.shell != "noshell" {
         echo("", rsmod.shell);
+
+        let inactive = get_inactive_modules();
+        if !inactive.is_empty() {
+            echo("  Inactive modules:", rsmod.shell);
+            echo("", rsmod.shell);
+            for module in inactive {
+                echo(&format!("  * {}", bold(rsmod.shell, &module)), rsmod.shell);
+            }
+            echo("", rsmod.shell);
+        }
     }
 }
 
@@ -939,11 +1361,12 @@ fn refresh(rsmod: &mut Rsmodule) {
         if module != "" {
             let mut rsmod_command: Rsmodule = Rsmodule {
                 cmd: "load",
-                typed_command: "load",
+                typed_command: "refresh",
                 arg: module,
                 search_path: rsmod.search_path,
                 shell: rsmod.shell,
                 shell_width: rsmod.shell_width,
+                dry_run: rsmod.dry_run.clone(),
             };
             command(&mut rsmod_command);
         }
@@ -970,10 +1393,15 @@ fn purge(rsmod: &mut Rsmodule) {
                 search_path: rsmod.search_path,
                 shell: rsmod.shell,
                 shell_width: rsmod.shell_width,
+                dry_run: rsmod.dry_run.clone(),
             };
             command(&mut rsmod_command);
         }
     }
+
+    if rsmod.dry_run.is_none() {
+        save_inactive_modules(&[], rsmod.shell);
+    }
 }
 
 fn refurbish(rsmod: &mut Rsmodule) {
@@ -985,51 +1413,6 @@ fn refurbish(rsmod: &mut Rsmodule) {
     autoload::run(subcommand, &mut args, rsmod.shell);
 }
 
-fn undo(rsmod: &mut Rsmodule) {
-    let args = match env::var(ENV_UNDO) {
-        Ok(list) => list,
-        Err(_) => {
-            return;
-        }
-    };
-    let mut args: Vec<&str> = args.split_whitespace().collect();
-
-    let mut cmd: &str;
-
-    if args.is_empty() {
-        return;
-    }
-
-    if args.len() > 1 {
-        // means we did a purge
-        cmd = args[0];
-        if cmd == "load" {
-            cmd = "unload";
-        } else if cmd == "unload" {
-            cmd = "load";
-        }
-        args.retain(|&i| (i != "load" && i != "unload" && i != "switch"));
-        let mut rsmod_command: Rsmodule = Rsmodule {
-            cmd,
-            typed_command: cmd,
-            arg: &args.join(" "),
-            search_path: rsmod.search_path,
-            shell: rsmod.shell,
-            shell_width: rsmod.shell_width,
-        };
-        command(&mut rsmod_command);
-        if cmd == "switch" {
-            args.reverse();
-        }
-
-        output(super::setenv(
-            "RSMODULES_UNDO",
-            &format!("{} {}", cmd, args.join(" ")),
-            rsmod.shell,
-        ));
-    }
-}
-
 fn autoload_usage(shell: &str) {
     echo("", shell);
     echo(
@@ -1093,10 +1476,14 @@ mod tests {
     use super::get_other_version_of_loaded_module;
     use super::is_module_loaded;
     use super::is_other_version_of_module_loaded;
+    use super::{find_activated, find_in_search_path, get_inactive_modules, lock_env, ENV_INACTIVE};
     use std::env;
+    use std::fs;
+    use std::process;
 
     #[test]
     fn _is_other_version_of_module_loaded() {
+        let _env = lock_env();
         env::set_var("LOADEDMODULES", "blast/12.3:blast/11.1");
         assert_eq!(true, is_other_version_of_module_loaded("blast/11.1"));
         assert_eq!(true, is_other_version_of_module_loaded("blast/13.4"));
@@ -1107,6 +1494,7 @@ mod tests {
     }
     #[test]
     fn _get_other_version_of_module_loaded() {
+        let _env = lock_env();
         env::set_var("LOADEDMODULES", "blast/12.3:blast/11.1");
         assert_eq!("blast/12.3", get_other_version_of_loaded_module("blast/11.1"));
         assert_eq!("blast/12.3", get_other_version_of_loaded_module("blast/x86_64/11.1"));
@@ -1115,6 +1503,7 @@ mod tests {
     }
     #[test]
     fn _is_module_loaded() {
+        let _env = lock_env();
         env::set_var("LOADEDMODULES", "blast/12.3:blast/11.1");
         assert_eq!(false, is_module_loaded("", false));
         // FIXME this should be false
@@ -1136,4 +1525,50 @@ mod tests {
         );
         assert_eq!(false, is_module_loaded("python2", false));
     }
+    #[test]
+    fn _hierarchy() {
+        let _env = lock_env();
+        // core has gcc, the gcc folder has the modules built with gcc
+        let root = env::temp_dir().join(format!("rsmodules-test-hierarchy-{}", process::id()));
+        let core = root.join("core");
+        let gcc = root.join("gcc12");
+        fs::create_dir_all(core.join("gcc")).unwrap();
+        fs::create_dir_all(gcc.join("blast")).unwrap();
+        fs::write(core.join("gcc/12.1"), "").unwrap();
+        fs::write(gcc.join("blast/2.1"), "").unwrap();
+        fs::write(gcc.join("blast/2.2"), "").unwrap();
+
+        let core = core.to_string_lossy().to_string();
+        let gcc = gcc.to_string_lossy().to_string();
+        let search_path = vec![gcc.clone(), core.clone()];
+
+        assert_eq!(
+            Some(String::from("blast/2.1")),
+            find_in_search_path("blast/2.1", &search_path)
+        );
+        // another version is loaded instead
+        assert_eq!(Some(String::from("blast")), find_in_search_path("blast/1.0", &search_path));
+        assert_eq!(None, find_in_search_path("blast/2.1", std::slice::from_ref(&core)));
+        assert_eq!(None, find_in_search_path("hmmer/3.3", &search_path));
+
+        let inactive = vec![String::from("blast/2.1"), String::from("hmmer/3.3"), String::from("gcc/12.1")];
+        assert_eq!(
+            vec![(String::from("blast/2.1"), String::from("blast/2.1"))],
+            find_activated(&inactive, &[String::from("gcc/12.1")], &search_path)
+        );
+        assert_eq!(
+            vec![(String::from("gcc/12.1"), String::from("gcc/12.1"))],
+            find_activated(&inactive, &[], std::slice::from_ref(&core))
+        );
+
+        env::set_var(ENV_INACTIVE, "blast/2.1::hmmer/3.3");
+        assert_eq!(
+            vec![String::from("blast/2.1"), String::from("hmmer/3.3")],
+            get_inactive_modules()
+        );
+        env::remove_var(ENV_INACTIVE);
+        assert_eq!(true, get_inactive_modules().is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -23,20 +23,29 @@ SOFTWARE.
 */
 extern crate rhai;
 
-use self::rhai::{Engine, RegisterFn};
-use super::super::bold;
+use self::rhai::{Any, Engine, RegisterFn};
+use super::super::{bold, quote};
+use super::lua;
+use super::preview::{Environment, Preview};
+use super::tcl;
 use super::{echo, get_shell_info, Rsmodule};
 use is_executable::IsExecutable;
 use regex::Regex;
+use std::cell::RefCell;
 use std::env;
 use std::ffi::OsString;
 use std::fs::read_dir;
 use std::io::Write;
 use std::path::{is_separator, Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
+use bincode::rustc_serialize::{decode, encode};
+use bincode::SizeLimit;
 use chrono::{DateTime, Utc};
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use rustc_serialize::json;
 
 // WARNING: the scripts don't support tabbed indents in if else structures
 
@@ -72,90 +81,139 @@ impl Deprecated {
     }
 }
 
-lazy_static! {
-    static ref ENV_VARS: Mutex<Vec<(String, String)>> = Mutex::new(vec![]);
-    static ref COMMANDS: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref CONFLICT: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
-    //static ref DEPRECATED: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
-    pub static ref DEPRECATED: Mutex<Deprecated> = Mutex::new(Deprecated::new());
-    static ref README_PATH: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref README_MANPATH: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref INFO_DESCRIPTION: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref INFO_DEPRECATED: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref INFO_GENERAL: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref SOURCES: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref INFO_PATH: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref INFO_LD_LIBRARY_PATH: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref INFO_PYTHONPATH: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref INFO_PERL5LIB: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref INFO_BIN: Mutex<Vec<String>> = Mutex::new(vec![]);
-    static ref LOAD: Mutex<Vec<String>> = Mutex::new(vec![]);
+#[derive(RustcEncodable)]
+struct JsonVariable {
+    name: String,
+    value: String,
 }
 
-// lu! means lock().unwrap()
-fn init_vars_and_commands() {
-    lu!(ENV_VARS).clear();
-    lu!(COMMANDS).clear();
-    lu!(README_PATH).clear();
-    lu!(README_MANPATH).clear();
-    lu!(INFO_DESCRIPTION).clear();
-    lu!(INFO_DEPRECATED).clear();
-    lu!(INFO_GENERAL).clear();
-    lu!(SOURCES).clear();
-    lu!(INFO_PATH).clear();
-    lu!(INFO_LD_LIBRARY_PATH).clear();
-    lu!(INFO_PYTHONPATH).clear();
-    lu!(INFO_PERL5LIB).clear();
-    lu!(LOAD).clear();
-
-    CONFLICT.store(false, Ordering::Relaxed);
-    //DEPRECATED.store(false, Ordering::Relaxed);
-    let mut deprecated = lu!(DEPRECATED);
-    *deprecated = Deprecated::new();
+// what module info prints when the json shell is used
+#[derive(RustcEncodable)]
+struct JsonInfo {
+    name: String,
+    description: Vec<String>,
+    deprecated: String,
+    deprecated_state: String,
+    variables: Vec<JsonVariable>,
+    sources: Vec<String>,
+    path: Vec<String>,
+    ld_library_path: Vec<String>,
+    pythonpath: Vec<String>,
+    perl5lib: Vec<String>,
+    dependencies: Vec<String>,
+    binaries: Vec<String>,
 }
 
-fn add_to_env_vars(variable: &str, value: &str) {
-    lu!(ENV_VARS).push((variable.to_string(), value.to_string()));
+// a single change a modulefile made to the environment while loading
+// this is what we replay (in reverse) when the module gets unloaded
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+enum Change {
+    // variable, old value, new value
+    Set(String, Option<String>, String),
+    // variable, old value
+    Unset(String, Option<String>),
+    // variable, added path, true when prepended
+    PathAdd(String, String, bool),
+    // alias name, value
+    Alias(String, String),
+    // command given to system_unload
+    Command(String),
+    // variable, removed path, the path that was in front of it
+    PathRemove(String, String, Option<String>),
 }
 
-fn add_to_commands(data: &str) {
-    lu!(COMMANDS).push(data.to_string());
+// all the changes one loaded module made
+#[derive(RustcEncodable, RustcDecodable, Clone, Debug)]
+struct ModuleDelta {
+    name: String,
+    changes: Vec<Change>,
 }
 
-fn add_to_readme_manpath(data: &str) {
-    lu!(README_MANPATH).push(data.to_string());
-}
-
-fn add_to_readme_path(data: &str) {
-    lu!(README_PATH).push(data.to_string());
-}
-
-fn add_to_info_general(data: &str) {
-    lu!(INFO_GENERAL).push(data.to_string());
+lazy_static! {
+    // how deep we are in load() calls from modulefiles
+    static ref DEPENDENCY_DEPTH: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
 }
 
-fn add_to_sources(data: &str) {
-    lu!(SOURCES).push(data.to_string());
+// everything a modulefile does while it is evaluated, every evaluation
+// gets its own context so modulefiles can be evaluated at the same time
+#[derive(Debug, Clone)]
+pub struct Context {
+    delta: Vec<Change>,
+    dependencies: Vec<String>,
+    env_vars: Vec<(String, String)>,
+    commands: Vec<String>,
+    conflict: bool,
+    deprecated: Deprecated,
+    readme_path: Vec<String>,
+    readme_manpath: Vec<String>,
+    info_description: Vec<String>,
+    info_deprecated: Vec<String>,
+    info_general: Vec<String>,
+    sources: Vec<String>,
+    info_path: Vec<String>,
+    info_ld_library_path: Vec<String>,
+    info_pythonpath: Vec<String>,
+    info_perl5lib: Vec<String>,
+    info_bin: Vec<String>,
+    load: Vec<String>,
+    families: Vec<String>,
+    replaced: Vec<String>,
+    preview: Vec<String>,
+    // the dry run this evaluation is part of, the environment is left alone
+    dry_run: Option<Rc<RefCell<Preview>>>,
 }
 
-fn add_to_info_path(data: &str) {
-    lu!(INFO_PATH).push(data.to_string());
+impl Context {
+    pub fn new() -> Context {
+        Context {
+            delta: Vec::new(),
+            dependencies: Vec::new(),
+            env_vars: Vec::new(),
+            commands: Vec::new(),
+            conflict: false,
+            deprecated: Deprecated::new(),
+            readme_path: Vec::new(),
+            readme_manpath: Vec::new(),
+            info_description: Vec::new(),
+            info_deprecated: Vec::new(),
+            info_general: Vec::new(),
+            sources: Vec::new(),
+            info_path: Vec::new(),
+            info_ld_library_path: Vec::new(),
+            info_pythonpath: Vec::new(),
+            info_perl5lib: Vec::new(),
+            info_bin: Vec::new(),
+            load: Vec::new(),
+            families: Vec::new(),
+            replaced: Vec::new(),
+            preview: Vec::new(),
+            dry_run: None,
+        }
+    }
 }
 
-fn add_to_info_ld_library_path(data: &str) {
-    lu!(INFO_LD_LIBRARY_PATH).push(data.to_string());
+// registers a function that needs the context of the modulefile that is evaluated
+macro_rules! register_context_fn {
+    ($engine:expr, $context:expr, $name:expr, $function:ident($($arg:ident),*)) => {{
+        let context = Rc::clone(&$context);
+        $engine.register_fn($name, move |$($arg: String),*| $function(&mut context.borrow_mut(), $($arg),*));
+    }};
 }
 
-fn add_to_info_pythonpath(data: &str) {
-    lu!(INFO_PYTHONPATH).push(data.to_string());
+// sets a variable without recording it in the delta
+fn set_var(context: &mut Context, var: &str, val: &str) {
+    context.env_vars.push((var.to_string(), val.to_string()));
+    if context.dry_run.is_none() {
+        env::set_var(var, val);
+    }
 }
 
-fn add_to_info_perl5lib(data: &str) {
-    lu!(INFO_PERL5LIB).push(data.to_string());
+fn add_to_commands(context: &mut Context, data: &str) {
+    context.commands.push(data.to_string());
 }
 
-fn add_to_load(data: String) {
-    lu!(LOAD).push(data);
+fn add_to_info_general(context: &mut Context, data: &str) {
+    context.info_general.push(data.to_string());
 }
 
 // functions for load and unload
@@ -176,15 +234,16 @@ fn print(msg: String) {
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn source(wanted_shell: String, path: String) {
+fn source(context: &mut Context, wanted_shell: String, path: String) {
     let (shell, _) = get_shell_info();
     if shell == wanted_shell {
-        add_to_commands(&format!("source \"{}\"", path));
+        add_to_commands(context, &format!("source {}", quote(&path, &shell)));
+        context.preview.push(format!("source {}", path));
     }
 }
 
-fn info_bin(bin: String) {
-    lu!(INFO_BIN).push(bin.to_string());
+fn info_bin(context: &mut Context, bin: String) {
+    context.info_bin.push(bin);
 }
 
 // stub functions for unloading
@@ -240,72 +299,109 @@ pub fn set_alias_stub(name: String, val: String) {}
 pub fn is_loaded_stub(var: String) -> bool {
     true
 }
+pub fn modulepath_stub(_path: String) {}
+pub fn family_stub(_name: String) {}
 
 // unload functions
 
 #[allow(unused_variables)]
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn setenv_unload(var: String, val: String) {
-    unsetenv(var);
+fn setenv_unload(context: &mut Context, var: String, val: String) {
+    unsetenv(context, var);
+}
+
+fn family_unload(context: &mut Context, name: String) {
+    let var = family_var(&name);
+    if env::var(&var).is_ok() {
+        unsetenv(context, var);
+    }
+}
+
+// test functions, nothing outside of rsmodules is changed
+
+fn unsetenv_test(context: &mut Context, var: String) {
+    context.delta.push(Change::Unset(var.clone(), env::var(&var).ok()));
+    env::remove_var(&var);
+}
+
+fn set_alias_test(context: &mut Context, name: String, val: String) {
+    context.delta.push(Change::Alias(name, val));
+}
+
+// the files for every shell, not only for the one we run in
+fn source_test(context: &mut Context, wanted_shell: String, path: String) {
+    context.sources.push(format!("{} {}", wanted_shell, path));
+}
+
+fn use_modulepath_unload(context: &mut Context, path: String) {
+    remove_path(context, String::from("MODULEPATH"), path);
+}
+
+fn unuse_modulepath_unload(context: &mut Context, path: String) {
+    prepend_path(context, String::from("MODULEPATH"), path);
 }
 
 // readme functions
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn readme_path(var: String, val: String) {
+fn readme_path(context: &mut Context, var: String, val: String) {
     if var == "PATH" {
-        add_to_readme_path(&val);
+        context.readme_path.push(val);
     } else if var == "MANPATH" {
-        add_to_readme_manpath(&val);
+        context.readme_manpath.push(val);
     }
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn setenv_readme(var: String, val: String) {
+fn setenv_readme(context: &mut Context, var: String, val: String) {
     if var == "MANPATH" {
-        add_to_readme_manpath(&val);
+        context.readme_manpath.push(val);
     } else if var == "PATH" {
-        add_to_readme_path(&val);
+        context.readme_path.push(val);
     }
 }
 
 // info functions
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn setenv_info(var: String, val: String) {
-    add_to_info_general(&format!("{}={}", var, val));
+fn setenv_info(context: &mut Context, var: String, val: String) {
+    add_to_info_general(context, &format!("{}={}", var, val));
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn prepend_path_info(var: String, val: String) {
+fn prepend_path_info(context: &mut Context, var: String, val: String) {
     if var == "PATH" {
-        add_to_info_path(&val);
+        context.info_path.push(val);
     } else if var == "LD_LIBRARY_PATH" {
-        add_to_info_ld_library_path(&val);
+        context.info_ld_library_path.push(val);
     } else if var == "PYTHONPATH" {
-        add_to_info_pythonpath(&val);
+        context.info_pythonpath.push(val);
     } else if var == "PERL5LIB" {
-        add_to_info_perl5lib(&val);
+        context.info_perl5lib.push(val);
     } else {
-        add_to_info_general(&format!("{}={}", var, val));
+        add_to_info_general(context, &format!("{}={}", var, val));
     }
 }
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn append_path_info(var: String, val: String) {
+fn append_path_info(context: &mut Context, var: String, val: String) {
     if var == "PATH" {
-        add_to_info_path(&val);
+        context.info_path.push(val);
     } else if var == "LD_LIBRARY_PATH" {
-        add_to_info_ld_library_path(&val);
+        context.info_ld_library_path.push(val);
     } else if var == "PYTHONPATH" {
-        add_to_info_pythonpath(&val);
+        context.info_pythonpath.push(val);
     } else if var == "PERL5LIB" {
-        add_to_info_perl5lib(&val);
+        context.info_perl5lib.push(val);
     } else {
-        add_to_info_general(&format!("{}={}", var, val));
+        add_to_info_general(context, &format!("{}={}", var, val));
     }
 }
 
+fn use_modulepath_info(context: &mut Context, path: String) {
+    prepend_path_info(context, String::from("MODULEPATH"), path);
+}
+
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn deprecated_info(time: String) {
+fn deprecated_info(context: &mut Context, time: String) {
     let now = Utc::now().timestamp_millis();
 
     let mstime = format!("{} 00:00:00 +0000", time);
@@ -318,40 +414,52 @@ fn deprecated_info(time: String) {
     };
     let mstime = mstime.timestamp_millis();
 
-    let mut deprecated = lu!(DEPRECATED);
     if now > mstime {
-        lu!(INFO_DEPRECATED).push(format!(
+        context.info_deprecated.push(format!(
             "\n   This module was removed at {} and cannot be used anymore.",
             time
         ));
-        *deprecated = Deprecated::from(String::new(), time, DeprecatedState::After);
+        context.deprecated = Deprecated::from(String::new(), time, DeprecatedState::After);
     } else {
-        lu!(INFO_DEPRECATED).push(format!(
+        context.info_deprecated.push(format!(
             "This has been marked as deprecated and will be removed after {}.\n",
             time
         ));
-        *deprecated = Deprecated::from(String::new(), time, DeprecatedState::Before);
+        context.deprecated = Deprecated::from(String::new(), time, DeprecatedState::Before);
     }
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn source_info(wanted_shell: String, path: String) {
+fn source_info(context: &mut Context, wanted_shell: String, path: String) {
     let (shell, _) = get_shell_info();
     if shell == wanted_shell {
-        add_to_sources(&path);
+        context.sources.push(path);
     }
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn load_info(module: String) {
-    add_to_load(module);
+fn load_info(context: &mut Context, module: String) {
+    context.load.push(module);
 }
 // load functions
 
+// a variable or an alias with a name the shell can't use is skipped
+fn is_valid_name(name: &str) -> bool {
+    if super::super::is_valid_name(name) {
+        return true;
+    }
+
+    show_warning!("{} is not a valid name for a variable or an alias, it is skipped", name);
+    false
+}
+
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn setenv(var: String, val: String) {
-    add_to_env_vars(&var, &val);
-    env::set_var(&var, val);
+fn setenv(context: &mut Context, var: String, val: String) {
+    if !is_valid_name(&var) {
+        return;
+    }
+    context.delta.push(Change::Set(var.clone(), env::var(&var).ok(), val.clone()));
+    set_var(context, &var, &val);
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
@@ -360,25 +468,27 @@ fn is_loaded(var: String) -> bool {
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn unsetenv(var: String) {
+fn unsetenv(context: &mut Context, var: String) {
+    if !is_valid_name(&var) {
+        return;
+    }
+
     let (shell, _) = get_shell_info();
-    if shell == "bash" || shell == "zsh" {
-        add_to_commands(&format!("unset \"{}\"", var));
-    } else if shell == "perl" {
-        add_to_commands(&format!("undef \"{}\"", var));
-    } else if shell == "python" {
-        add_to_commands(&format!("os.environ[\"{}\"] = \"\";", var));
-        add_to_commands(&format!("del os.environ[\"{}\"];", var));
-    } else if shell == "r" {
-        add_to_commands(&format!("Sys.unsetenv(\"{}\")", var));
-    } else {
-        add_to_commands(&format!("unsetenv \"{}\"", var));
+    for line in super::super::unsetenv(&var, &shell).lines() {
+        add_to_commands(context, line);
+    }
+    context.delta.push(Change::Unset(var.clone(), env::var(&var).ok()));
+    if context.dry_run.is_none() {
+        env::remove_var(&var);
     }
-    env::remove_var(&var);
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn prepend_path(var: String, val: String) {
+fn prepend_path(context: &mut Context, var: String, val: String) {
+    if !is_valid_name(&var) {
+        return;
+    }
+
     let mut current_val: String = String::from("");
     let mut notfound: bool = false;
 
@@ -390,17 +500,21 @@ fn prepend_path(var: String, val: String) {
         }
     };
 
+    context.delta.push(Change::PathAdd(var.clone(), val.clone(), true));
+
     if notfound {
-        setenv(var, val);
+        set_var(context, &var, &val);
     } else {
-        let final_val = format!("{}:{}", val, current_val);
-        add_to_env_vars(&var, &final_val);
-        env::set_var(&var, format!("{}:{}", val, current_val));
+        set_var(context, &var, &format!("{}:{}", val, current_val));
     }
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn append_path(var: String, val: String) {
+fn append_path(context: &mut Context, var: String, val: String) {
+    if !is_valid_name(&var) {
+        return;
+    }
+
     let mut current_val: String = String::from("");
     let mut notfound: bool = false;
 
@@ -412,17 +526,21 @@ fn append_path(var: String, val: String) {
         }
     };
 
+    context.delta.push(Change::PathAdd(var.clone(), val.clone(), false));
+
     if notfound {
-        setenv(var, val);
+        set_var(context, &var, &val);
     } else {
-        let final_val = format!("{}:{}", current_val, val);
-        add_to_env_vars(&var, &final_val);
-        env::set_var(&var, format!("{}:{}", current_val, val));
+        set_var(context, &var, &format!("{}:{}", current_val, val));
     }
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn remove_path(var: String, val: String) {
+fn remove_path(context: &mut Context, var: String, val: String) {
+    if !is_valid_name(&var) {
+        return;
+    }
+
     let current_val: String;
 
     match env::var(&var) {
@@ -434,52 +552,91 @@ fn remove_path(var: String, val: String) {
     };
 
     let mut values: Vec<&str> = current_val.split(':').collect();
+    // only the entry and its neighbour are remembered, not the whole variable
+    if let Some(position) = values.iter().position(|&x| x == val) {
+        let previous = position.checked_sub(1).map(|previous| values[previous].to_string());
+        context.delta.push(Change::PathRemove(var.clone(), val.clone(), previous));
+    }
     values.retain(|&x| x != val);
 
     let result = values.join(":");
 
-    add_to_env_vars(&var, &result);
-    env::set_var(&var, result);
+    set_var(context, &var, &result);
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn unset_alias(name: String, val: String) {
+fn unset_alias(context: &mut Context, name: String, _val: String) {
+    if !is_valid_name(&name) {
+        return;
+    }
+
     let (shell, _) = get_shell_info();
-    if shell == "bash" || shell == "zsh" {
-        add_to_commands(&format!("unalias \"{}\"", name));
-    } else if shell == "tcsh" || shell == "csh" {
-        add_to_commands(&format!("unalias \"{}={}\"", name, val));
+    if let Some(unalias) = super::super::unalias(&name, &shell) {
+        add_to_commands(context, &unalias);
     }
+    context.preview.push(format!("unalias {}", name));
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn set_alias(name: String, val: String) {
+fn set_alias(context: &mut Context, name: String, val: String) {
+    if !is_valid_name(&name) {
+        return;
+    }
+
+    context.delta.push(Change::Alias(name.clone(), val.clone()));
     let (shell, _) = get_shell_info();
-    if shell != "python" && shell != "perl" {
-        add_to_commands(&format!("alias {}=\"{}\"", name, val));
+    if let Some(alias) = super::super::alias(&name, &val, &shell) {
+        add_to_commands(context, &alias);
     }
+    context.preview.push(format!("alias {}={}", name, val));
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn system(cmd: String) {
+fn system(context: &mut Context, cmd: String) {
     let (shell, _) = get_shell_info();
     if shell != "python" && shell != "perl" {
-        add_to_commands(&cmd);
+        add_to_commands(context, &cmd);
+        context.preview.push(format!("system {}", cmd));
     }
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn system_unload(cmd: String) {
+fn system_unload(context: &mut Context, cmd: String) {
     let (shell, _) = get_shell_info();
     if shell != "python" && shell != "perl" {
-        add_to_commands(&cmd);
+        add_to_commands(context, &cmd);
+        context.preview.push(format!("system {}", cmd));
     }
 }
 
+// hierarchical modules: a compiler can make the modules that are
+// built with it available by adding a path to $MODULEPATH
+fn use_modulepath(context: &mut Context, path: String) {
+    prepend_path(context, String::from("MODULEPATH"), path);
+}
+
+fn unuse_modulepath(context: &mut Context, path: String) {
+    remove_path(context, String::from("MODULEPATH"), path);
+}
+
+// when loading we don't run system_unload, but we remember it
+// so it can be run when the module gets unloaded
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn load(module: String) {
+fn system_unload_delta(context: &mut Context, cmd: String) {
+    context.delta.push(Change::Command(cmd));
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
+fn load(context: &mut Context, module: String) {
     let (shell, _) = get_shell_info();
 
+    // a module that isn't loaded yet, or that was loaded because another
+    // module needed it, becomes a dependency of the module we are loading
+    // load("gcc@12.*") can be satisfied by any loaded gcc version
+    let name = super::version::name(&module);
+    let loaded = super::get_other_version_of_loaded_module(name);
+    let is_dependency = loaded.is_empty() || is_dependency(&loaded);
+
     let modulepaths = super::get_module_paths(false);
     let mut rsmod_command: Rsmodule = Rsmodule {
         cmd: "load",
@@ -488,12 +645,21 @@ fn load(module: String) {
         search_path: &modulepaths,
         shell: &shell,
         shell_width: 80,
+        dry_run: context.dry_run.clone(),
     };
+    // the dependency is evaluated with its own context, ours is left alone
+    DEPENDENCY_DEPTH.fetch_add(1, Ordering::Relaxed);
     super::command(&mut rsmod_command);
+    DEPENDENCY_DEPTH.fetch_sub(1, Ordering::Relaxed);
+
+    let loaded = super::get_other_version_of_loaded_module(name);
+    if is_dependency && !loaded.is_empty() {
+        context.dependencies.push(loaded);
+    }
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn deprecated(time: String) {
+fn deprecated(context: &mut Context, time: String) {
     let now = Utc::now().timestamp_millis();
 
     let mstime = format!("{} 00:00:00 +0000", time);
@@ -506,16 +672,15 @@ fn deprecated(time: String) {
     };
     let mstime = mstime.timestamp_millis();
 
-    let mut deprecated = lu!(DEPRECATED);
     if now > mstime {
-        *deprecated = Deprecated::from(String::new(), time, DeprecatedState::After);
+        context.deprecated = Deprecated::from(String::new(), time, DeprecatedState::After);
     } else {
-        *deprecated = Deprecated::from(String::new(), time, DeprecatedState::Before);
+        context.deprecated = Deprecated::from(String::new(), time, DeprecatedState::Before);
     }
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn conflict(module: String) {
+fn conflict(context: &mut Context, module: String) {
     if super::is_module_loaded(module.as_ref(), false) {
         let (shell, _) = get_shell_info();
 
@@ -545,12 +710,50 @@ fn conflict(module: String) {
             echo(&format!("{}module unload {}", spaces, bold_module), shell);
             echo("", shell);
         }
-        CONFLICT.store(true, Ordering::Relaxed);
+        context.conflict = true;
+    }
+}
+
+// the variable that remembers which module of a family is loaded
+// family("compiler") -> RSMODULES_FAMILY_COMPILER
+pub fn family_var(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}{}", super::ENV_FAMILY, name)
+}
+
+// the setup scripts of the site set $RSMODULES_SWAP_FAMILY, when it is no
+// the module of a family that is already loaded has to be unloaded first
+fn swap_family() -> bool {
+    !env::var(super::ENV_SWAP_FAMILY).map(|swap| swap == "no").unwrap_or(false)
+}
+
+// only one module of a family (compiler, mpi, ...) can be loaded, the module that is
+// already loaded is replaced, unless the site doesn't swap families
+fn family(context: &mut Context, name: String) {
+    if let Ok(other) = env::var(family_var(&name)) {
+        if !other.is_empty() && super::is_module_loaded(&other, false) {
+            if !swap_family() {
+                conflict(context, other);
+                return;
+            }
+            unload(context, other.clone());
+            context.replaced.push(other);
+        }
     }
+    context.families.push(name);
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn unload(module: String) {
+fn unload(context: &mut Context, module: String) {
     let (shell, _) = get_shell_info();
     let shell: &String = &shell;
     let modulepaths = super::get_module_paths(false);
@@ -561,20 +764,46 @@ fn unload(module: String) {
         search_path: &modulepaths,
         shell,
         shell_width: 80,
+        dry_run: context.dry_run.clone(),
     };
     super::command(&mut rsmod_command);
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn description(desc: String) {
-    lu!(INFO_DESCRIPTION).push(desc.replace("\"", "\\\""));
+fn description(context: &mut Context, desc: String) {
+    context.info_description.push(desc);
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(clippy::needless_pass_by_value))]
-fn description_cache(desc: String) {
-    add_to_info_general(&desc);
+fn description_cache(context: &mut Context, desc: String) {
+    add_to_info_general(context, &desc);
 }
 
+// the functions a modulefile can call, the ones that register_stub_fn registers
+pub static FUNCTIONS: &[&str] = &[
+    "setenv",
+    "unsetenv",
+    "prepend_path",
+    "append_path",
+    "remove_path",
+    "system",
+    "system_unload",
+    "load",
+    "conflict",
+    "deprecated",
+    "unload",
+    "getenv",
+    "description",
+    "set_alias",
+    "is_loaded",
+    "print",
+    "source",
+    "add_bin_to_info",
+    "use_modulepath",
+    "unuse_modulepath",
+    "family",
+];
+
 pub fn register_stub_fn(engine: &mut Engine) {
     engine.register_fn("setenv", setenv_stub);
     engine.register_fn("unsetenv", unsetenv_stub);
@@ -594,113 +823,511 @@ pub fn register_stub_fn(engine: &mut Engine) {
     engine.register_fn("print", print_stub);
     engine.register_fn("source", source_stub);
     engine.register_fn("add_bin_to_info", info_bin_stub);
+    engine.register_fn("use_modulepath", modulepath_stub);
+    engine.register_fn("unuse_modulepath", modulepath_stub);
+    engine.register_fn("family", family_stub);
 }
 
-pub fn run(path: &PathBuf, action: &str) {
+pub fn run(path: &PathBuf, action: &str) -> Context {
+    run_with(path, action, None)
+}
+
+// dry_run is the dry run the evaluation is part of, if any
+pub fn run_with(path: &PathBuf, action: &str, dry_run: Option<Rc<RefCell<Preview>>>) -> Context {
+    let mut context = Context::new();
+    context.dry_run = dry_run;
+    let context = Rc::new(RefCell::new(context));
     let mut engine = Engine::new();
 
     register_stub_fn(&mut engine);
-    init_vars_and_commands();
 
     if action == "unload" {
         // for unloading, we swap some functions
         // prepand_path and append_path are just remove_path
         // setenv should be an alternative to unsetenv
         // the others arent used
-        engine.register_fn("setenv", setenv_unload);
-        engine.register_fn("prepend_path", remove_path);
-        engine.register_fn("append_path", remove_path);
-        engine.register_fn("system_unload", system_unload);
-        engine.register_fn("set_alias", unset_alias);
-    } else if action == "load" {
-        engine.register_fn("setenv", setenv);
-        engine.register_fn("unsetenv", unsetenv);
-        engine.register_fn("prepend_path", prepend_path);
-        engine.register_fn("append_path", append_path);
-        engine.register_fn("remove_path", remove_path);
-        engine.register_fn("system", system);
-        engine.register_fn("load", load);
-        engine.register_fn("conflict", conflict);
-        engine.register_fn("deprecated", deprecated);
-        engine.register_fn("unload", unload);
+        register_context_fn!(engine, context, "setenv", setenv_unload(var, val));
+        register_context_fn!(engine, context, "prepend_path", remove_path(var, val));
+        register_context_fn!(engine, context, "append_path", remove_path(var, val));
+        register_context_fn!(engine, context, "system_unload", system_unload(cmd));
+        register_context_fn!(engine, context, "set_alias", unset_alias(name, val));
+        register_context_fn!(engine, context, "use_modulepath", use_modulepath_unload(path));
+        register_context_fn!(engine, context, "unuse_modulepath", unuse_modulepath_unload(path));
+        register_context_fn!(engine, context, "family", family_unload(name));
+    } else if action == "load" || action == "test" {
+        register_context_fn!(engine, context, "setenv", setenv(var, val));
+        register_context_fn!(engine, context, "unsetenv", unsetenv(var));
+        register_context_fn!(engine, context, "prepend_path", prepend_path(var, val));
+        register_context_fn!(engine, context, "append_path", append_path(var, val));
+        register_context_fn!(engine, context, "remove_path", remove_path(var, val));
+        register_context_fn!(engine, context, "system", system(cmd));
+        register_context_fn!(engine, context, "load", load(module));
+        register_context_fn!(engine, context, "conflict", conflict(module));
+        register_context_fn!(engine, context, "deprecated", deprecated(time));
+        register_context_fn!(engine, context, "unload", unload(module));
         engine.register_fn("getenv", getenv);
-        engine.register_fn("set_alias", set_alias);
+        register_context_fn!(engine, context, "set_alias", set_alias(name, val));
         engine.register_fn("is_loaded", is_loaded);
         engine.register_fn("print", print);
-        engine.register_fn("source", source);
+        register_context_fn!(engine, context, "source", source(wanted_shell, path));
+        register_context_fn!(engine, context, "system_unload", system_unload_delta(cmd));
+        register_context_fn!(engine, context, "use_modulepath", use_modulepath(path));
+        register_context_fn!(engine, context, "unuse_modulepath", unuse_modulepath(path));
+        register_context_fn!(engine, context, "family", family(name));
+        if action == "test" {
+            // the modules it loads are only remembered
+            register_context_fn!(engine, context, "load", load_info(module));
+            engine.register_fn("unload", unload_stub);
+            register_context_fn!(engine, context, "unsetenv", unsetenv_test(var));
+            register_context_fn!(engine, context, "set_alias", set_alias_test(name, val));
+            register_context_fn!(engine, context, "source", source_test(wanted_shell, path));
+        }
     } else if action == "info" {
-        engine.register_fn("setenv", setenv_info);
-        engine.register_fn("prepend_path", prepend_path_info);
-        engine.register_fn("append_path", append_path_info);
-        engine.register_fn("load", load_info);
-        engine.register_fn("deprecated", deprecated_info);
-        engine.register_fn("description", description);
+        register_context_fn!(engine, context, "setenv", setenv_info(var, val));
+        register_context_fn!(engine, context, "prepend_path", prepend_path_info(var, val));
+        register_context_fn!(engine, context, "append_path", append_path_info(var, val));
+        register_context_fn!(engine, context, "load", load_info(module));
+        register_context_fn!(engine, context, "deprecated", deprecated_info(time));
+        register_context_fn!(engine, context, "description", description(desc));
         engine.register_fn("is_loaded", is_loaded);
-        engine.register_fn("source", source_info);
-        engine.register_fn("add_bin_to_info", info_bin);
+        register_context_fn!(engine, context, "source", source_info(wanted_shell, path));
+        register_context_fn!(engine, context, "add_bin_to_info", info_bin(bin));
+        register_context_fn!(engine, context, "use_modulepath", use_modulepath_info(path));
     } else if action == "description" {
-        engine.register_fn("description", description_cache);
-        engine.register_fn("set_alias", set_alias);
+        register_context_fn!(engine, context, "description", description_cache(desc));
+        register_context_fn!(engine, context, "set_alias", set_alias(name, val));
         engine.register_fn("is_loaded", is_loaded);
     } else if action == "readme" || action == "cd" {
-        engine.register_fn("setenv", setenv_readme);
-        engine.register_fn("prepend_path", readme_path);
-        engine.register_fn("append_path", readme_path);
-        engine.register_fn("set_alias", set_alias);
+        register_context_fn!(engine, context, "setenv", setenv_readme(var, val));
+        register_context_fn!(engine, context, "prepend_path", readme_path(var, val));
+        register_context_fn!(engine, context, "append_path", readme_path(var, val));
+        register_context_fn!(engine, context, "set_alias", set_alias(name, val));
         engine.register_fn("is_loaded", is_loaded);
     } else if action == "deprecated" {
-        engine.register_fn("deprecated", deprecated);
+        register_context_fn!(engine, context, "deprecated", deprecated(time));
+    }
+
+    if tcl::is_modulefile(path) || lua::is_modulefile(path) {
+        let mut call = |name: &str, args: &[String]| call_registered(&engine, name, args);
+        // a test evaluates the modulefile like a load
+        let action = if action == "test" { "load" } else { action };
+        let result = if lua::is_modulefile(path) {
+            lua::run(path, action, &mut call)
+        } else {
+            tcl::run(path, action, &mut call)
+        };
+        if let Err(e) = result {
+            show_warning!("modulescript error: {} ({})", e, path.to_string_lossy().into_owned());
+        }
+    } else {
+        // FIXME: this error is vague when a module exists in the cache but not on disk
+        match engine.eval_file::<String>(path.to_string_lossy().into_owned().as_ref()) {
+            Ok(result) => println!("{}", result),
+            Err(e) => {
+                // if e.to_string() != "Cast of output failed" {
+                if !e.to_string().starts_with("Cast of output failed") {
+                    show_warning!(
+                        "modulescript error: {} ({})",
+                        e.to_string(),
+                        path.to_string_lossy().into_owned()
+                    );
+                }
+            }
+        }
+    }
+
+    // the registered functions hold a reference to the context until the engine is gone
+    drop(engine);
+    match Rc::try_unwrap(context) {
+        Ok(context) => context.into_inner(),
+        Err(context) => context.borrow().clone(),
+    }
+}
+
+// calls the function with this name and these arguments, returns its result
+pub type Call<'a> = dyn FnMut(&str, &[String]) -> String + 'a;
+
+// tcl and lua modulefiles call the functions that are registered for the rhai ones
+fn call_registered(engine: &Engine, name: &str, args: &[String]) -> String {
+    let mut args: Vec<String> = args.to_vec();
+    let args: Vec<&mut dyn Any> = args.iter_mut().map(|arg| arg as &mut dyn Any).collect();
+    match engine.call_fn_raw(name.to_string(), args) {
+        Ok(result) => {
+            if let Some(result) = result.downcast_ref::<String>() {
+                result.to_string()
+            } else if let Some(result) = result.downcast_ref::<bool>() {
+                String::from(if *result { "1" } else { "0" })
+            } else {
+                String::new()
+            }
+        }
+        Err(_) => String::new(),
+    }
+}
+
+fn get_deltas() -> Vec<ModuleDelta> {
+    let encoded = match env::var(super::ENV_DELTA) {
+        Ok(encoded) => encoded,
+        Err(_) => return Vec::new(),
+    };
+
+    if encoded.is_empty() {
+        return Vec::new();
     }
 
-    // FIXME: this error is vague when a module exists in the cache but not on disk
-    match engine.eval_file::<String>(path.to_string_lossy().into_owned().as_ref()) {
-        Ok(result) => println!("{}", result),
+    let decoded = match encoded.from_base64() {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            show_warning!("Cannot decode ${} ({})", super::ENV_DELTA, e);
+            return Vec::new();
+        }
+    };
+
+    match decode(&decoded) {
+        Ok(deltas) => deltas,
         Err(e) => {
-            // if e.to_string() != "Cast of output failed" {
-            if !e.to_string().starts_with("Cast of output failed") {
-                show_warning!(
-                    "modulescript error: {} ({})",
-                    e.to_string(),
-                    path.to_string_lossy().into_owned()
-                );
+            show_warning!("Cannot decode ${} ({})", super::ENV_DELTA, e);
+            Vec::new()
+        }
+    }
+}
+
+// replaces the recorded changes of a module, None removes them
+fn save_delta(context: &mut Context, selected_module: &str, changes: Option<Vec<Change>>) {
+    let mut deltas = get_deltas();
+    let count = deltas.len();
+    deltas.retain(|delta| delta.name != selected_module);
+
+    if let Some(changes) = changes {
+        deltas.push(ModuleDelta {
+            name: selected_module.to_string(),
+            changes,
+        });
+    } else if count == deltas.len() {
+        return;
+    }
+
+    if deltas.is_empty() {
+        unsetenv(context, super::ENV_DELTA.to_string());
+        return;
+    }
+
+    match encode(&deltas, SizeLimit::Infinite) {
+        Ok(encoded) => set_var(context, super::ENV_DELTA, &encoded.to_base64(STANDARD)),
+        Err(e) => show_warning!("Cannot encode ${} ({})", super::ENV_DELTA, e),
+    }
+}
+
+// removes a path entry that was added when loading, prepended entries are
+// searched from the front, appended ones from the back
+fn remove_added_path(context: &mut Context, var: &str, val: &str, prepended: bool) {
+    let current_val = match env::var(var) {
+        Ok(res) => res,
+        Err(_) => return,
+    };
+
+    let mut values: Vec<&str> = current_val.split(':').collect();
+    let position = if prepended {
+        values.iter().position(|&x| x == val)
+    } else {
+        values.iter().rposition(|&x| x == val)
+    };
+
+    if let Some(position) = position {
+        values.remove(position);
+        if values.is_empty() {
+            unsetenv(context, var.to_string());
+        } else {
+            set_var(context, var, &values.join(":"));
+        }
+    }
+}
+
+// puts a path entry that was removed when loading back behind the entry that
+// was in front of it, unless something else added it again in the meantime
+fn restore_removed_path(context: &mut Context, var: &str, val: &str, previous: Option<&str>) {
+    let current_val = env::var(var).unwrap_or_default();
+
+    let mut values: Vec<&str> = current_val.split(':').filter(|x| !x.is_empty()).collect();
+    if values.contains(&val) {
+        return;
+    }
+
+    let position = previous
+        .and_then(|previous| values.iter().position(|&x| x == previous))
+        .map(|position| position + 1)
+        .unwrap_or(0);
+    values.insert(position, val);
+    set_var(context, var, &values.join(":"));
+}
+
+// unloads a module by undoing the changes it made while loading, in reverse order
+// returns None when nothing was recorded for this module (loaded by an older
+// version of rsmodules), then the modulefile has to be evaluated instead
+pub fn run_delta(selected_module: &str, dry_run: Option<Rc<RefCell<Preview>>>) -> Option<Context> {
+    let delta = get_deltas().into_iter().find(|delta| delta.name == selected_module)?;
+
+    let mut context = Context::new();
+    context.dry_run = dry_run;
+    // what undoing the changes changes, for a dry run
+    let mut undone: Vec<Change> = Vec::new();
+
+    for change in delta.changes.into_iter().rev() {
+        match change {
+            Change::Set(var, old, new) => {
+                // somebody else changed it in the meantime, leave it alone
+                if env::var(&var).ok().as_ref() != Some(&new) {
+                    continue;
+                }
+                match old {
+                    Some(old) => {
+                        set_var(&mut context, &var, &old);
+                        undone.push(Change::Set(var, Some(new), old));
+                    }
+                    None => {
+                        unsetenv(&mut context, var.clone());
+                        undone.push(Change::Unset(var, Some(new)));
+                    }
+                }
+            }
+            Change::Unset(var, old) => {
+                if let Some(old) = old {
+                    if env::var(&var).is_err() {
+                        set_var(&mut context, &var, &old);
+                        undone.push(Change::Set(var, None, old));
+                    }
+                }
+            }
+            Change::PathAdd(var, val, prepended) => {
+                remove_added_path(&mut context, &var, &val, prepended);
+                undone.push(Change::PathRemove(var, val, None));
             }
+            Change::Alias(name, val) => unset_alias(&mut context, name, val),
+            Change::Command(cmd) => system_unload(&mut context, cmd),
+            Change::PathRemove(var, val, previous) => {
+                restore_removed_path(&mut context, &var, &val, previous.as_deref());
+                undone.push(Change::PathAdd(var, val, previous.is_none()));
+            }
+        }
+    }
+
+    context.delta = undone;
+
+    Some(context)
+}
+
+// applies the changes of a modulefile to a copy of the environment, this is
+// what the environment would be after a dry run. A path entry that is put back
+// behind another one is appended, a dry run only shows that it is added
+pub fn apply_delta(context: &Context, environment: &mut Environment) {
+    for change in &context.delta {
+        match change {
+            Change::Set(var, _, new) => {
+                environment.insert(var.to_string(), new.to_string());
+            }
+            Change::Unset(var, _) => {
+                environment.remove(var);
+            }
+            Change::PathAdd(var, val, prepended) => {
+                let current = environment.get(var).cloned().unwrap_or_default();
+                let value = if current.is_empty() {
+                    val.to_string()
+                } else if *prepended {
+                    format!("{}:{}", val, current)
+                } else {
+                    format!("{}:{}", current, val)
+                };
+                environment.insert(var.to_string(), value);
+            }
+            Change::PathRemove(var, val, _) => {
+                if let Some(current) = environment.get_mut(var) {
+                    let values: Vec<&str> = current.split(':').filter(|entry| entry != val).collect();
+                    *current = values.join(":");
+                }
+            }
+            Change::Alias(_, _) | Change::Command(_) => {}
+        }
+    }
+}
+
+// returns true when we are loading a module because a modulefile asked for it
+pub fn is_loading_dependency() -> bool {
+    DEPENDENCY_DEPTH.load(Ordering::Relaxed) > 0
+}
+
+// the modules that were loaded as a dependency and the modules that needed them
+// stored as: module=needed_by,needed_by:module=needed_by
+fn get_dependencies() -> Vec<(String, Vec<String>)> {
+    match env::var(super::ENV_DEPENDENCIES) {
+        Ok(encoded) => parse_dependencies(&encoded),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn parse_dependencies(encoded: &str) -> Vec<(String, Vec<String>)> {
+    let mut dependencies: Vec<(String, Vec<String>)> = Vec::new();
+    for entry in encoded.split(':') {
+        let mut parts = entry.splitn(2, '=');
+        let name = parts.next().unwrap_or("");
+        let needed_by: Vec<String> = parts
+            .next()
+            .unwrap_or("")
+            .split(',')
+            .filter(|module| !module.is_empty())
+            .map(|module| module.to_string())
+            .collect();
+
+        if !name.is_empty() && !needed_by.is_empty() {
+            dependencies.push((name.to_string(), needed_by));
+        }
+    }
+
+    dependencies
+}
+
+pub fn is_dependency(module: &str) -> bool {
+    get_dependencies().iter().any(|dependency| dependency.0 == module)
+}
+
+fn encode_dependencies(dependencies: &[(String, Vec<String>)]) -> String {
+    let encoded: Vec<String> = dependencies
+        .iter()
+        .map(|dependency| format!("{}={}", dependency.0, dependency.1.join(",")))
+        .collect();
+    encoded.join(":")
+}
+
+// adds the dependencies the modulefile of selected_module loaded, when the
+// user loaded or unloaded selected_module it isn't a dependency (anymore)
+fn save_dependencies(context: &mut Context, selected_module: &str, explicit: bool) {
+    let mut dependencies = get_dependencies();
+
+    // a module that was loaded as a dependency is promoted when the user loads it,
+    // unloading its parents doesn't unload it anymore
+    if explicit {
+        dependencies.retain(|dependency| dependency.0 != selected_module);
+    }
+
+    for module in context.dependencies.drain(..) {
+        match dependencies.iter_mut().find(|dependency| dependency.0 == module) {
+            Some(dependency) => {
+                if !dependency.1.iter().any(|needed_by| needed_by == selected_module) {
+                    dependency.1.push(selected_module.to_string());
+                }
+            }
+            None => dependencies.push((module, vec![selected_module.to_string()])),
+        }
+    }
+
+    let encoded = encode_dependencies(&dependencies);
+    let current = env::var(super::ENV_DEPENDENCIES).unwrap_or_default();
+    if encoded == current {
+        return;
+    }
+
+    if encoded.is_empty() {
+        unsetenv(context, super::ENV_DEPENDENCIES.to_string());
+    } else {
+        set_var(context, super::ENV_DEPENDENCIES, &encoded);
+    }
+}
+
+// returns the dependencies of module that are not needed anymore by any loaded module
+pub fn get_unneeded_dependencies(module: &str) -> Vec<String> {
+    let mut unneeded: Vec<String> = Vec::new();
+
+    for (name, needed_by) in get_dependencies().into_iter().rev() {
+        // module itself doesn't count, a dry run leaves it in $LOADEDMODULES
+        if needed_by.iter().any(|needed| needed == module)
+            && super::is_module_loaded(&name, true)
+            && !needed_by
+                .iter()
+                .any(|needed| needed != module && super::is_module_loaded(needed, true))
+        {
+            unneeded.push(name);
         }
     }
+
+    unneeded
 }
 
-pub fn get_readme_paths() -> Vec<String> {
-    let paths: Vec<String> = lu!(README_PATH).to_vec();
+// removes an unloaded module from the modules that need a dependency, whatever
+// unloaded it: module unload, purge or a change of $MODULEPATH
+pub fn prune_dependencies(module: &str, shell: &str) {
+    let mut dependencies = get_dependencies();
+    for dependency in dependencies.iter_mut() {
+        dependency.1.retain(|needed_by| needed_by != module);
+    }
+    dependencies.retain(|dependency| !dependency.1.is_empty());
 
-    paths
+    let encoded = encode_dependencies(&dependencies);
+    if encoded != env::var(super::ENV_DEPENDENCIES).unwrap_or_default() {
+        super::save_env(super::ENV_DEPENDENCIES, &encoded, shell);
+    }
 }
 
-pub fn get_readme_manpaths() -> Vec<String> {
-    let paths: Vec<String> = lu!(README_MANPATH).to_vec();
+pub fn get_readme_paths(context: &Context) -> Vec<String> {
+    context.readme_path.to_vec()
+}
+
+pub fn get_readme_manpaths(context: &Context) -> Vec<String> {
+    context.readme_manpath.to_vec()
+}
 
-    paths
+pub fn get_deprecated(context: &Context) -> Deprecated {
+    context.deprecated.clone()
 }
 
-pub fn get_description() -> Vec<String> {
+pub fn get_description(context: &Context) -> Vec<String> {
     let mut output: Vec<String> = Vec::new();
 
     // there can be multiple description calls, but
     // only store the first line of the description in
     // the cache file
-    let desc: Vec<String> = lu!(INFO_GENERAL).to_vec();
-    if !desc.is_empty() {
-        output.push(lu!(INFO_GENERAL).get(0).unwrap().to_string());
+    if let Some(desc) = context.info_general.first() {
+        output.push(desc.to_string());
     }
 
     output
 }
 
-pub fn get_output(selected_module: &str, action: &str, shell: &str) -> Vec<String> {
-    if CONFLICT.load(Ordering::Relaxed) {
+// what module spider and module which search in, this is stored in the cache file
+// after the modulefile was run in info mode
+pub fn get_metadata(context: &Context) -> (Vec<String>, Vec<String>, Vec<String>, Vec<String>) {
+    let description: Vec<String> = context.info_description.to_vec();
+
+    let mut variables: Vec<String> = context.info_general.to_vec();
+    for (var, paths) in &[
+        ("PATH", &context.info_path),
+        ("LD_LIBRARY_PATH", &context.info_ld_library_path),
+        ("PYTHONPATH", &context.info_pythonpath),
+        ("PERL5LIB", &context.info_perl5lib),
+    ] {
+        for path in paths.iter() {
+            variables.push(format!("{}={}", var, path));
+        }
+    }
+
+    let (mut binaries, _) = get_binaries(context);
+    binaries.sort();
+    binaries.dedup();
+
+    // module which also finds the executables that add_bin_to_info hides
+    let mut executables = get_executables_in_path(context);
+    executables.extend(context.info_bin.iter().cloned());
+    executables.sort();
+    executables.dedup();
+
+    (description, variables, binaries, executables)
+}
+
+pub fn get_output(context: &mut Context, selected_module: &str, action: &str, shell: &str, explicit: bool) -> Vec<String> {
+    if context.conflict {
         return Vec::new();
     }
 
     // don't load, this module is deprecated
-    let deprecated = lu!(DEPRECATED);
+    let deprecated = context.deprecated.clone();
     match deprecated.state {
         DeprecatedState::Not => {}
         DeprecatedState::Before => eprintln!(
@@ -719,40 +1346,37 @@ pub fn get_output(selected_module: &str, action: &str, shell: &str) -> Vec<Strin
     }
 
     if action == "unload" {
-        remove_path(super::ENV_LOADEDMODULES.to_string(), selected_module.to_string());
+        remove_path(context, super::ENV_LOADEDMODULES.to_string(), selected_module.to_string());
+        save_delta(context, selected_module, None);
+        // the unload that is part of a reload keeps its place in the dependencies
+        save_dependencies(context, selected_module, explicit);
     } else if action == "load" {
-        prepend_path(super::ENV_LOADEDMODULES.to_string(), selected_module.to_string());
+        // recorded in the delta, so unloading forgets the family again
+        for name in context.families.clone() {
+            setenv(context, family_var(&name), selected_module.to_string());
+        }
+        let changes: Vec<Change> = context.delta.drain(..).collect();
+        prepend_path(context, super::ENV_LOADEDMODULES.to_string(), selected_module.to_string());
+        save_delta(context, selected_module, Some(changes));
+        save_dependencies(context, selected_module, explicit);
     }
 
     // this part must be below the above part
     let mut output: Vec<String> = Vec::new();
 
-    for result in lu!(ENV_VARS).iter() {
-        let mut data: String = String::new();
-        if shell == "bash" || shell == "zsh" {
-            data = format!("export {}=\"{}\"", result.0, result.1);
-        } else if shell == "tcsh" || shell == "csh" {
-            data = format!("setenv {} \"{}\"", result.0, result.1);
-        } else if shell == "python" {
-            data = format!("os.environ[\"{}\"] = \"{}\";", result.0, result.1);
-        } else if shell == "r" {
-            // todo
-            // if result.0 = LD_LIBRARY_PATH
-            // loop through that folder and for each *.so
-            // dyn.load('lib.so')
-            data = format!("Sys.setenv({} = \"{}\")", result.0, result.1);
-        } else if shell == "perl" {
-            data = format!("$ENV{{{}}}=\"{}\";", result.0, result.1);
-        }
-
+    for (var, val) in context.env_vars.iter() {
+        // a module this modulefile loaded can have changed the variable
+        // after it was set here, so the current value is the one we want
+        let value = env::var(var).unwrap_or_else(|_| val.to_string());
         if shell != "noshell" {
-            output.push(data);
+            let data = super::super::setenv(var, &value, shell);
+            output.push(data.trim_end_matches('\n').to_string());
         }
     }
 
-    for line in lu!(COMMANDS).iter() {
+    for line in context.commands.iter() {
         if shell == "r" {
-            output.push(format!("system(\"{}\")", line.to_string()));
+            output.push(format!("system({})", quote(line, shell)));
         } else {
             output.push(line.to_string());
         }
@@ -761,176 +1385,130 @@ pub fn get_output(selected_module: &str, action: &str, shell: &str) -> Vec<Strin
     output
 }
 
+fn echo_line(line: &str, shell: &str) -> String {
+    format!("echo {}", quote(line, shell))
+}
+
 // this function prints information about the module
-pub fn get_info(shell: &str, module: &str) -> Vec<String> {
+pub fn get_info(context: &Context, shell: &str, module: &str) -> Vec<String> {
+    if shell == "json" {
+        return vec![get_info_json(context, module)];
+    }
+
     let mut output: Vec<String> = Vec::new();
     let mut got_output: bool = false;
-    let is_deprecated = is_deprecated();
+    let is_deprecated = is_deprecated(context);
 
     let tmp = format!("= {} =", module);
     let title_bold_module = bold(shell, &tmp);
 
     //output.push(format!("echo \"{:=^1$}\"", module.to_string(), module.len()+5));
     if !is_deprecated {
-        output.push(format!("echo \"{}\"", bold(shell, &"=".repeat(module.len() + 4))));
+        output.push(echo_line(&bold(shell, &"=".repeat(module.len() + 4)).to_string(), shell));
 
-        output.push(format!("echo \"{}\"", title_bold_module.to_string()));
-        output.push(format!("echo \"{}\"", bold(shell, &"=".repeat(module.len() + 4))));
+        output.push(echo_line(&title_bold_module.to_string(), shell));
+        output.push(echo_line(&bold(shell, &"=".repeat(module.len() + 4)).to_string(), shell));
         output.push(String::from("echo \"\""));
     }
 
-    if lu!(INFO_DEPRECATED).iter().len() > 0 {
+    if context.info_deprecated.iter().len() > 0 {
         got_output = true;
     }
-    for line in lu!(INFO_DEPRECATED).iter() {
-        let line = bold(shell, line);
-        if shell == "bash" || shell == "zsh" {
-            output.push(format!("echo $\"{}\"", line.to_string()));
-        } else if shell == "csh" || shell == "tcsh" {
-            output.push(format!("echo \"{}\"", line.to_string().replace("\n", "\\n")));
-        } else {
-            output.push(format!("echo \"{}\"", line.to_string()));
-        }
+    for line in context.info_deprecated.iter() {
+        output.push(echo_line(&bold(shell, line).to_string(), shell));
     }
 
     if !is_deprecated {
-        if lu!(INFO_DESCRIPTION).iter().len() > 0 {
+        if context.info_description.iter().len() > 0 {
             got_output = true;
         }
-        for line in lu!(INFO_DESCRIPTION).iter() {
-            if shell == "bash" || shell == "zsh" {
-                output.push(format!("echo $\"{}\"", line.to_string()));
-            } else if shell == "csh" || shell == "tcsh" {
-                output.push(format!("echo \"{}\"", line.to_string().replace("\n", "\\n")));
-            } else {
-                output.push(format!("echo \"{}\"", line.to_string()));
-            }
+        for line in context.info_description.iter() {
+            output.push(echo_line(line, shell));
         }
 
-        if lu!(INFO_GENERAL).iter().len() > 0 {
+        if context.info_general.iter().len() > 0 {
             output.push("echo \"\"".to_string());
-            output.push(format!("echo \"{}\"", bold(shell, "Sets the following variables: ")));
+            output.push(echo_line(&bold(shell, "Sets the following variables: ").to_string(), shell));
             got_output = true;
         }
-        for line in lu!(INFO_GENERAL).iter() {
-            output.push(format!("echo '{}'", line.to_string()));
+        for line in context.info_general.iter() {
+            output.push(echo_line(line, shell));
         }
 
-        if lu!(SOURCES).iter().len() > 0 {
+        if context.sources.iter().len() > 0 {
             output.push("echo \"\"".to_string());
-            output.push(format!("echo \"{}\"", bold(shell, "Sources the following files:")));
+            output.push(echo_line(&bold(shell, "Sources the following files:").to_string(), shell));
             got_output = true;
         }
-        for line in lu!(SOURCES).iter() {
-            output.push(format!("echo '{}'", line.to_string()));
+        for line in context.sources.iter() {
+            output.push(echo_line(line, shell));
         }
         // TODO: find man pages and let the user know
 
-        if lu!(INFO_PATH).iter().len() > 0 {
+        if context.info_path.iter().len() > 0 {
             output.push("echo \"\"".to_string());
-            output.push(format!("echo \"{}\"", bold(shell, "Executables can be found in: ")));
+            output.push(echo_line(&bold(shell, "Executables can be found in: ").to_string(), shell));
             got_output = true;
         }
-        for line in lu!(INFO_PATH).iter() {
-            output.push(format!("echo '{}'", line.to_string()));
+        for line in context.info_path.iter() {
+            output.push(echo_line(line, shell));
         }
 
-        if lu!(INFO_LD_LIBRARY_PATH).iter().len() > 0 {
+        if context.info_ld_library_path.iter().len() > 0 {
             output.push("echo \"\"".to_string());
-            output.push(format!("echo \"{}\"", bold(shell, "Libraries can be found in: ")));
+            output.push(echo_line(&bold(shell, "Libraries can be found in: ").to_string(), shell));
             got_output = true;
         }
-        for line in lu!(INFO_LD_LIBRARY_PATH).iter() {
-            output.push(format!("echo '{}'", line.to_string()));
+        for line in context.info_ld_library_path.iter() {
+            output.push(echo_line(line, shell));
         }
 
-        if lu!(INFO_PYTHONPATH).iter().len() > 0 {
+        if context.info_pythonpath.iter().len() > 0 {
             output.push("echo \"\"".to_string());
-            output.push(format!("echo \"{}\"", bold(shell, "\\$PYTHONPATH: ")));
+            output.push(echo_line(&bold(shell, "$PYTHONPATH: ").to_string(), shell));
             got_output = true;
         }
-        for line in lu!(INFO_PYTHONPATH).iter() {
-            output.push(format!("echo '{}'", line.to_string()));
+        for line in context.info_pythonpath.iter() {
+            output.push(echo_line(line, shell));
         }
 
-        if lu!(INFO_PERL5LIB).iter().len() > 0 {
+        if context.info_perl5lib.iter().len() > 0 {
             output.push("echo \"\"".to_string());
-            output.push(format!("echo \"{}\"", bold(shell, "\\$PERL5LIB: ")));
+            output.push(echo_line(&bold(shell, "$PERL5LIB: ").to_string(), shell));
             got_output = true;
         }
-        for line in lu!(INFO_PERL5LIB).iter() {
-            output.push(format!("echo '{}'", line.to_string()));
+        for line in context.info_perl5lib.iter() {
+            output.push(echo_line(line, shell));
         }
 
-        if lu!(LOAD).iter().len() > 0 {
+        if context.load.iter().len() > 0 {
             output.push("echo \"\"".to_string());
-            output.push(format!("echo \"{}\"", bold(shell, "Depends on: ")));
+            output.push(echo_line(&bold(shell, "Depends on: ").to_string(), shell));
             got_output = true;
         }
-        for line in lu!(LOAD).iter() {
-            output.push(format!("echo '{}'", line.to_string()));
+        for line in context.load.iter() {
+            output.push(echo_line(line, shell));
         }
 
+        let (bins, filtered) = get_binaries(context);
         let mut execs: Vec<String> = Vec::new();
-        let mut filtered: bool = false;
-        if lu!(INFO_BIN).is_empty() || env::var("RSMODULES_DONT_FILTER_INFO").is_ok() {
-            for line in lu!(INFO_PATH).iter() {
-                if Path::new(line).is_dir() {
-                    // if activate, activate.csh, activate.fish and activate_this.py exist
-                    // then we are in a python virtualenv, we can skip the typical python
-                    // binaries, we don't want to see them when we run 'module info program/arch/version'
-
-                    let is_virtual_env = is_virtual_env(PathBuf::from(line));
-
-                    let entries = match read_dir(line) {
-                        Ok(entry) => entry,
-                        Err(_) => continue,
-                    };
-
-                    for entry in entries {
-                        let path = match &entry {
-                            Ok(p) => p.path(),
-                            Err(_) => continue,
-                        };
-
-                        let file_name = match &entry {
-                            Ok(p) => p.file_name(),
-                            Err(_) => continue,
-                        };
-
-                        if is_python_binary(file_name) && is_virtual_env {
-                            continue;
-                        }
-
-                        if path.is_dir() {
-                            continue;
-                        }
-
-                        if path.is_executable() {
-                            execs.push(format!("echo '{}'", strip_dir(path.to_str().unwrap())));
-                            got_output = true;
-                        }
-                    }
-                }
-            }
-        } else {
-            let bins: Vec<String> = lu!(INFO_BIN).to_vec();
-            for bin in bins {
-                execs.push(format!("echo '{}'", bin));
-                got_output = true;
-                filtered = true;
-            }
+        for bin in bins {
+            execs.push(echo_line(&bin, shell));
+            got_output = true;
         }
 
         if !execs.is_empty() {
             output.push(String::from("echo ''"));
             if execs.len() > 1 {
-                output.push(format!(
-                    "echo \"{}\"",
-                    bold(shell, "Try one of these commands to run the program: ")
+                output.push(echo_line(
+                    &bold(shell, "Try one of these commands to run the program: ").to_string(),
+                    shell,
                 ));
             } else {
-                output.push(format!("echo \"{}\"", bold(shell, "Try this command to run the program: ")));
+                output.push(echo_line(
+                    &bold(shell, "Try this command to run the program: ").to_string(),
+                    shell,
+                ));
             }
         }
 
@@ -955,25 +1533,161 @@ pub fn get_info(shell: &str, module: &str) -> Vec<String> {
     output
 }
 
-// returns true if the deprecated AFTER state has been reached
-fn is_deprecated() -> bool {
-    let is_deprecated: bool;
-    {
-        // we ne need a different scope, or DEPRECATED is locked
-        let deprecated = lu!(DEPRECATED);
-        match deprecated.state {
-            DeprecatedState::After => {
-                is_deprecated = true;
-            }
-            DeprecatedState::Before => {
-                is_deprecated = false;
-            }
-            DeprecatedState::Not => {
-                is_deprecated = false;
+// returns the binaries this module provides, when add_bin_to_info was used
+// only those are returned and the second value is true (filtered)
+// the executables in the folders that the modulefile adds to $PATH
+fn get_executables_in_path(context: &Context) -> Vec<String> {
+    let mut execs: Vec<String> = Vec::new();
+
+    for line in context.info_path.iter() {
+        if Path::new(line).is_dir() {
+            // if activate, activate.csh, activate.fish and activate_this.py exist
+            // then we are in a python virtualenv, we can skip the typical python
+            // binaries, we don't want to see them when we run 'module info program/arch/version'
+
+            let is_virtual_env = is_virtual_env(PathBuf::from(line));
+
+            let entries = match read_dir(line) {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+
+            for entry in entries {
+                let path = match &entry {
+                    Ok(p) => p.path(),
+                    Err(_) => continue,
+                };
+
+                let file_name = match &entry {
+                    Ok(p) => p.file_name(),
+                    Err(_) => continue,
+                };
+
+                if is_python_binary(file_name) && is_virtual_env {
+                    continue;
+                }
+
+                if path.is_dir() {
+                    continue;
+                }
+
+                if path.is_executable() {
+                    execs.push(strip_dir(path.to_str().unwrap()));
+                }
             }
         }
     }
-    is_deprecated
+
+    execs
+}
+
+fn get_binaries(context: &Context) -> (Vec<String>, bool) {
+    let mut execs: Vec<String> = Vec::new();
+    let mut filtered: bool = false;
+    if context.info_bin.is_empty() || env::var("RSMODULES_DONT_FILTER_INFO").is_ok() {
+        execs = get_executables_in_path(context);
+    } else {
+        let bins: Vec<String> = context.info_bin.to_vec();
+        for bin in bins {
+            execs.push(bin);
+            filtered = true;
+        }
+    }
+
+    (execs, filtered)
+}
+
+// module info output when the json shell is used
+pub fn get_info_json(context: &Context, module: &str) -> String {
+    let mut variables: Vec<JsonVariable> = Vec::new();
+    for line in context.info_general.iter() {
+        let mut parts = line.splitn(2, '=');
+        let name = parts.next().unwrap_or("").to_string();
+        let value = parts.next().unwrap_or("").to_string();
+        variables.push(JsonVariable { name, value });
+    }
+
+    let deprecated = context.deprecated.clone();
+    let deprecated_state = match deprecated.state {
+        DeprecatedState::Not => "not",
+        DeprecatedState::Before => "before",
+        DeprecatedState::After => "after",
+    };
+
+    let (mut binaries, _) = get_binaries(context);
+    binaries.sort();
+
+    let info = JsonInfo {
+        name: module.to_string(),
+        description: context.info_description.to_vec(),
+        deprecated: deprecated.time,
+        deprecated_state: deprecated_state.to_string(),
+        variables,
+        sources: context.sources.to_vec(),
+        path: context.info_path.to_vec(),
+        ld_library_path: context.info_ld_library_path.to_vec(),
+        pythonpath: context.info_pythonpath.to_vec(),
+        perl5lib: context.info_perl5lib.to_vec(),
+        dependencies: context.load.to_vec(),
+        binaries,
+    };
+
+    json::encode(&info).unwrap()
+}
+
+// the modules of the same family that were unloaded to load this one
+pub fn get_replaced_modules(context: &Context) -> Vec<String> {
+    context.replaced.to_vec()
+}
+
+pub fn get_loads(context: &Context) -> Vec<String> {
+    context.load.to_vec()
+}
+
+pub fn get_sources(context: &Context) -> Vec<String> {
+    context.sources.to_vec()
+}
+
+pub fn get_aliases(context: &Context) -> Vec<(String, String)> {
+    context
+        .delta
+        .iter()
+        .filter_map(|change| match change {
+            Change::Alias(name, val) => Some((name.to_string(), val.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+// the commands system_unload will run, before get_output has saved them
+pub fn get_unload_commands(context: &Context) -> Vec<String> {
+    context
+        .delta
+        .iter()
+        .filter_map(|change| match change {
+            Change::Command(cmd) => Some(cmd.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+// the aliases, sourced files and commands of a dry run, in a readable form
+pub fn get_preview(context: &Context) -> Vec<String> {
+    context.preview.to_vec()
+}
+
+// false when get_output gave the shell nothing, because of a conflict or a removed module
+pub fn is_applied(context: &Context) -> bool {
+    !context.conflict && !is_deprecated(context)
+}
+
+// returns true if the deprecated AFTER state has been reached
+pub fn is_deprecated(context: &Context) -> bool {
+    match context.deprecated.state {
+        DeprecatedState::After => true,
+        DeprecatedState::Before => false,
+        DeprecatedState::Not => false,
+    }
 }
 
 fn is_virtual_env(path: PathBuf) -> bool {
@@ -1056,3 +1770,129 @@ fn strip_dir(fullname: &str) -> String {
         None => "".to_owned(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::lock_env;
+    use super::{
+        encode_dependencies, get_deltas, is_dependency, parse_dependencies, prepend_path, remove_path, run_delta, save_delta,
+        save_dependencies, setenv, swap_family, Change, Context,
+    };
+    use std::env;
+
+    // loads a module by hand: evaluate, record the delta
+    fn record(module: &str, context: &mut Context) {
+        let changes: Vec<Change> = context.delta.drain(..).collect();
+        save_delta(context, module, Some(changes));
+    }
+
+    #[test]
+    fn _delta() {
+        let _env = lock_env();
+        env::remove_var(super::super::ENV_DELTA);
+        env::set_var("RSMODULES_TEST_HOME", "/old");
+        env::set_var("RSMODULES_TEST_PATH", "/usr/bin:/remove/me:/bin");
+
+        let mut context = Context::new();
+        setenv(&mut context, String::from("RSMODULES_TEST_HOME"), String::from("/new"));
+        prepend_path(&mut context, String::from("RSMODULES_TEST_PATH"), String::from("/a/bin"));
+        remove_path(&mut context, String::from("RSMODULES_TEST_PATH"), String::from("/remove/me"));
+        record("a/1.0", &mut context);
+        assert_eq!("/a/bin:/usr/bin:/bin", env::var("RSMODULES_TEST_PATH").unwrap());
+
+        // only the path entries are recorded, never the whole variable
+        let deltas = get_deltas();
+        assert_eq!(1, deltas.len());
+        for change in &deltas[0].changes {
+            if let Change::Set(var, _, _) = change {
+                assert_eq!("RSMODULES_TEST_HOME", var);
+            }
+        }
+
+        // another module changes the same variables after this one
+        let mut context = Context::new();
+        prepend_path(&mut context, String::from("RSMODULES_TEST_PATH"), String::from("/b/bin"));
+        record("b/1.0", &mut context);
+
+        assert_eq!(true, run_delta("a/1.0", None).is_some());
+        assert_eq!("/old", env::var("RSMODULES_TEST_HOME").unwrap());
+        assert_eq!("/b/bin:/usr/bin:/remove/me:/bin", env::var("RSMODULES_TEST_PATH").unwrap());
+
+        // nothing recorded, the modulefile has to be evaluated
+        assert_eq!(true, run_delta("c/1.0", None).is_none());
+
+        // a value that was changed by somebody else is left alone
+        let mut context = Context::new();
+        setenv(&mut context, String::from("RSMODULES_TEST_HOME"), String::from("/c"));
+        record("c/1.0", &mut context);
+        env::set_var("RSMODULES_TEST_HOME", "/user");
+        run_delta("c/1.0", None);
+        assert_eq!("/user", env::var("RSMODULES_TEST_HOME").unwrap());
+
+        env::remove_var(super::super::ENV_DELTA);
+    }
+
+    #[test]
+    fn _parse_dependencies() {
+        assert_eq!(
+            vec![
+                (
+                    String::from("gcc/12.1"),
+                    vec![String::from("blast/2.1"), String::from("hmmer/3.3")]
+                ),
+                (String::from("zlib/1.2"), vec![String::from("gcc/12.1")]),
+            ],
+            parse_dependencies("gcc/12.1=blast/2.1,hmmer/3.3:zlib/1.2=gcc/12.1")
+        );
+        // entries without a module that needs them are dropped
+        assert_eq!(
+            vec![(String::from("zlib/1.2"), vec![String::from("gcc/12.1")])],
+            parse_dependencies("gcc/12.1=:=blast/2.1:perl:zlib/1.2=,gcc/12.1,")
+        );
+        assert_eq!(true, parse_dependencies("").is_empty());
+
+        let encoded = "gcc/12.1=blast/2.1,hmmer/3.3:zlib/1.2=gcc/12.1";
+        assert_eq!(encoded, encode_dependencies(&parse_dependencies(encoded)));
+    }
+
+    #[test]
+    fn _save_dependencies() {
+        let _env = lock_env();
+        env::set_var(super::super::ENV_DEPENDENCIES, "zlib/1.2=gcc/12.1");
+
+        // blast loads gcc and zlib, zlib is needed by both now
+        let mut context = Context::new();
+        context.dependencies = vec![String::from("gcc/12.1"), String::from("zlib/1.2")];
+        save_dependencies(&mut context, "blast/2.1", true);
+        assert_eq!(
+            "zlib/1.2=gcc/12.1,blast/2.1:gcc/12.1=blast/2.1",
+            env::var(super::super::ENV_DEPENDENCIES).unwrap()
+        );
+        assert_eq!(true, is_dependency("gcc/12.1"));
+
+        // the user loads gcc, it isn't a dependency anymore
+        let mut context = Context::new();
+        save_dependencies(&mut context, "gcc/12.1", true);
+        assert_eq!(false, is_dependency("gcc/12.1"));
+        assert_eq!(true, is_dependency("zlib/1.2"));
+
+        // a dependency that is loaded again by another module keeps its place
+        let mut context = Context::new();
+        save_dependencies(&mut context, "zlib/1.2", false);
+        assert_eq!(true, is_dependency("zlib/1.2"));
+
+        env::remove_var(super::super::ENV_DEPENDENCIES);
+    }
+
+    #[test]
+    fn _swap_family() {
+        let _env = lock_env();
+        env::remove_var(super::super::ENV_SWAP_FAMILY);
+        assert_eq!(true, swap_family());
+        env::set_var(super::super::ENV_SWAP_FAMILY, "yes");
+        assert_eq!(true, swap_family());
+        env::set_var(super::super::ENV_SWAP_FAMILY, "no");
+        assert_eq!(false, swap_family());
+        env::remove_var(super::super::ENV_SWAP_FAMILY);
+    }
+}
@@ -0,0 +1,1152 @@
+/*
+MIT License
+
+Copyright (c) 2017 Frederik Delaere
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+// An evaluator for the part of Tcl that is used in the modulefiles of
+// Environment Modules. It is not a full Tcl interpreter: there is no
+// arithmetic, no lists apart from foreach and no namespaces.
+//
+// The modulefile commands (setenv, prepend-path, module load, ...) are
+// passed on to the functions that the rhai modulefiles use, so a Tcl
+// modulefile behaves the same way as its rhai counterpart.
+
+use super::script::Call;
+use glob::Pattern;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// why the evaluation of a script stopped
+#[derive(Debug)]
+enum Stop {
+    // return, this only stops a proc or the modulefile
+    Return(String),
+    // exit, this stops the modulefile, also from inside a proc
+    Exit,
+    Error(String),
+}
+
+type TclResult = Result<String, Stop>;
+
+// Tcl stops at 1000, we stop earlier because the cache is made on threads with a small stack
+const MAX_FRAMES: usize = 100;
+
+fn error<T>(msg: String) -> Result<T, Stop> {
+    Err(Stop::Error(msg))
+}
+
+#[derive(Default)]
+struct Frame {
+    vars: HashMap<String, String>,
+    // the variables that were declared with global
+    globals: Vec<String>,
+}
+
+struct Interp<'a> {
+    // load, unload, display or whatis, what module-info mode returns
+    mode: &'a str,
+    script: String,
+    frames: Vec<Frame>,
+    procs: HashMap<String, (Vec<String>, String)>,
+    // the changes this modulefile made, so $env() sees them
+    env: HashMap<String, Option<String>>,
+    // module-version: the module and its symbolic names
+    versions: Vec<(String, Vec<String>)>,
+    // puts inside ModulesHelp is collected here
+    output: Option<Vec<String>>,
+    // && and || don't evaluate their right side when the left side decides
+    skip: bool,
+    call: &'a mut Call<'a>,
+}
+
+// Environment Modules only evaluates files that start with this
+pub fn is_modulefile(path: &Path) -> bool {
+    let mut header = [0; 8];
+    match File::open(path) {
+        Ok(mut file) => file.read_exact(&mut header).is_ok() && &header == b"#%Module",
+        Err(_) => false,
+    }
+}
+
+// evaluates a Tcl modulefile for action: load, unload, info or description
+pub fn run(path: &Path, action: &str, call: &mut Call) -> Result<(), String> {
+    let mut contents = String::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            if let Err(e) = file.read_to_string(&mut contents) {
+                return Err(e.to_string());
+            }
+        }
+        Err(e) => return Err(e.to_string()),
+    }
+
+    run_script(&contents, &path.to_string_lossy(), action, call)
+}
+
+fn run_script(contents: &str, script: &str, action: &str, call: &mut Call) -> Result<(), String> {
+    // what module-info mode returns
+    let mode = match action {
+        "load" | "unload" => action,
+        "description" => "whatis",
+        _ => "display",
+    };
+
+    let mut interp = Interp::new(mode, script, call);
+    interp.run(contents)?;
+
+    // module info shows the help text of the module
+    if mode == "display" && interp.procs.contains_key("ModulesHelp") {
+        interp.output = Some(Vec::new());
+        interp.run("ModulesHelp")?;
+        for line in interp.output.take().unwrap_or_default() {
+            (interp.call)("description", &[line]);
+        }
+    }
+
+    Ok(())
+}
+
+// the default version of a module from a .modulerc file or a .version file
+// in Tcl format, groupname is the name of the folder that contains the file
+pub fn get_default_version(path: &Path, groupname: &str) -> Option<String> {
+    let mut contents = String::new();
+    File::open(path).ok()?.read_to_string(&mut contents).ok()?;
+
+    default_version(&contents, &path.to_string_lossy(), groupname)
+}
+
+fn default_version(contents: &str, script: &str, groupname: &str) -> Option<String> {
+    let mut ignore = |_: &str, _: &[String]| String::new();
+    let mut interp = Interp::new("load", script, &mut ignore);
+    // what was declared before an error still counts
+    let _ = interp.run(contents);
+
+    let mut default: Option<String> = interp
+        .get_var("ModulesVersion", None)
+        .ok()
+        .map(|version| format!("{}/{}", groupname, version));
+
+    for (module, names) in &interp.versions {
+        if !names.iter().any(|name| name == "default") {
+            continue;
+        }
+        // /2.5.0 and 2.5.0 are relative to the folder of the .modulerc
+        default = Some(if module.starts_with('/') {
+            format!("{}{}", groupname, module)
+        } else if module.contains('/') {
+            module.to_string()
+        } else {
+            format!("{}/{}", groupname, module)
+        });
+    }
+
+    default
+}
+
+fn is_true(value: &str) -> Result<bool, Stop> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => match value.parse::<f64>() {
+            Ok(number) => Ok(number != 0.0),
+            Err(_) => error(format!("expected boolean value but got \"{}\"", value)),
+        },
+    }
+}
+
+fn from_bool(value: bool) -> String {
+    String::from(if value { "1" } else { "0" })
+}
+
+// the position of the bracket that closes the one at start
+fn find_close(chars: &[char], start: usize, open: char, close: char) -> Result<usize, Stop> {
+    let mut depth = 0;
+    let mut pos = start;
+    while pos < chars.len() {
+        match chars[pos] {
+            '\\' => pos += 1,
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(pos);
+                }
+            }
+            _ => {}
+        }
+        pos += 1;
+    }
+
+    error(format!("missing {}", close))
+}
+
+// splits a Tcl list in its elements
+fn split_list(list: &str) -> Result<Vec<String>, Stop> {
+    let chars: Vec<char> = list.chars().collect();
+    let mut elements: Vec<String> = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        if chars[pos].is_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        if chars[pos] == '{' {
+            let end = find_close(&chars, pos, '{', '}')?;
+            elements.push(chars[pos + 1..end].iter().collect());
+            pos = end + 1;
+        } else if chars[pos] == '"' {
+            let end = match chars[pos + 1..].iter().position(|&c| c == '"') {
+                Some(end) => pos + 1 + end,
+                None => return error(String::from("missing \"")),
+            };
+            elements.push(chars[pos + 1..end].iter().collect());
+            pos = end + 1;
+        } else {
+            let start = pos;
+            while pos < chars.len() && !chars[pos].is_whitespace() {
+                pos += 1;
+            }
+            elements.push(chars[start..pos].iter().collect());
+        }
+    }
+
+    Ok(elements)
+}
+
+fn backslash(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+// the length of the variable name at the start of chars, namespaces are separated by ::
+fn name_length(chars: &[char]) -> usize {
+    let mut length = 0;
+    while length < chars.len() {
+        if chars[length].is_alphanumeric() || chars[length] == '_' {
+            length += 1;
+        } else if chars[length..].starts_with(&[':', ':']) {
+            length += 2;
+        } else {
+            break;
+        }
+    }
+
+    length
+}
+
+// a backslash at the end of a line and the whitespace at the start of the next line
+// are replaced by a single space, returns how many characters that are
+fn continuation_length(chars: &[char]) -> usize {
+    if !chars.starts_with(&['\\', '\n']) {
+        return 0;
+    }
+
+    2 + chars[2..].iter().take_while(|&&c| c == ' ' || c == '\t').count()
+}
+
+impl<'a> Interp<'a> {
+    fn new(mode: &'a str, script: &str, call: &'a mut Call<'a>) -> Interp<'a> {
+        let mut global = Frame::default();
+        global
+            .vars
+            .insert(String::from("ModulesCurrentModulefile"), script.to_string());
+
+        Interp {
+            mode,
+            script: script.to_string(),
+            frames: vec![global],
+            procs: HashMap::new(),
+            env: HashMap::new(),
+            versions: Vec::new(),
+            output: None,
+            skip: false,
+            call,
+        }
+    }
+
+    // evaluates a whole modulefile, return and exit just stop it
+    fn run(&mut self, script: &str) -> Result<(), String> {
+        match self.eval(script) {
+            Ok(_) | Err(Stop::Return(_)) | Err(Stop::Exit) => Ok(()),
+            Err(Stop::Error(msg)) => Err(msg),
+        }
+    }
+
+    fn eval(&mut self, script: &str) -> TclResult {
+        let chars: Vec<char> = script.chars().collect();
+        let mut pos = 0;
+        let mut result = String::new();
+
+        while pos < chars.len() {
+            let c = chars[pos];
+            if c.is_whitespace() || c == ';' {
+                pos += 1;
+            } else if c == '\\' && chars.get(pos + 1) == Some(&'\n') {
+                pos += 2;
+            } else if c == '#' {
+                // a comment runs until the end of the line, unless it ends with a backslash
+                while pos < chars.len() && chars[pos] != '\n' {
+                    if chars[pos] == '\\' {
+                        pos += 1;
+                    }
+                    pos += 1;
+                }
+            } else {
+                let words = self.parse_command(&chars, &mut pos)?;
+                if !words.is_empty() {
+                    result = self.command(&words)?;
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn parse_command(&mut self, chars: &[char], pos: &mut usize) -> Result<Vec<String>, Stop> {
+        let mut words: Vec<String> = Vec::new();
+
+        while *pos < chars.len() {
+            let c = chars[*pos];
+            if c == '\n' || c == ';' {
+                *pos += 1;
+                break;
+            } else if continuation_length(&chars[*pos..]) > 0 {
+                *pos += continuation_length(&chars[*pos..]);
+            } else if c.is_whitespace() {
+                *pos += 1;
+            } else if c == '{' && chars[*pos..].starts_with(&['{', '*', '}']) {
+                *pos += 3;
+                let word = self.parse_word(chars, pos)?;
+                words.extend(split_list(&word)?);
+            } else {
+                words.push(self.parse_word(chars, pos)?);
+            }
+        }
+
+        Ok(words)
+    }
+
+    fn parse_word(&mut self, chars: &[char], pos: &mut usize) -> TclResult {
+        if chars[*pos] == '{' {
+            let end = find_close(chars, *pos, '{', '}')?;
+            let mut word = String::new();
+            let mut i = *pos + 1;
+            while i < end {
+                match continuation_length(&chars[i..end]) {
+                    0 => {
+                        word.push(chars[i]);
+                        i += 1;
+                    }
+                    length => {
+                        word.push(' ');
+                        i += length;
+                    }
+                }
+            }
+            *pos = end + 1;
+            return Ok(word);
+        }
+
+        let quoted = chars[*pos] == '"';
+        if quoted {
+            *pos += 1;
+        }
+
+        let mut word = String::new();
+        while *pos < chars.len() {
+            let c = chars[*pos];
+            if quoted && c == '"' {
+                *pos += 1;
+                return Ok(word);
+            }
+            if !quoted && (c.is_whitespace() || c == ';') {
+                return Ok(word);
+            }
+            self.substitute(chars, pos, &mut word)?;
+        }
+
+        if quoted {
+            return error(String::from("missing \""));
+        }
+
+        Ok(word)
+    }
+
+    // adds the character at pos to word, with backslash, variable and command substitution
+    fn substitute(&mut self, chars: &[char], pos: &mut usize, word: &mut String) -> Result<(), Stop> {
+        match chars[*pos] {
+            '\\' if *pos + 1 < chars.len() => {
+                if chars[*pos + 1] == '\n' {
+                    word.push(' ');
+                    *pos += continuation_length(&chars[*pos..]);
+                } else {
+                    word.push(backslash(chars[*pos + 1]));
+                    *pos += 2;
+                }
+            }
+            '[' => {
+                let end = find_close(chars, *pos, '[', ']')?;
+                let script: String = chars[*pos + 1..end].iter().collect();
+                if !self.skip {
+                    word.push_str(&self.eval(&script)?);
+                }
+                *pos = end + 1;
+            }
+            '$' => {
+                *pos += 1;
+                let value = self.parse_variable(chars, pos)?;
+                if !self.skip {
+                    word.push_str(&value);
+                }
+            }
+            c => {
+                word.push(c);
+                *pos += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn substitute_all(&mut self, text: &str) -> TclResult {
+        let chars: Vec<char> = text.chars().collect();
+        let mut pos = 0;
+        let mut result = String::new();
+        while pos < chars.len() {
+            self.substitute(&chars, &mut pos, &mut result)?;
+        }
+
+        Ok(result)
+    }
+
+    // $name, ${name} and $name(index), pos is just after the $
+    fn parse_variable(&mut self, chars: &[char], pos: &mut usize) -> TclResult {
+        if chars.get(*pos) == Some(&'{') {
+            let end = find_close(chars, *pos, '{', '}')?;
+            let name: String = chars[*pos + 1..end].iter().collect();
+            *pos = end + 1;
+            return self.get_var(&name, None);
+        }
+
+        let start = *pos;
+        *pos += name_length(&chars[start..]);
+        if *pos == start {
+            return Ok(String::from("$"));
+        }
+        let name: String = chars[start..*pos].iter().collect();
+
+        if chars.get(*pos) == Some(&'(') {
+            let end = find_close(chars, *pos, '(', ')')?;
+            let index: String = chars[*pos + 1..end].iter().collect();
+            *pos = end + 1;
+            let index = self.substitute_all(&index)?;
+            return self.get_var(&name, Some(&index));
+        }
+
+        self.get_var(&name, None)
+    }
+
+    // the frame that holds a variable, globals live in the first one
+    fn frame(&mut self, name: &str) -> (&mut Frame, String) {
+        let is_global = name.starts_with("::") || self.frames.last().unwrap().globals.iter().any(|g| g == name);
+        let name = name.trim_start_matches("::").to_string();
+        if is_global {
+            (self.frames.first_mut().unwrap(), name)
+        } else {
+            (self.frames.last_mut().unwrap(), name)
+        }
+    }
+
+    fn get_var(&mut self, name: &str, index: Option<&str>) -> TclResult {
+        if self.skip {
+            return Ok(String::new());
+        }
+        let (frame, name) = self.frame(name);
+        match index {
+            Some(index) if name == "env" => {
+                let value = match self.env.get(index) {
+                    Some(value) => value.clone(),
+                    None => env::var(index).ok(),
+                };
+                match value {
+                    Some(value) => Ok(value),
+                    None => error(format!("can't read \"env({})\": no such variable", index)),
+                }
+            }
+            Some(index) => match frame.vars.get(&format!("{}({})", name, index)) {
+                Some(value) => Ok(value.clone()),
+                None => error(format!("can't read \"{}({})\": no such element in array", name, index)),
+            },
+            None => match frame.vars.get(&name) {
+                Some(value) => Ok(value.clone()),
+                None => error(format!("can't read \"{}\": no such variable", name)),
+            },
+        }
+    }
+
+    // name can be an array element: name(index)
+    fn split_var_name(name: &str) -> (&str, Option<&str>) {
+        if name.ends_with(')') {
+            if let Some(open) = name.find('(') {
+                return (&name[..open], Some(&name[open + 1..name.len() - 1]));
+            }
+        }
+        (name, None)
+    }
+
+    fn set_var(&mut self, name: &str, value: &str) -> TclResult {
+        match Interp::split_var_name(name) {
+            (var, Some(index)) if var.trim_start_matches("::") == "env" => {
+                self.setenv(index, value);
+            }
+            _ => {
+                let (frame, name) = self.frame(name);
+                frame.vars.insert(name, value.to_string());
+            }
+        }
+
+        Ok(value.to_string())
+    }
+
+    fn setenv(&mut self, var: &str, value: &str) {
+        self.env.insert(var.to_string(), Some(value.to_string()));
+        (self.call)("setenv", &[var.to_string(), value.to_string()]);
+    }
+
+    fn command(&mut self, words: &[String]) -> TclResult {
+        let args = &words[1..];
+        match words[0].as_str() {
+            "set" => match args.len() {
+                1 => {
+                    let (var, index) = Interp::split_var_name(&args[0]);
+                    self.get_var(var, index)
+                }
+                2 => self.set_var(&args[0], &args[1]),
+                _ => error(String::from("wrong # args: should be \"set varName ?newValue?\"")),
+            },
+            "unset" => {
+                for name in args.iter().filter(|arg| !arg.starts_with('-')) {
+                    let (frame, name) = self.frame(name);
+                    frame.vars.remove(&name);
+                }
+                Ok(String::new())
+            }
+            "global" => {
+                let frame = self.frames.last_mut().unwrap();
+                frame.globals.extend(args.iter().cloned());
+                Ok(String::new())
+            }
+            "variable" => Ok(String::new()),
+            "if" => self.command_if(args),
+            "expr" => self.expr(&args.join(" ")),
+            "foreach" => self.command_foreach(args),
+            "proc" => {
+                if args.len() != 3 {
+                    return error(String::from("wrong # args: should be \"proc name args body\""));
+                }
+                self.procs
+                    .insert(args[0].to_string(), (split_list(&args[1])?, args[2].to_string()));
+                Ok(String::new())
+            }
+            "return" => Err(Stop::Return(args.last().cloned().unwrap_or_default())),
+            "exit" => Err(Stop::Exit),
+            "catch" => {
+                let result = match args.first() {
+                    Some(script) => self.eval(script),
+                    None => return error(String::from("wrong # args: should be \"catch script\"")),
+                };
+                match result {
+                    Err(Stop::Exit) => Err(Stop::Exit),
+                    Err(Stop::Error(msg)) => {
+                        if let Some(var) = args.get(1) {
+                            self.set_var(var, &msg)?;
+                        }
+                        Ok(String::from("1"))
+                    }
+                    Ok(value) | Err(Stop::Return(value)) => {
+                        if let Some(var) = args.get(1) {
+                            self.set_var(var, &value)?;
+                        }
+                        Ok(String::from("0"))
+                    }
+                }
+            }
+            "puts" => {
+                let msg = args.iter().rfind(|arg| *arg != "-nonewline").cloned().unwrap_or_default();
+                match self.output {
+                    Some(ref mut output) => output.push(msg),
+                    None => {
+                        (self.call)("print", &[msg]);
+                    }
+                }
+                Ok(String::new())
+            }
+            "info" => self.command_info(args),
+            "file" => self.command_file(args),
+            "string" => self.command_string(args),
+            "concat" => Ok(args.iter().map(|arg| arg.trim()).collect::<Vec<&str>>().join(" ")),
+            "module-info" => match (args.first().map(String::as_str), args.get(1)) {
+                (Some("mode"), Some(mode)) => Ok(from_bool(mode == self.mode)),
+                (Some("mode"), None) => Ok(self.mode.to_string()),
+                _ => Ok(String::new()),
+            },
+            "module-whatis" => {
+                (self.call)("description", &[args.join(" ")]);
+                Ok(String::new())
+            }
+            "module-version" => {
+                if let Some((module, names)) = args.split_first() {
+                    self.versions.push((module.to_string(), names.to_vec()));
+                }
+                Ok(String::new())
+            }
+            "module-alias" => Ok(String::new()),
+            "setenv" => {
+                let args: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+                if args.len() != 2 {
+                    return error(String::from("wrong # args: should be \"setenv variable value\""));
+                }
+                self.setenv(args[0], args[1]);
+                Ok(String::new())
+            }
+            "unsetenv" => {
+                if let Some(var) = args.first() {
+                    self.env.insert(var.to_string(), None);
+                    (self.call)("unsetenv", &[var.to_string()]);
+                }
+                Ok(String::new())
+            }
+            "getenv" => match args.first() {
+                Some(var) => Ok(self
+                    .get_var("env", Some(var))
+                    .unwrap_or_else(|_| args.get(1).cloned().unwrap_or_default())),
+                None => error(String::from("wrong # args: should be \"getenv variable\"")),
+            },
+            "prepend-path" => self.command_path("prepend_path", args),
+            "append-path" => self.command_path("append_path", args),
+            "remove-path" => self.command_path("remove_path", args),
+            "module" => self.command_module(args),
+            "conflict" => {
+                for module in args {
+                    (self.call)("conflict", &[module.to_string()]);
+                }
+                Ok(String::new())
+            }
+            "is-loaded" => {
+                let loaded = args
+                    .iter()
+                    .all(|module| (self.call)("is_loaded", &[module.to_string()]) == "1");
+                Ok(from_bool(loaded && !args.is_empty()))
+            }
+            "set-alias" => {
+                if args.len() != 2 {
+                    return error(String::from("wrong # args: should be \"set-alias name value\""));
+                }
+                (self.call)("set_alias", &[args[0].to_string(), args[1].to_string()]);
+                Ok(String::new())
+            }
+            "family" => {
+                if args.len() != 1 {
+                    return error(String::from("wrong # args: should be \"family name\""));
+                }
+                (self.call)("family", &[args[0].to_string()]);
+                Ok(String::new())
+            }
+            name => match self.procs.get(name).cloned() {
+                Some((params, body)) => self.call_proc(&params, &body, args),
+                None => error(format!("invalid command name \"{}\"", name)),
+            },
+        }
+    }
+
+    fn call_proc(&mut self, params: &[String], body: &str, args: &[String]) -> TclResult {
+        // a proc that calls itself would overflow the stack
+        if self.frames.len() >= MAX_FRAMES {
+            return error(String::from("too many nested evaluations (infinite loop?)"));
+        }
+
+        let mut frame = Frame::default();
+        for (i, param) in params.iter().enumerate() {
+            let param = split_list(param)?;
+            let name = param.first().cloned().unwrap_or_default();
+            if name == "args" && i == params.len() - 1 {
+                frame.vars.insert(name, args.get(i..).unwrap_or_default().join(" "));
+            } else if let Some(value) = args.get(i).or_else(|| param.get(1)) {
+                frame.vars.insert(name, value.to_string());
+            } else {
+                return error(format!("wrong # args: no value given for parameter \"{}\"", name));
+            }
+        }
+
+        self.frames.push(frame);
+        let result = self.eval(body);
+        self.frames.pop();
+
+        match result {
+            Err(Stop::Return(value)) => Ok(value),
+            result => result,
+        }
+    }
+
+    fn command_if(&mut self, args: &[String]) -> TclResult {
+        let mut pos = 0;
+        while pos < args.len() {
+            let condition = &args[pos];
+            pos += 1;
+            if args.get(pos).map(String::as_str) == Some("then") {
+                pos += 1;
+            }
+            let body = match args.get(pos) {
+                Some(body) => body,
+                None => return error(String::from("wrong # args: no script following condition")),
+            };
+            pos += 1;
+
+            if is_true(&self.expr(condition)?)? {
+                return self.eval(body);
+            }
+
+            match args.get(pos).map(String::as_str) {
+                Some("elseif") => pos += 1,
+                Some("else") => match args.get(pos + 1) {
+                    Some(body) => return self.eval(body),
+                    None => return error(String::from("wrong # args: no script following else")),
+                },
+                Some(_) => return self.eval(&args[pos]),
+                None => break,
+            }
+        }
+
+        Ok(String::new())
+    }
+
+    fn command_foreach(&mut self, args: &[String]) -> TclResult {
+        if args.len() != 3 {
+            return error(String::from("wrong # args: should be \"foreach varName list body\""));
+        }
+
+        for element in split_list(&args[1])? {
+            self.set_var(&args[0], &element)?;
+            self.eval(&args[2])?;
+        }
+
+        Ok(String::new())
+    }
+
+    fn command_info(&mut self, args: &[String]) -> TclResult {
+        match (args.first().map(String::as_str), args.get(1)) {
+            (Some("exists"), Some(name)) => {
+                let (var, index) = Interp::split_var_name(name);
+                Ok(from_bool(self.get_var(var, index).is_ok()))
+            }
+            (Some("script"), _) => Ok(self.script.clone()),
+            _ => error(format!("unsupported info command: info {}", args.join(" "))),
+        }
+    }
+
+    fn command_file(&mut self, args: &[String]) -> TclResult {
+        let path = args.get(1).map(String::as_str).unwrap_or("");
+        match args.first().map(String::as_str) {
+            Some("join") => Ok(args[1..].iter().fold(String::new(), |joined, part| {
+                if part.starts_with('/') || joined.is_empty() {
+                    part.to_string()
+                } else {
+                    format!("{}/{}", joined.trim_end_matches('/'), part)
+                }
+            })),
+            Some("dirname") => Ok(match Path::new(path).parent() {
+                Some(parent) if parent != Path::new("") => parent.to_string_lossy().into_owned(),
+                _ => String::from("."),
+            }),
+            Some("tail") => Ok(Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()),
+            Some("exists") => Ok(from_bool(Path::new(path).exists())),
+            Some("isdirectory") => Ok(from_bool(Path::new(path).is_dir())),
+            Some("isfile") => Ok(from_bool(Path::new(path).is_file())),
+            _ => error(format!("unsupported file command: file {}", args.join(" "))),
+        }
+    }
+
+    fn command_string(&mut self, args: &[String]) -> TclResult {
+        let first = args.get(1).map(String::as_str).unwrap_or("");
+        let second = args.get(2).map(String::as_str).unwrap_or("");
+        match args.first().map(String::as_str) {
+            Some("match") => Ok(from_bool(
+                Pattern::new(first).map(|pattern| pattern.matches(second)).unwrap_or(false),
+            )),
+            Some("equal") => Ok(from_bool(first == second)),
+            Some("first") => Ok(match second.find(first) {
+                Some(index) => second[..index].chars().count().to_string(),
+                None => String::from("-1"),
+            }),
+            Some("length") => Ok(first.chars().count().to_string()),
+            Some("tolower") => Ok(first.to_lowercase()),
+            Some("toupper") => Ok(first.to_uppercase()),
+            Some("trim") => Ok(first.trim().to_string()),
+            _ => error(format!("unsupported string command: string {}", args.join(" "))),
+        }
+    }
+
+    // prepend-path, append-path and remove-path, the values can be lists
+    // separated by the delimiter, just like PATH itself
+    fn command_path(&mut self, function: &str, args: &[String]) -> TclResult {
+        let mut delimiter = String::from(":");
+        let mut rest: Vec<&String> = Vec::new();
+        let mut pos = 0;
+        while pos < args.len() {
+            let arg = &args[pos];
+            if arg == "-d" || arg == "--delim" {
+                delimiter = args.get(pos + 1).cloned().unwrap_or(delimiter);
+                pos += 1;
+            } else if let Some(value) = arg.strip_prefix("--delim=") {
+                delimiter = value.to_string();
+            } else if !arg.starts_with("--") {
+                rest.push(arg);
+            }
+            pos += 1;
+        }
+
+        let (var, values) = match rest.split_first() {
+            Some((var, values)) if !values.is_empty() => (var.to_string(), values),
+            _ => {
+                return error(format!(
+                    "wrong # args: should be \"{} variable value\"",
+                    function.replace('_', "-")
+                ))
+            }
+        };
+
+        let mut values: Vec<&str> = values
+            .iter()
+            .flat_map(|value| value.split(delimiter.as_str()))
+            .filter(|value| !value.is_empty())
+            .collect();
+
+        // every prepend_path call puts its value in front
+        if function == "prepend_path" {
+            values.reverse();
+        }
+
+        for value in values {
+            (self.call)(function, &[var.to_string(), value.to_string()]);
+        }
+
+        Ok(String::new())
+    }
+
+    fn command_module(&mut self, args: &[String]) -> TclResult {
+        let (command, modules) = match args.split_first() {
+            Some((command, modules)) => (command.as_str(), modules.iter().filter(|arg| !arg.starts_with('-'))),
+            None => return error(String::from("wrong # args: should be \"module command ?args?\"")),
+        };
+
+        let function = match command {
+            "load" | "add" | "try-load" | "try-add" => "load",
+            "unload" | "rm" | "del" => "unload",
+            "use" => "use_modulepath",
+            "unuse" => "unuse_modulepath",
+            "swap" | "switch" => {
+                let modules: Vec<&String> = modules.collect();
+                if let [old, new] = modules.as_slice() {
+                    (self.call)("unload", &[old.to_string()]);
+                    (self.call)("load", &[new.to_string()]);
+                } else if let [new] = modules.as_slice() {
+                    (self.call)("load", &[new.to_string()]);
+                }
+                return Ok(String::new());
+            }
+            _ => return error(format!("unsupported module command: module {}", command)),
+        };
+
+        for module in modules {
+            (self.call)(function, &[module.to_string()]);
+        }
+
+        Ok(String::new())
+    }
+
+    // the expressions of if and expr: comparisons, !, && and ||
+    fn expr(&mut self, expression: &str) -> TclResult {
+        let chars: Vec<char> = expression.chars().collect();
+        let mut pos = 0;
+        let value = self.expr_or(&chars, &mut pos)?;
+        self.skip_whitespace(&chars, &mut pos);
+        if pos < chars.len() {
+            return error(format!("syntax error in expression \"{}\"", expression));
+        }
+
+        Ok(value)
+    }
+
+    fn skip_whitespace(&self, chars: &[char], pos: &mut usize) {
+        while *pos < chars.len() && chars[*pos].is_whitespace() {
+            *pos += 1;
+        }
+    }
+
+    // parses the next operand, without evaluating it when skip is true
+    fn expr_skip(
+        &mut self,
+        skip: bool,
+        chars: &[char],
+        pos: &mut usize,
+        parse: fn(&mut Self, &[char], &mut usize) -> TclResult,
+    ) -> TclResult {
+        let previous = self.skip;
+        self.skip = previous || skip;
+        let value = parse(self, chars, pos);
+        self.skip = previous;
+
+        if skip {
+            return value.map(|_| from_bool(false));
+        }
+        value
+    }
+
+    fn expr_or(&mut self, chars: &[char], pos: &mut usize) -> TclResult {
+        let mut value = self.expr_and(chars, pos)?;
+        loop {
+            self.skip_whitespace(chars, pos);
+            if !chars[*pos..].starts_with(&['|', '|']) {
+                return Ok(value);
+            }
+            *pos += 2;
+            let left = self.skip || is_true(&value)?;
+            let right = self.expr_skip(left, chars, pos, Interp::expr_and)?;
+            value = from_bool(left || is_true(&right)?);
+        }
+    }
+
+    fn expr_and(&mut self, chars: &[char], pos: &mut usize) -> TclResult {
+        let mut value = self.expr_compare(chars, pos)?;
+        loop {
+            self.skip_whitespace(chars, pos);
+            if !chars[*pos..].starts_with(&['&', '&']) {
+                return Ok(value);
+            }
+            *pos += 2;
+            let left = !self.skip && is_true(&value)?;
+            let right = self.expr_skip(!left, chars, pos, Interp::expr_compare)?;
+            value = from_bool(left && is_true(&right)?);
+        }
+    }
+
+    fn expr_compare(&mut self, chars: &[char], pos: &mut usize) -> TclResult {
+        let left = self.expr_unary(chars, pos)?;
+        self.skip_whitespace(chars, pos);
+
+        let rest: String = chars[*pos..].iter().take(3).collect();
+        let operator = ["==", "!=", "<=", ">=", "<", ">", "eq ", "ne "]
+            .iter()
+            .find(|operator| rest.starts_with(*operator));
+        let operator = match operator {
+            Some(operator) => operator.trim(),
+            None => return Ok(left),
+        };
+        *pos += operator.len();
+        let right = self.expr_unary(chars, pos)?;
+
+        let ordering = match (left.parse::<f64>(), right.parse::<f64>()) {
+            (Ok(left), Ok(right)) if operator != "eq" && operator != "ne" => left.partial_cmp(&right),
+            _ => Some(left.cmp(&right)),
+        };
+        let ordering = match ordering {
+            Some(ordering) => ordering,
+            None => return Ok(from_bool(false)),
+        };
+
+        Ok(from_bool(match operator {
+            "==" | "eq" => ordering.is_eq(),
+            "!=" | "ne" => ordering.is_ne(),
+            "<=" => ordering.is_le(),
+            ">=" => ordering.is_ge(),
+            "<" => ordering.is_lt(),
+            _ => ordering.is_gt(),
+        }))
+    }
+
+    fn expr_unary(&mut self, chars: &[char], pos: &mut usize) -> TclResult {
+        self.skip_whitespace(chars, pos);
+        if chars.get(*pos) == Some(&'!') {
+            *pos += 1;
+            let value = self.expr_unary(chars, pos)?;
+            return Ok(from_bool(!self.skip && !is_true(&value)?));
+        }
+
+        self.expr_operand(chars, pos)
+    }
+
+    fn expr_operand(&mut self, chars: &[char], pos: &mut usize) -> TclResult {
+        match chars.get(*pos) {
+            Some('(') => {
+                *pos += 1;
+                let value = self.expr_or(chars, pos)?;
+                self.skip_whitespace(chars, pos);
+                if chars.get(*pos) != Some(&')') {
+                    return error(String::from("missing )"));
+                }
+                *pos += 1;
+                Ok(value)
+            }
+            Some('"') | Some('{') => self.parse_word(chars, pos),
+            Some('$') => {
+                *pos += 1;
+                self.parse_variable(chars, pos)
+            }
+            Some('[') => {
+                let mut value = String::new();
+                self.substitute(chars, pos, &mut value)?;
+                Ok(value)
+            }
+            Some(_) => {
+                let start = *pos;
+                while *pos < chars.len() && (chars[*pos].is_alphanumeric() || ".-_+".contains(chars[*pos])) {
+                    *pos += 1;
+                }
+                if *pos == start {
+                    return error(format!("syntax error in expression near \"{}\"", chars[start]));
+                }
+                Ok(chars[start..*pos].iter().collect())
+            }
+            None => error(String::from("missing operand in expression")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_version, run_script};
+
+    // the functions a modulefile called, with their arguments
+    fn evaluate(contents: &str, action: &str) -> (Vec<(String, Vec<String>)>, Result<(), String>) {
+        let mut calls: Vec<(String, Vec<String>)> = Vec::new();
+        let mut call = |name: &str, args: &[String]| {
+            calls.push((name.to_string(), args.to_vec()));
+            String::new()
+        };
+        let result = run_script(contents, "/modules/test/1.0", action, &mut call);
+
+        (calls, result)
+    }
+
+    fn call(name: &str, args: &[&str]) -> (String, Vec<String>) {
+        (name.to_string(), args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn setenv_and_paths() {
+        let source =
+            "#%Module\nset root /opt/test\nsetenv TEST_ROOT $root\nprepend-path -d , LIST \"a,b\"\nappend-path PATH $root/bin\n";
+        let (calls, result) = evaluate(source, "load");
+        assert_eq!(Ok(()), result);
+        assert_eq!(
+            calls,
+            vec![
+                call("setenv", &["TEST_ROOT", "/opt/test"]),
+                call("prepend_path", &["LIST", "b"]),
+                call("prepend_path", &["LIST", "a"]),
+                call("append_path", &["PATH", "/opt/test/bin"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn if_elseif_else() {
+        let source = "set x 2\nif {$x == 1} {\n    setenv A one\n} elseif {$x == 2 && [info exists x]} {\n    setenv A two\n} else {\n    setenv A other\n}\n";
+        let (calls, _) = evaluate(source, "load");
+        assert_eq!(calls, vec![call("setenv", &["A", "two"])]);
+
+        let source = "if {0 || 0} { setenv A yes } else { setenv A no }";
+        let (calls, _) = evaluate(source, "load");
+        assert_eq!(calls, vec![call("setenv", &["A", "no"])]);
+    }
+
+    #[test]
+    fn module_info_mode() {
+        let source =
+            "if {[module-info mode load]} { setenv A load }\nif {[module-info mode] eq \"unload\"} { setenv A unload }\n";
+        let (calls, _) = evaluate(source, "load");
+        assert_eq!(calls, vec![call("setenv", &["A", "load"])]);
+
+        let (calls, _) = evaluate(source, "unload");
+        assert_eq!(calls, vec![call("setenv", &["A", "unload"])]);
+
+        let (calls, _) = evaluate(source, "info");
+        assert_eq!(calls, Vec::new());
+    }
+
+    #[test]
+    fn modules_help() {
+        let source = "proc ModulesHelp { } {\n    puts stderr \"Test help\"\n}\nmodule-whatis \"A test module\"\n";
+        let (calls, _) = evaluate(source, "info");
+        assert_eq!(
+            calls,
+            vec![call("description", &["A test module"]), call("description", &["Test help"])]
+        );
+
+        // the help text is only shown by module info
+        let (calls, _) = evaluate(source, "load");
+        assert_eq!(calls, vec![call("description", &["A test module"])]);
+    }
+
+    #[test]
+    fn recursive_proc() {
+        let (calls, result) = evaluate("proc loop { } { loop }\nloop\nsetenv A 1\n", "load");
+        assert_eq!(Err(String::from("too many nested evaluations (infinite loop?)")), result);
+        assert_eq!(calls, Vec::new());
+    }
+
+    #[test]
+    fn default_versions() {
+        let source = "#%Module\nset ModulesVersion \"2.5.0\"\n";
+        assert_eq!(
+            Some(String::from("blast/2.5.0")),
+            default_version(source, ".version", "blast")
+        );
+
+        let source = "#%Module\nmodule-version /2.6.0 default\n";
+        assert_eq!(
+            Some(String::from("blast/2.6.0")),
+            default_version(source, ".modulerc", "blast")
+        );
+
+        let source = "#%Module\nmodule-version blast/2.7.0 default latest\n";
+        assert_eq!(
+            Some(String::from("blast/2.7.0")),
+            default_version(source, ".modulerc", "blast")
+        );
+
+        let source = "#%Module\nmodule-version 2.4.0 stable\n";
+        assert_eq!(None, default_version(source, ".modulerc", "blast"));
+    }
+}
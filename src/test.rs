@@ -0,0 +1,259 @@
+/*
+MIT License
+
+Copyright (c) 2017 Frederik Delaere
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+use super::{
+    crash, echo, failed, find_module, get_module_list, script, usage, Rsmodule, CRASH_MODULE_NOT_FOUND, ENV_DELTA,
+    ENV_DEPENDENCIES, ENV_LOADEDMODULES,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// the variables of the real environment a modulefile test can see,
+// the others are left out so the result is the same everywhere
+static KEPT_VARIABLES: &[&str] = &["HOME", "USER", "MODULEPATH", "XDG_CACHE_HOME"];
+static TEST_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+type Environment = BTreeMap<String, String>;
+
+// the environment without the variables rsmodules uses for its own bookkeeping
+fn get_environment() -> Environment {
+    env::vars()
+        .filter(|(var, _)| var != ENV_LOADEDMODULES && var != ENV_DELTA && var != ENV_DEPENDENCIES)
+        .collect()
+}
+
+// replaces the whole environment of this process
+fn set_environment(environment: &[(String, String)]) {
+    for (var, _) in env::vars() {
+        env::remove_var(var);
+    }
+    for (var, val) in environment {
+        env::set_var(var, val);
+    }
+}
+
+// what a modulefile does when it is loaded and what is left when
+// it is unloaded again, in a fresh environment
+fn run_test(name: &str, modulefile: &PathBuf) -> (Vec<String>, Vec<String>) {
+    let mut report: Vec<String> = Vec::new();
+    let mut problems: Vec<String> = Vec::new();
+
+    let before = get_environment();
+
+    let mut context = script::run(modulefile, "test");
+    let aliases = script::get_aliases(&context);
+    let sources = script::get_sources(&context);
+    let loads = script::get_loads(&context);
+    let unload_commands = script::get_unload_commands(&context);
+    let commands = script::get_output(&mut context, name, "load", "noshell", true);
+
+    let loaded = env::var(ENV_LOADEDMODULES).unwrap_or_default();
+    if !loaded.split(':').any(|module| module == name) {
+        problems.push(format!("{} was not loaded", name));
+        return (report, problems);
+    }
+
+    let after = get_environment();
+    for (var, val) in &after {
+        if before.get(var) != Some(val) {
+            report.push(format!("set {}={}", var, val));
+        }
+    }
+    for var in before.keys() {
+        if !after.contains_key(var) {
+            report.push(format!("unset {}", var));
+        }
+    }
+    for (alias, val) in aliases {
+        report.push(format!("alias {}={}", alias, val));
+    }
+    for command in commands {
+        report.push(format!("system {}", command));
+    }
+    for source in sources {
+        report.push(format!("source {}", source));
+    }
+    for module in loads {
+        report.push(format!("load {}", module));
+    }
+    for command in unload_commands {
+        report.push(format!("system_unload {}", command));
+    }
+
+    // unload the way module unload does it, by undoing the recorded changes
+    let mut context = match script::run_delta(name, None) {
+        Some(context) => context,
+        None => script::run(modulefile, "unload"),
+    };
+    script::get_output(&mut context, name, "unload", "noshell", true);
+
+    let unloaded = get_environment();
+    let unset = String::from("unset");
+    let vars: BTreeSet<&String> = before.keys().chain(unloaded.keys()).collect();
+    for var in vars {
+        let was = before.get(var).unwrap_or(&unset);
+        let is = unloaded.get(var).unwrap_or(&unset);
+        if was != is {
+            problems.push(format!("unload didn't restore {} ({} instead of {})", var, is, was));
+        }
+    }
+
+    (report, problems)
+}
+
+// the lines of an expected result file, without the empty lines and the comments
+fn read_expected(path: &str) -> Result<Vec<String>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("cannot read {} ({})", path, e))?;
+
+    Ok(contents
+        .lines()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
+
+pub fn run(rsmod: &Rsmodule) {
+    let args = shell_words::split(rsmod.arg).unwrap_or_default();
+    if args.is_empty() || args.len() > 2 {
+        usage(true, true);
+        return;
+    }
+
+    // the module that module load would pick
+    let mut modules = get_module_list(rsmod.shell);
+    modules.reverse();
+    let (name, modulefile) = match find_module(&args[0], rsmod.search_path, &modules) {
+        Ok(module) => module,
+        Err(msg) => {
+            crash(CRASH_MODULE_NOT_FOUND, &msg);
+            return;
+        }
+    };
+
+    // the modulefile changes the environment of this process, not the one of the shell
+    let real: Vec<(String, String)> = env::vars().collect();
+    let mut synthetic: Vec<(String, String)> = real
+        .iter()
+        .filter(|(var, _)| KEPT_VARIABLES.contains(&var.as_str()))
+        .cloned()
+        .collect();
+    synthetic.push((String::from("PATH"), String::from(TEST_PATH)));
+
+    set_environment(&synthetic);
+    let (report, mut problems) = run_test(&name, &modulefile);
+    set_environment(&real);
+
+    if let Some(expected) = args.get(1) {
+        match read_expected(expected) {
+            Ok(expected_lines) => {
+                for line in &expected_lines {
+                    if !report.contains(line) {
+                        problems.push(format!("expected, but missing: {}", line));
+                    }
+                }
+                for line in &report {
+                    if !expected_lines.contains(line) {
+                        problems.push(format!("not expected: {}", line));
+                    }
+                }
+            }
+            Err(e) => problems.push(e),
+        }
+    }
+
+    // the lines that aren't a result are comments, so this output
+    // can be saved as the expected result
+    echo(&format!("# {} ({})", name, modulefile.display()), rsmod.shell);
+    for line in &report {
+        echo(line, rsmod.shell);
+    }
+    for problem in &problems {
+        echo(&format!("# {}", problem), rsmod.shell);
+    }
+
+    if problems.is_empty() {
+        echo("# ok", rsmod.shell);
+    } else {
+        echo(&format!("# failed, {} problems", problems.len()), rsmod.shell);
+        failed(rsmod.shell);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lock_env;
+    use super::run_test;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn _run_test() {
+        let _env = lock_env();
+        let dir = env::temp_dir().join(format!("rsmodules-test-fixture-{}", process::id()));
+        fs::create_dir_all(dir.join("fixture")).unwrap();
+        let modulefile = dir.join("fixture/1.0");
+        fs::write(
+            &modulefile,
+            "setenv(\"FIXTURE_HOME\", \"/opt/fixture\");\n\
+             prepend_path(\"FIXTURE_PATH\", \"/opt/fixture/bin\");\n\
+             remove_path(\"FIXTURE_LIBS\", \"/old/lib\");\n\
+             set_alias(\"fx\", \"fixture --fast\");\n\
+             system_unload(\"fixture --stop\");\n",
+        )
+        .unwrap();
+
+        env::remove_var("FIXTURE_HOME");
+        env::set_var("FIXTURE_PATH", "/usr/bin");
+        env::set_var("FIXTURE_LIBS", "/usr/lib:/old/lib");
+        let (report, problems) = run_test("fixture/1.0", &modulefile);
+        assert_eq!(
+            vec![
+                "set FIXTURE_HOME=/opt/fixture",
+                "set FIXTURE_LIBS=/usr/lib",
+                "set FIXTURE_PATH=/opt/fixture/bin:/usr/bin",
+                "alias fx=fixture --fast",
+                "system_unload fixture --stop",
+            ],
+            report
+        );
+        assert_eq!(true, problems.is_empty());
+        assert_eq!(Ok(String::from("/usr/bin")), env::var("FIXTURE_PATH"));
+
+        // a folder that is twice in the path is put back only once
+        env::set_var("FIXTURE_LIBS", "/old/lib:/usr/lib:/old/lib");
+        let (_, problems) = run_test("fixture/1.0", &modulefile);
+        assert_eq!(
+            vec!["unload didn't restore FIXTURE_LIBS (/old/lib:/usr/lib instead of /old/lib:/usr/lib:/old/lib)"],
+            problems
+        );
+
+        for var in &["FIXTURE_HOME", "FIXTURE_PATH", "FIXTURE_LIBS"] {
+            env::remove_var(var);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+/*
+MIT License
+
+Copyright (c) 2017 Frederik Delaere
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+use super::{cache, is_module_loaded};
+use regex::Regex;
+use std::cmp::Ordering;
+
+// a module name can have a version constraint after an @
+//   python@>=3.8      python@>=3.8,<4    (all the constraints must match)
+//   gcc@12.*          blast@~2.5         blast@2.5 (the same as ~2.5)
+// the versions are compared with natural ordering, so 3.10 is newer than 3.8
+
+pub fn has_constraint(module: &str) -> bool {
+    module.contains('@')
+}
+
+// python@>=3.8 -> (python, >=3.8)
+pub fn split(module: &str) -> (&str, &str) {
+    let mut parts = module.splitn(2, '@');
+    let name = parts.next().unwrap_or("");
+    let constraint = parts.next().unwrap_or("");
+
+    (name, constraint)
+}
+
+// the name without the constraint, python@>=3.8 -> python
+pub fn name(module: &str) -> &str {
+    split(module).0
+}
+
+fn compare(version: &str, other: &str) -> Ordering {
+    natord::compare(version, other)
+}
+
+// 2.5 matches 2.5, 2.5.1 and 2.5/x86_64 but not 2.50
+fn matches_prefix(version: &str, prefix: &str) -> bool {
+    version == prefix || version.starts_with(&format!("{}.", prefix)) || version.starts_with(&format!("{}/", prefix))
+}
+
+fn matches_wildcard(version: &str, pattern: &str) -> bool {
+    let parts: Vec<String> = pattern.split('*').map(regex::escape).collect();
+    match Regex::new(&format!("^{}$", parts.join(".*"))) {
+        Ok(re) => re.is_match(version),
+        Err(_) => false,
+    }
+}
+
+fn matches_one(version: &str, constraint: &str) -> bool {
+    let constraint = constraint.trim();
+
+    // the two character operators are checked first
+    if let Some(other) = constraint.strip_prefix(">=") {
+        compare(version, other.trim()) != Ordering::Less
+    } else if let Some(other) = constraint.strip_prefix("<=") {
+        compare(version, other.trim()) != Ordering::Greater
+    } else if let Some(other) = constraint.strip_prefix("==") {
+        version == other.trim()
+    } else if let Some(other) = constraint.strip_prefix("!=") {
+        version != other.trim()
+    } else if let Some(other) = constraint.strip_prefix('>') {
+        compare(version, other.trim()) == Ordering::Greater
+    } else if let Some(other) = constraint.strip_prefix('<') {
+        compare(version, other.trim()) == Ordering::Less
+    } else if let Some(other) = constraint.strip_prefix('=') {
+        version == other.trim()
+    } else if let Some(other) = constraint.strip_prefix('~') {
+        matches_prefix(version, other.trim())
+    } else if constraint.contains('*') {
+        matches_wildcard(version, constraint)
+    } else {
+        matches_prefix(version, constraint)
+    }
+}
+
+pub fn matches(version: &str, constraint: &str) -> bool {
+    constraint
+        .split(',')
+        .filter(|constraint| !constraint.trim().is_empty())
+        .all(|constraint| matches_one(version, constraint))
+}
+
+// returns the module that satisfies the constraint, this is the loaded version or
+// the default version when they match, otherwise the newest version that matches
+// modules is the list from the .modulecache files or the loaded modules
+pub fn resolve(module: &str, modules: &[(String, bool, String)]) -> Result<String, String> {
+    select(module, modules, |candidate| is_module_loaded(candidate, true))
+}
+
+fn select<F>(module: &str, modules: &[(String, bool, String)], is_loaded: F) -> Result<String, String>
+where
+    F: Fn(&str) -> bool,
+{
+    let (name, constraint) = split(module);
+    let prefix = format!("{}/", name);
+
+    let mut candidates: Vec<(&str, bool)> = modules
+        .iter()
+        .filter(|module| module.0.starts_with(&prefix))
+        .filter(|module| cache::get_deprecated_state(&module.2) != "after")
+        .map(|module| (module.0.as_str(), module.1))
+        .collect();
+
+    if candidates.is_empty() {
+        return Err(format!("Module {} not found.", name));
+    }
+
+    candidates.sort_by(|a, b| compare(a.0, b.0));
+    candidates.dedup_by(|a, b| a.0 == b.0);
+
+    let satisfying: Vec<&(&str, bool)> = candidates
+        .iter()
+        .filter(|candidate| matches(&candidate.0[prefix.len()..], constraint))
+        .collect();
+
+    if let Some(loaded) = satisfying.iter().find(|candidate| is_loaded(candidate.0)) {
+        return Ok(loaded.0.to_string());
+    }
+
+    if let Some(default) = satisfying.iter().find(|candidate| candidate.1) {
+        return Ok(default.0.to_string());
+    }
+
+    match satisfying.last() {
+        Some(newest) => Ok(newest.0.to_string()),
+        None => {
+            let available: Vec<&str> = candidates.iter().map(|candidate| candidate.0).collect();
+            Err(format!(
+                "No version of {} satisfies {}, the available versions are: {}",
+                name,
+                constraint,
+                available.join(", ")
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches, select, split};
+
+    fn modules() -> Vec<(String, bool, String)> {
+        vec![
+            ("python/2.7.2".to_string(), false, "0".to_string()),
+            ("python/3.8.1".to_string(), true, "0".to_string()),
+            ("python/3.10.0".to_string(), false, "0".to_string()),
+            ("python/3.11.0".to_string(), false, "2000-01-01".to_string()),
+        ]
+    }
+
+    #[test]
+    fn _split() {
+        assert_eq!(("python", ">=3.8,<4"), split("python@>=3.8,<4"));
+        assert_eq!(("python", ""), split("python"));
+    }
+
+    #[test]
+    fn _operators() {
+        // natural order, 3.10 is newer than 3.8
+        assert_eq!(true, matches("3.10", ">=3.8"));
+        assert_eq!(false, matches("3.7", ">=3.8"));
+        assert_eq!(true, matches("3.8", "<=3.8"));
+        assert_eq!(false, matches("3.10", "<=3.8"));
+        assert_eq!(true, matches("3.10", ">3.8"));
+        assert_eq!(false, matches("3.8", ">3.8"));
+        assert_eq!(true, matches("3.7", "<3.8"));
+        assert_eq!(true, matches("3.8", "==3.8"));
+        assert_eq!(false, matches("3.8.1", "==3.8"));
+        assert_eq!(true, matches("3.8", "=3.8"));
+        assert_eq!(false, matches("3.8", "!=3.8"));
+        assert_eq!(true, matches("3.8.1", "!=3.8"));
+    }
+
+    #[test]
+    fn _prefix() {
+        assert_eq!(true, matches("2.5", "~2.5"));
+        assert_eq!(true, matches("2.5.1", "~2.5"));
+        assert_eq!(true, matches("2.5/x86_64", "~2.5"));
+        assert_eq!(false, matches("2.50", "~2.5"));
+        // a bare version is a prefix too
+        assert_eq!(true, matches("2.5.1", "2.5"));
+        assert_eq!(false, matches("2.50", "2.5"));
+    }
+
+    #[test]
+    fn _wildcard() {
+        assert_eq!(true, matches("12.2.0", "12.*"));
+        assert_eq!(false, matches("11.2", "12.*"));
+        assert_eq!(false, matches("112.2", "12.*"));
+    }
+
+    #[test]
+    fn _combined() {
+        assert_eq!(true, matches("3.9", ">=3.8,<4"));
+        assert_eq!(true, matches("3.10", ">=3.8, <4"));
+        assert_eq!(false, matches("4.0", ">=3.8,<4"));
+        assert_eq!(false, matches("3.7", ">=3.8,<4"));
+        assert_eq!(true, matches("3.9", ""));
+    }
+
+    #[test]
+    fn _select() {
+        let modules = modules();
+        let not_loaded = |_: &str| false;
+
+        // the default version is preferred over the newest one
+        assert_eq!(Ok("python/3.8.1".to_string()), select("python@>=3", &modules, not_loaded));
+        // a deprecated version that was removed is skipped
+        assert_eq!(Ok("python/3.10.0".to_string()), select("python@>3.8.1", &modules, not_loaded));
+        // the loaded version is preferred over the default one
+        assert_eq!(
+            Ok("python/3.10.0".to_string()),
+            select("python@>=3", &modules, |candidate| candidate == "python/3.10.0")
+        );
+
+        assert_eq!(
+            Err(
+                "No version of python satisfies >=4, the available versions are: python/2.7.2, python/3.8.1, python/3.10.0"
+                    .to_string()
+            ),
+            select("python@>=4", &modules, not_loaded)
+        );
+        assert_eq!(
+            Err("Module perl not found.".to_string()),
+            select("perl@>=5", &modules, not_loaded)
+        );
+    }
+}
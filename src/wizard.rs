@@ -77,6 +77,7 @@ fn update_setup_rsmodules_c_sh(recursive: bool, path: &str) {
     let executable_path = executable_path.parent();
     let current_path_sh: &str = &format!("{}/setup_rsmodules.sh", executable_path.unwrap().display());
     let current_path_csh: &str = &format!("{}/setup_rsmodules.csh", executable_path.unwrap().display());
+    let current_path_fish: &str = &format!("{}/setup_rsmodules.fish", executable_path.unwrap().display());
 
     let bash_result: bool;
     let csh_result: bool;
@@ -95,12 +96,12 @@ fn update_setup_rsmodules_c_sh(recursive: bool, path: &str) {
         // add path to the file
         // use detect_line but with a regex: export MODULEPATH="(randomblah)"
         // and replace with export MODULEPATH="(randomblah):OURNEWPATH"
-        bash_result = add_path(path, current_path_sh, "MODULEPATH", true);
+        bash_result = add_path(path, current_path_sh, "MODULEPATH", PathAction::Append);
         bash_result2 = add_path(
             &format!("{}", executable_path.unwrap().display()),
             current_path_sh,
             "RSMODULES_INSTALL_DIR",
-            false,
+            PathAction::Replace,
         );
     }
 
@@ -115,15 +116,29 @@ fn update_setup_rsmodules_c_sh(recursive: bool, path: &str) {
         // add path to the file
         // use detect_line but with a regex: setenv MODULEPATH "(randomblah)"
         // and replace with setenv MODULEPATH "(randomblah):OURNEWPATH"
-        csh_result = add_path(path, current_path_csh, "MODULEPATH", true);
+        csh_result = add_path(path, current_path_csh, "MODULEPATH", PathAction::Append);
         csh_result2 = add_path(
             &format!("{}", executable_path.unwrap().display()),
             current_path_csh,
             "RSMODULES_INSTALL_DIR",
-            false,
+            PathAction::Replace,
         );
     }
 
+    // the fish setup file is optional, older installs don't have it
+    let mut fish_result = false;
+    if Path::new(current_path_fish).is_file() {
+        // set -gx MODULEPATH "(randomblah)"
+        let fish_result1 = add_path(path, current_path_fish, "MODULEPATH", PathAction::Append);
+        let fish_result2 = add_path(
+            &format!("{}", executable_path.unwrap().display()),
+            current_path_fish,
+            "RSMODULES_INSTALL_DIR",
+            PathAction::Replace,
+        );
+        fish_result = fish_result1 || fish_result2;
+    }
+
     if (bash_result || bash_result2) && (csh_result || csh_result2) {
         println!();
         println!("    Successfully modified:");
@@ -137,6 +152,10 @@ fn update_setup_rsmodules_c_sh(recursive: bool, path: &str) {
         println!("    - {}", current_path_csh);
     }
 
+    if fish_result {
+        println!("    - {}", current_path_fish);
+    }
+
     if get_current_uid() == 0 {
         let path_sh: &str = "/etc/profile.d/rsmodules.sh";
         let path_csh: &str = "/etc/profile.d/rsmodules.csh";
@@ -177,6 +196,9 @@ fn update_setup_rsmodules_c_sh(recursive: bool, path: &str) {
                     println!("    To have it active in the current terminal, type this:");
                     println!("    bash or zsh : source {}", current_path_sh);
                     println!("    csh or tcsh : source {}", current_path_csh);
+                    if fish_result {
+                        println!("    fish        : source {}", current_path_fish);
+                    }
                 }
             }
         }
@@ -322,7 +344,7 @@ pub fn detect_line(line: &str, file: &str) -> bool {
 // go over the file line by line, do we have
 // a export MODULEPATH="" match, replace it
 // same for setenv MODULEPATH ""
-fn add_path(newpath: &str, filename: &str, variable: &str, append: bool) -> bool {
+fn add_path(newpath: &str, filename: &str, variable: &str, action: PathAction) -> bool {
     let mut newbuffer: Vec<String> = Vec::new();
 
     if Path::new(filename).is_file() {
@@ -336,15 +358,16 @@ fn add_path(newpath: &str, filename: &str, variable: &str, append: bool) -> bool
         let file = BufReader::new(file);
         for (_, entry) in file.lines().enumerate() {
             let buffer = entry.unwrap();
-            newbuffer.push(set_path(&buffer, newpath, variable, append));
+            newbuffer.push(set_path(&buffer, newpath, variable, action));
         }
     }
 
     if !newbuffer.is_empty() {
-        let mut file: File = match OpenOptions::new().write(true).open(filename) {
+        // truncate, removing a path makes the file shorter
+        let mut file: File = match OpenOptions::new().write(true).truncate(true).open(filename) {
             Ok(fileresult) => fileresult,
             Err(e) => {
-                println!("    - Cannot write to file {} ({})", filename, e);
+                eprintln!("    - Cannot write to file {} ({})", filename, e);
                 return false;
             }
         };
@@ -362,11 +385,20 @@ fn add_path(newpath: &str, filename: &str, variable: &str, append: bool) -> bool
     true
 }
 
-// match against export MODULEPATH="" and setenv MODULEPATH ""
-// and add the new path to it
-fn set_path(input: &str, path: &str, variable: &str, append: bool) -> String {
+// what set_path does with the path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathAction {
+    Append,
+    Prepend,
+    Replace,
+    Remove,
+}
+
+// match against export MODULEPATH="", setenv MODULEPATH "" and set -gx MODULEPATH ""
+// and add the new path to it, or remove it
+fn set_path(input: &str, path: &str, variable: &str, action: PathAction) -> String {
     let re = Regex::new(&format!(
-        r#"^\s*(?P<export>export|setenv)\s+{}(?P<equals>[= ]?)"(?P<value>.*)""#,
+        r#"^\s*(?P<export>export|setenv|set -gx)\s+{}(?P<equals>[= ]?)"(?P<value>.*)""#,
         variable
     ))
     .unwrap();
@@ -374,31 +406,53 @@ fn set_path(input: &str, path: &str, variable: &str, append: bool) -> String {
     let mut output: String = input.to_string();
     for cap in re.captures_iter(input) {
         let value = &cap["value"];
-        let value: Vec<&str> = value.split(':').collect();
+        let mut value: Vec<&str> = value.split(':').filter(|existing_path| existing_path != &"").collect();
 
-        for existing_path in &value {
-            if existing_path == &path {
+        if action == PathAction::Remove {
+            if !value.contains(&path) {
                 return String::from(input);
             }
-        }
+            value.retain(|existing_path| existing_path != &path);
+        } else {
+            for existing_path in &value {
+                if existing_path == &path {
+                    return String::from(input);
+                }
+            }
 
-        if append {
-            if !(value.len() == 1 && value[0] == "" || value.is_empty()) {
-                output = format!(
-                    r#"{} {}{}"{}:{}""#,
-                    &cap["export"], variable, &cap["equals"], &cap["value"], path
-                );
-            } else {
-                output = format!(r#"{} {}{}"{}""#, &cap["export"], variable, &cap["equals"], path);
+            match action {
+                PathAction::Append => value.push(path),
+                PathAction::Prepend => value.insert(0, path),
+                _ => value = vec![path],
             }
-        } else {
-            output = format!(r#"{} {}{}"{}""#, &cap["export"], variable, &cap["equals"], path);
         }
+
+        output = format!(r#"{} {}{}"{}""#, &cap["export"], variable, &cap["equals"], value.join(":"));
     }
 
     output
 }
 
+// adds or removes a path in $MODULEPATH of the setup_rsmodules files
+// that are in the same folder as the rsmodules executable
+pub fn update_modulepath(path: &str, action: PathAction) -> bool {
+    let executable_path = env::current_exe().unwrap();
+    let executable_path = executable_path.parent().unwrap();
+    let mut result = false;
+
+    for extension in &["sh", "csh", "fish"] {
+        let filename = format!("{}/setup_rsmodules.{}", executable_path.display(), extension);
+        if Path::new(&filename).is_file() {
+            if !add_path(path, &filename, "MODULEPATH", action) {
+                return false;
+            }
+            result = true;
+        }
+    }
+
+    result
+}
+
 // if no modulepath variable found, or it is empty
 // start a wizard to add one to the path
 // if uid = 0 suggest /usr/local/modulefiles as